@@ -0,0 +1,192 @@
+use capn::git::{Commit, Git, LiveGit};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use git2::{MergeOptions, Oid, Repository, Signature};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use uuid::Uuid;
+
+const MERGE_COMMITS: usize = 50;
+
+struct BenchRepo {
+    path: PathBuf,
+    merge_commits: Vec<Commit>,
+}
+
+impl Drop for BenchRepo {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).unwrap();
+    }
+}
+
+// Builds a mainline where each commit is a trivial merge of a branch
+// with a mainline commit. With `same_file`, the branch and the mainline
+// change different lines of the same file, so the merge has to merge
+// the file's contents. Otherwise they change different files.
+fn bench_repo(same_file: bool) -> BenchRepo {
+    let path = std::env::temp_dir().join(format!("capn_bench_{}.git", Uuid::new_v4()));
+    let repo = Repository::init_bare(&path).unwrap();
+    let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+
+    let commit_file = |parent: Option<Oid>, name: &str, contents: &str| -> Oid {
+        let parent = parent.map(|id| repo.find_commit(id).unwrap());
+        let mut tree_builder = repo
+            .treebuilder(parent.as_ref().map(|p| p.tree().unwrap()).as_ref())
+            .unwrap();
+        tree_builder
+            .insert(name, repo.blob(contents.as_bytes()).unwrap(), 0o100644)
+            .unwrap();
+        let tree = repo.find_tree(tree_builder.write().unwrap()).unwrap();
+        let parents = parent.iter().collect::<Vec<_>>();
+        repo.commit(None, &signature, &signature, name, &tree, &parents)
+            .unwrap()
+    };
+
+    let mut mainline = commit_file(None, "README", "Benchmark repo");
+    // The file that both sides change, with lines far enough apart
+    // that the changes merge cleanly.
+    let shared = |first: &str, last: &str| format!("{}\n{}{}\n", first, "\n".repeat(10), last);
+    if same_file {
+        mainline = commit_file(Some(mainline), "shared", &shared("branch", "mainline"));
+    }
+    let mut merge_ids = Vec::new();
+    for i in 0..MERGE_COMMITS {
+        let (branch, ours) = if same_file {
+            let (previous_branch, previous_mainline) = match i {
+                0 => ("branch".to_string(), "mainline".to_string()),
+                _ => (format!("branch-{}", i - 1), format!("mainline-{}", i - 1)),
+            };
+            (
+                commit_file(
+                    Some(mainline),
+                    "shared",
+                    &shared(&format!("branch-{}", i), &previous_mainline),
+                ),
+                commit_file(
+                    Some(mainline),
+                    "shared",
+                    &shared(&previous_branch, &format!("mainline-{}", i)),
+                ),
+            )
+        } else {
+            (
+                commit_file(Some(mainline), &format!("branch-{}", i), "branch"),
+                commit_file(Some(mainline), &format!("mainline-{}", i), "mainline"),
+            )
+        };
+        let ours = repo.find_commit(ours).unwrap();
+        let theirs = repo.find_commit(branch).unwrap();
+        let tree_id = repo
+            .merge_commits(&ours, &theirs, None)
+            .unwrap()
+            .write_tree_to(&repo)
+            .unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        mainline = repo
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "Merge",
+                &tree,
+                &[&ours, &theirs],
+            )
+            .unwrap();
+        merge_ids.push(mainline);
+    }
+    repo.reference("refs/heads/master", mainline, true, "benchmark")
+        .unwrap();
+
+    let git = LiveGit::default(&path).unwrap();
+    let merge_commits = merge_ids
+        .into_iter()
+        .map(|id| git.find_commit(id, &None).unwrap())
+        .collect();
+    BenchRepo {
+        path,
+        merge_commits,
+    }
+}
+
+// The approach used before merges were reproduced in memory: clone
+// the repo into a temp directory, and write the reproduced merge out
+// as a tree to compare its id.
+fn is_trivial_merge_commit_with_temp_clone(temp_repo: &Repository, commit: &Commit) -> bool {
+    let commit = temp_repo.find_commit(commit.id).unwrap();
+    let parents = commit.parents().collect::<Vec<_>>();
+    temp_repo
+        .merge_commits(
+            &parents[0],
+            &parents[1],
+            Some(MergeOptions::new().fail_on_conflict(true)),
+        )
+        .and_then(|mut index| index.write_tree_to(temp_repo))
+        .map(|id| id == commit.tree_id())
+        .unwrap_or(false)
+}
+
+fn temp_clone(src: &Path) -> Repository {
+    let path = std::env::temp_dir().join(format!("capn_tmp_{}.git", Uuid::new_v4()));
+    let status = Command::new("git")
+        .arg("clone")
+        .arg("--bare")
+        .arg("--shared")
+        .arg("--quiet")
+        .arg(src)
+        .arg(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    Repository::open(path).unwrap()
+}
+
+fn trivial_merge_benchmark(c: &mut Criterion) {
+    let bench_repo = bench_repo(false);
+    let mut group = c.benchmark_group(format!("push with {} merge commits", MERGE_COMMITS));
+    group.sample_size(20);
+
+    group.bench_function("in memory", |b| {
+        b.iter(|| {
+            let git = LiveGit::default(&bench_repo.path).unwrap();
+            for commit in &bench_repo.merge_commits {
+                assert!(git.is_trivial_merge_commit(commit).unwrap());
+            }
+        })
+    });
+
+    group.bench_function("temp clone", |b| {
+        b.iter(|| {
+            let temp_repo = temp_clone(&bench_repo.path);
+            for commit in &bench_repo.merge_commits {
+                assert!(is_trivial_merge_commit_with_temp_clone(&temp_repo, commit));
+            }
+            fs::remove_dir_all(temp_repo.path()).unwrap();
+        })
+    });
+
+    group.finish();
+}
+
+fn content_merge_benchmark(c: &mut Criterion) {
+    let bench_repo = bench_repo(true);
+    let mut group = c.benchmark_group(format!(
+        "push with {} merge commits of the same file",
+        MERGE_COMMITS
+    ));
+    group.sample_size(20);
+
+    group.bench_function("in memory", |b| {
+        b.iter(|| {
+            let git = LiveGit::default(&bench_repo.path).unwrap();
+            for commit in &bench_repo.merge_commits {
+                assert!(git.is_trivial_merge_commit(commit).unwrap());
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, trivial_merge_benchmark, content_merge_benchmark);
+criterion_main!(benches);
@@ -1,4 +1,9 @@
+use crate::keyring::Keyring;
+
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use toml;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -7,6 +12,24 @@ pub struct Config {
     pub git: GitConfig,
     pub prepend_branch_name: Option<Unit>,
     pub verify_git_commits: Option<VerifyGitCommitsConfig>,
+    pub verify_tags: Option<VerifyTagsConfig>,
+    pub verify_file_sizes: Option<VerifyFileSizesConfig>,
+    pub verify_filenames: Option<VerifyFilenamesConfig>,
+    pub verify_sign_off: Option<VerifySignOffConfig>,
+    #[serde(default)]
+    pub verify_linear_history: Vec<LinearHistoryConfig>,
+    pub verify_subjects: Option<VerifySubjectsConfig>,
+    pub verify_parent_count: Option<VerifyParentCountConfig>,
+    pub verify_submodules_and_symlinks: Option<VerifySubmodulesAndSymlinksConfig>,
+    #[serde(default)]
+    pub external_policy: Vec<ExternalPolicyConfig>,
+    #[serde(default)]
+    pub wasm_policy: Vec<WasmPolicyConfig>,
+
+    /// Sections that aren't used by the built in policies, which may
+    /// be read by policies registered from other crates.
+    #[serde(flatten)]
+    pub other: toml::value::Table,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -23,10 +46,16 @@ impl Default for GitConfig {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct VerifyGitCommitsConfig {
-    pub author_domain: String,
-    pub committer_domain: String,
+    pub author_domain: EmailDomains,
+    pub committer_domain: EmailDomains,
+    #[serde(default)]
+    pub allowed_emails: Vec<String>,
+    #[serde(default = "default_false")]
+    pub allow_keyring_emails: bool,
+    #[serde(default)]
+    pub mailmap_file: Option<String>,
     pub keyserver: String,
     pub team_fingerprints_file: String,
     #[serde(default = "default_true")]
@@ -39,12 +68,341 @@ pub struct VerifyGitCommitsConfig {
     #[serde(default = "default_false")]
     pub verify_different_authors: bool,
     #[serde(default = "default_false")]
+    pub count_co_authored_by: bool,
+    #[serde(default = "default_false")]
+    pub count_reviewed_by: bool,
+    #[serde(default = "default_false")]
+    pub verify_trailer_authors: bool,
+    #[serde(default = "default_false")]
     pub verify_rebased: bool,
+    #[serde(default = "default_false")]
+    pub verify_signer_is_committer: bool,
+    #[serde(default = "default_false")]
+    pub verify_identity_names: bool,
+    #[serde(default = "default_false")]
+    pub normalise_identity_names: bool,
+    #[serde(default = "default_false")]
+    pub verify_author_is_committer: bool,
+    #[serde(default)]
+    pub max_future_skew_seconds: Option<i64>,
+    #[serde(default)]
+    pub max_parent_skew_seconds: Option<i64>,
+    #[serde(default = "default_false")]
+    pub verify_signing_key_validity: bool,
 
     #[serde(default)]
     pub override_tag_pattern: Option<String>,
-    #[serde(default = "default_two")]
-    pub override_tags_required: u8,
+    #[serde(default = "default_two_signers")]
+    pub override_tags_required: SignerRequirement,
+
+    #[serde(default)]
+    pub required_approvals: SignerRequirement,
+    /// Only tags matching this count as approvals. Unlike override tags
+    /// this can't be turned off, since otherwise any tag would count.
+    #[serde(default = "default_approval_tag_pattern")]
+    pub approval_tag_pattern: String,
+    #[serde(default)]
+    pub code_owners_file: Option<String>,
+
+    #[serde(default)]
+    pub time_budget_ms: Option<u64>,
+    #[serde(default)]
+    pub on_time_budget_exceeded: TimeBudgetOutcome,
+
+    #[serde(default = "default_false")]
+    pub verification_cache: bool,
+
+    #[serde(default = "default_false")]
+    pub audit_notes: bool,
+    #[serde(default)]
+    pub audit_signing_key: Option<String>,
+}
+
+/// The same defaults as a config section that only sets the required
+/// fields. The domains, keyserver and Team Fingerprints file are left
+/// empty, since they have no sensible default.
+impl Default for VerifyGitCommitsConfig {
+    fn default() -> VerifyGitCommitsConfig {
+        VerifyGitCommitsConfig {
+            author_domain: EmailDomains::Many(Vec::new()),
+            committer_domain: EmailDomains::Many(Vec::new()),
+            allowed_emails: Vec::new(),
+            allow_keyring_emails: default_false(),
+            mailmap_file: None,
+            keyserver: String::new(),
+            team_fingerprints_file: String::new(),
+            recv_keys_par: default_true(),
+            verify_email_addresses: default_true(),
+            verify_commit_signatures: default_true(),
+            verify_different_authors: default_false(),
+            count_co_authored_by: default_false(),
+            count_reviewed_by: default_false(),
+            verify_trailer_authors: default_false(),
+            verify_rebased: default_false(),
+            verify_signer_is_committer: default_false(),
+            verify_identity_names: default_false(),
+            normalise_identity_names: default_false(),
+            verify_author_is_committer: default_false(),
+            max_future_skew_seconds: None,
+            max_parent_skew_seconds: None,
+            verify_signing_key_validity: default_false(),
+            override_tag_pattern: None,
+            override_tags_required: default_two_signers(),
+            required_approvals: SignerRequirement::default(),
+            approval_tag_pattern: default_approval_tag_pattern(),
+            code_owners_file: None,
+            time_budget_ms: None,
+            on_time_budget_exceeded: TimeBudgetOutcome::default(),
+            verification_cache: default_false(),
+            audit_notes: default_false(),
+            audit_signing_key: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct VerifyFileSizesConfig {
+    #[serde(default)]
+    pub max_file_size_bytes: Option<u64>,
+    /// Globs for binary files that need to be stored in Git LFS, like
+    /// `*.zip`. They follow the .gitignore rules.
+    #[serde(default)]
+    pub lfs_required: Vec<String>,
+    #[serde(default = "default_true")]
+    pub verify_lfs_pointers: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct VerifyFilenamesConfig {
+    #[serde(default = "default_true")]
+    pub verify_case_collisions: bool,
+    #[serde(default = "default_true")]
+    pub verify_windows_names: bool,
+    #[serde(default = "default_true")]
+    pub verify_unicode: bool,
+    #[serde(default)]
+    pub max_path_length: Option<usize>,
+    #[serde(default)]
+    pub time_budget_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct VerifySignOffConfig {
+    /// The repo's mailmap, so that authors can sign off as either
+    /// their canonical identity or the one written in the commit.
+    #[serde(default)]
+    pub mailmap_file: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct VerifySubmodulesAndSymlinksConfig {
+    pub keyserver: String,
+    pub team_fingerprints_file: String,
+
+    /// Globs for the paths where submodules may be added, changed or
+    /// pointed at a different URL, like `vendor/`. They follow the
+    /// .gitignore rules.
+    #[serde(default)]
+    pub allowed_submodule_paths: Vec<String>,
+    /// Globs for the paths where symlinks may point outside the repo.
+    /// They follow the .gitignore rules.
+    #[serde(default)]
+    pub allowed_symlink_paths: Vec<String>,
+
+    #[serde(default)]
+    pub override_tag_pattern: Option<String>,
+    #[serde(default = "default_two_signers")]
+    pub override_tags_required: SignerRequirement,
+}
+
+/// The domains that email addresses are allowed to have. This can be
+/// a single domain, like `"jemstep.com"`, or a list of globs, like
+/// `["jemstep.com", "*.jemstep.com"]`. Domains are matched ignoring
+/// case.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum EmailDomains {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<&str> for EmailDomains {
+    fn from(domain: &str) -> EmailDomains {
+        EmailDomains::One(domain.to_string())
+    }
+}
+
+impl EmailDomains {
+    pub fn patterns(&self) -> &[String] {
+        match self {
+            EmailDomains::One(domain) => std::slice::from_ref(domain),
+            EmailDomains::Many(domains) => domains,
+        }
+    }
+}
+
+impl fmt::Display for EmailDomains {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.patterns().join(", "))
+    }
+}
+
+/// Globs for commit subjects that can't be pushed to a mainline, like
+/// `fixup! *`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct VerifySubjectsConfig {
+    #[serde(default)]
+    pub rejected_subjects: Vec<String>,
+}
+
+/// The most parents that a commit can have. 2 rejects octopus merges,
+/// and 1 rejects all merge commits.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct VerifyParentCountConfig {
+    pub max_parents: u8,
+}
+
+/// Refs that can't have merge commits pushed to them. `refs` is a
+/// glob matched against the full ref name, like `refs/heads/release/*`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct LinearHistoryConfig {
+    pub refs: String,
+    #[serde(default = "default_false")]
+    pub fast_forward_only: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct VerifyTagsConfig {
+    pub keyserver: String,
+    pub team_fingerprints_file: String,
+
+    #[serde(default)]
+    pub tag_name_pattern: Option<String>,
+    #[serde(default = "default_true")]
+    pub verify_tag_signatures: bool,
+    #[serde(default)]
+    pub signer_group: Option<String>,
+    #[serde(default = "default_true")]
+    pub verify_reachable_from_mainline: bool,
+
+    #[serde(default = "default_false")]
+    pub allow_moving_tags: bool,
+    #[serde(default = "default_false")]
+    pub allow_deleting_tags: bool,
+}
+
+/// How many different people need to have signed something, either as
+/// a number, like `2`, or with a minimum from some of the groups in
+/// the Team Fingerprints file, like `{ signers = 2, groups = {
+/// security = 1 } }`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum SignerRequirement {
+    Count(u8),
+    Groups {
+        signers: u8,
+        #[serde(default)]
+        groups: BTreeMap<String, u8>,
+    },
+}
+
+impl Default for SignerRequirement {
+    fn default() -> SignerRequirement {
+        SignerRequirement::Count(0)
+    }
+}
+
+impl From<u8> for SignerRequirement {
+    fn from(signers: u8) -> SignerRequirement {
+        SignerRequirement::Count(signers)
+    }
+}
+
+impl SignerRequirement {
+    pub fn signers(&self) -> u8 {
+        match self {
+            SignerRequirement::Count(signers) => *signers,
+            SignerRequirement::Groups { signers, .. } => *signers,
+        }
+    }
+
+    /// Whether anyone needs to sign at all, counting the signers needed
+    /// from groups as well.
+    pub fn is_required(&self) -> bool {
+        self.signers() > 0
+            || match self {
+                SignerRequirement::Count(_) => false,
+                SignerRequirement::Groups { groups, .. } => {
+                    groups.values().any(|required| *required > 0)
+                }
+            }
+    }
+
+    pub fn is_met_by(&self, signers: &HashSet<&String>, keyring: &Keyring) -> bool {
+        let groups_are_met = match self {
+            SignerRequirement::Count(_) => true,
+            SignerRequirement::Groups { groups, .. } => groups.iter().all(|(group, required)| {
+                signers
+                    .iter()
+                    .filter(|signer| keyring.is_in_group(signer, group))
+                    .count()
+                    >= (*required).into()
+            }),
+        };
+        signers.len() >= self.signers().into() && groups_are_met
+    }
+}
+
+impl fmt::Display for SignerRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} signers", self.signers())?;
+        if let SignerRequirement::Groups { groups, .. } = self {
+            for (group, required) in groups {
+                write!(f, ", at least {} from @{}", required, group)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeBudgetOutcome {
+    #[default]
+    FailClosed,
+    FailOpen,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ExternalPolicyConfig {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "default_external_policy_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct WasmPolicyConfig {
+    pub name: String,
+    pub module: String,
+    #[serde(default)]
+    pub location: WasmModuleLocation,
+    #[serde(default = "default_wasm_policy_fuel")]
+    pub fuel: u64,
+    #[serde(default = "default_wasm_policy_max_memory_bytes")]
+    pub max_memory_bytes: usize,
+    #[serde(default = "default_external_policy_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WasmModuleLocation {
+    #[default]
+    Repo,
+    Server,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -58,14 +416,37 @@ fn default_false() -> bool {
     false
 }
 
-fn default_two() -> u8 {
-    2
+fn default_approval_tag_pattern() -> String {
+    "capn-approve-*".to_string()
+}
+
+fn default_two_signers() -> SignerRequirement {
+    SignerRequirement::Count(2)
+}
+
+fn default_external_policy_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_wasm_policy_fuel() -> u64 {
+    1_000_000_000
+}
+
+fn default_wasm_policy_max_memory_bytes() -> usize {
+    64 * 1024 * 1024
 }
 
 impl Config {
     pub fn from_toml_string(input: &str) -> Result<Config, toml::de::Error> {
         toml::from_str(input)
     }
+
+    pub fn section<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, toml::de::Error> {
+        self.other
+            .get(name)
+            .map(|section| section.clone().try_into())
+            .transpose()
+    }
 }
 
 impl GitConfig {
@@ -73,3 +454,17 @@ impl GitConfig {
         vec!["HEAD".into()]
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verify_git_commits_default_matches_a_minimal_config_section() {
+        let config: VerifyGitCommitsConfig = toml::from_str(
+            "author_domain = []\ncommitter_domain = []\nkeyserver = \"\"\nteam_fingerprints_file = \"\"\n",
+        )
+        .unwrap();
+        assert_eq!(config, VerifyGitCommitsConfig::default());
+    }
+}
@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt;
+use std::time::Instant;
 
 #[derive(Debug)]
 pub struct CapnError {
@@ -21,3 +22,33 @@ impl fmt::Display for CapnError {
 }
 
 impl Error for CapnError {}
+
+/// Returned when the `time_budget_ms` deadline passes partway through
+/// a check, so that the rest of the check is skipped.
+#[derive(Debug)]
+pub struct OutOfTime {
+    pub commits_verified: usize,
+}
+
+impl OutOfTime {
+    /// Fails with `OutOfTime` if the deadline has passed.
+    pub fn check(deadline: Option<Instant>, commits_verified: usize) -> Result<(), OutOfTime> {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            Err(OutOfTime { commits_verified })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for OutOfTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Time budget exceeded after verifying {} commits",
+            self.commits_verified
+        )
+    }
+}
+
+impl Error for OutOfTime {}
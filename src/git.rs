@@ -1,13 +1,15 @@
 use crate::error::CapnError;
+use crate::gpg::Gpg;
 use crate::keyring::Keyring;
+use crate::mailmap::Identity;
 use git2;
 use git2::{ErrorClass, ErrorCode, ObjectType, Oid, Repository};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::*;
 use std::str;
 use uuid::Uuid;
@@ -18,12 +20,130 @@ use log::*;
 #[derive(Debug, Clone)]
 pub struct Commit {
     pub id: Oid,
+    pub author_name: Option<String>,
     pub author_email: Option<String>,
+    pub committer_name: Option<String>,
     pub committer_email: Option<String>,
+    /// The author and committer written in the commit, if the mailmap
+    /// replaced them with different canonical identities.
+    pub raw_author: Option<Identity>,
+    pub raw_committer: Option<Identity>,
+    /// Seconds since the Unix epoch.
+    pub author_time: i64,
+    /// Seconds since the Unix epoch.
+    pub committer_time: i64,
     pub is_identical_tree_to_any_parent: bool,
     pub is_merge_commit: bool,
     pub tags: Vec<Tag>,
     pub parents: Vec<Oid>,
+    pub message: String,
+}
+
+impl Commit {
+    pub fn author(&self) -> Identity {
+        Identity {
+            name: self.author_name.clone(),
+            email: self.author_email.clone(),
+        }
+    }
+
+    pub fn committer(&self) -> Identity {
+        Identity {
+            name: self.committer_name.clone(),
+            email: self.committer_email.clone(),
+        }
+    }
+
+    /// The author email, followed by the email written in the commit
+    /// if the mailmap changed it.
+    pub fn reported_author_email(&self) -> String {
+        reported(
+            &self.author_email,
+            self.raw_author.as_ref().map(|a| &a.email),
+        )
+    }
+
+    /// The committer email, followed by the email written in the
+    /// commit if the mailmap changed it.
+    pub fn reported_committer_email(&self) -> String {
+        reported(
+            &self.committer_email,
+            self.raw_committer.as_ref().map(|c| &c.email),
+        )
+    }
+
+    /// The first line of the commit message.
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or_default().trim()
+    }
+
+    /// The trailers at the end of the commit message, like
+    /// `Signed-off-by: Name <email>`. Following `git interpret-trailers`,
+    /// these are only found in the last paragraph of the message, and
+    /// only if every line in it is a trailer or a continuation of one.
+    pub fn trailers(&self) -> Vec<Trailer> {
+        let lines = self
+            .message
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>();
+        let end = match lines.iter().rposition(|line| !line.is_empty()) {
+            Some(last) => last + 1,
+            None => return Vec::new(),
+        };
+        let start = match lines[..end].iter().rposition(|line| line.is_empty()) {
+            Some(blank) => blank + 1,
+            None => return Vec::new(), // the subject is never a trailer
+        };
+
+        let mut trailers: Vec<Trailer> = Vec::new();
+        for line in &lines[start..end] {
+            if line.starts_with(char::is_whitespace) {
+                match trailers.last_mut() {
+                    Some(trailer) => {
+                        trailer.value.push(' ');
+                        trailer.value.push_str(line.trim());
+                    }
+                    None => return Vec::new(),
+                }
+            } else {
+                match line.split_once(':') {
+                    Some((key, value)) if !key.is_empty() && !key.contains(char::is_whitespace) => {
+                        trailers.push(Trailer {
+                            key: key.to_string(),
+                            value: value.trim().to_string(),
+                        })
+                    }
+                    _ => return Vec::new(),
+                }
+            }
+        }
+        trailers
+    }
+}
+
+/// A canonical name or email, with the one written in the commit if
+/// it was different, like `alice@jemstep.com (mailmapped from
+/// alice@old.com)`.
+pub fn reported(canonical: &Option<String>, raw: Option<&Option<String>>) -> String {
+    let canonical = canonical.as_deref().unwrap_or_default();
+    match raw.and_then(|raw| raw.as_deref()) {
+        Some(raw) if raw != canonical => format!("{} (mailmapped from {})", canonical, raw),
+        _ => canonical.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trailer {
+    pub key: String,
+    pub value: String,
+}
+
+impl Trailer {
+    pub fn is(&self, key: &str) -> bool {
+        self.key.eq_ignore_ascii_case(key)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -31,18 +151,58 @@ pub struct Tag {
     pub id: Oid,
     pub name: String,
     pub tagger_email: Option<String>,
+    pub target_id: Oid,
+}
+
+/// A file that a commit added or changed, compared to all of its
+/// parents.
+#[derive(Debug, Clone)]
+pub struct AddedBlob {
+    pub id: Oid,
+    pub path: String,
+    pub size: usize,
+    pub is_binary: bool,
+}
+
+/// A submodule or symlink that a commit added or changed, compared
+/// to all of its parents.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddedLink {
+    Submodule { path: String, commit_id: Oid },
+    Symlink { path: String, target: String },
+}
+
+/// The paths, including directories, that a commit adds and removes
+/// compared to its first parent.
+#[derive(Debug, Clone, Default)]
+pub struct PathChanges {
+    pub added: Vec<Vec<u8>>,
+    pub removed: Vec<Vec<u8>>,
+    /// The added paths that one of a merge commit's other parents
+    /// already has, so they were added by the merged branch.
+    pub merged: HashSet<Vec<u8>>,
 }
 
 pub trait Git: Sized {
     fn read_file(&self, path: &str) -> Result<String, Box<dyn Error>>;
+    fn read_git_file(&self, path: &str) -> Result<Option<String>, Box<dyn Error>>;
     fn write_git_file(
         &self,
         path: &str,
         file_mode: u32,
         contents: &str,
     ) -> Result<(), Box<dyn Error>>;
+    /// Removes a file from the Git directory, if it's there.
+    fn remove_git_file(&self, path: &str) -> Result<(), Box<dyn Error>>;
+    /// A value from the repo's own Git config, which can't be changed
+    /// by pushing to it.
+    fn config_value(&self, name: &str) -> Result<Option<String>, Box<dyn Error>>;
     fn current_branch(&self) -> Result<String, Box<dyn Error>>;
     fn is_tag(&self, ref_name: &str) -> Result<bool, Box<dyn Error>>;
+    /// The annotated tag with this id, or `None` if the object isn't
+    /// an annotated tag, like the commit that a lightweight tag points
+    /// to. The tag's target is peeled to a commit.
+    fn find_tag(&self, tag_id: Oid) -> Result<Option<Tag>, Box<dyn Error>>;
     fn find_commit(
         &self,
         commit_id: Oid,
@@ -69,19 +229,60 @@ pub trait Git: Sized {
         tag: &Tag,
         keyring: &Keyring,
     ) -> Result<bool, Box<dyn Error>>;
+    /// The fingerprint of the primary key that made a valid signature
+    /// on the commit, or `None` if it isn't signed with a key in the
+    /// local keyring.
+    fn commit_signer(path: &Path, commit: &Commit) -> Result<Option<String>, Box<dyn Error>>;
+    /// The fingerprint of the key that made a valid signature on the
+    /// commit, which is a subkey if the primary key has a signing
+    /// subkey, or `None` if it isn't signed with a key in the local
+    /// keyring.
+    fn commit_signing_key(path: &Path, commit: &Commit) -> Result<Option<String>, Box<dyn Error>>;
     fn read_config(&self) -> Result<Config, Box<dyn Error>> {
         let config_str = self.read_file(".capn")?;
         let config = Config::from_toml_string(&config_str)?;
         Ok(config)
     }
     fn is_descendent_of(&self, commit: Oid, ancestor: Oid) -> Result<bool, Box<dyn Error>>;
+    fn head_commit_id(&self) -> Result<Option<Oid>, Box<dyn Error>>;
+    fn read_file_at_commit(
+        &self,
+        commit_id: Oid,
+        path: &str,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
+    fn commit_diff(&self, commit_id: Oid) -> Result<Option<String>, Box<dyn Error>>;
+    fn changed_paths(
+        &self,
+        old_commit_id: Oid,
+        new_commit_id: Oid,
+    ) -> Result<Vec<String>, Box<dyn Error>>;
+    fn added_blobs(&self, commit_id: Oid) -> Result<Vec<AddedBlob>, Box<dyn Error>>;
+    fn added_links(&self, commit_id: Oid) -> Result<Vec<AddedLink>, Box<dyn Error>>;
+    fn read_blob(&self, blob_id: Oid) -> Result<Vec<u8>, Box<dyn Error>>;
+    fn tree_paths(&self, commit_id: Oid) -> Result<Vec<Vec<u8>>, Box<dyn Error>>;
+    fn path_changes(&self, commit_id: Oid) -> Result<PathChanges, Box<dyn Error>>;
+    fn resolve_commit(&self, revision: &str) -> Result<Oid, Box<dyn Error>>;
+    fn read_note(&self, notes_ref: &str, commit_id: Oid) -> Result<Option<String>, Box<dyn Error>>;
+    /// Adds the notes in a single commit to `notes_ref`. If there's a
+    /// `signing_key`, the notes commit is signed with it by `gpg`.
+    fn write_notes<P: Gpg>(
+        &self,
+        notes_ref: &str,
+        notes: &[(Oid, String)],
+        gpg: &P,
+        signing_key: Option<&str>,
+    ) -> Result<(), Box<dyn Error>>;
 }
 
+type TagCache = HashMap<Option<String>, HashMap<Oid, Vec<Tag>>>;
+
+/// The path, ID and mode of an entry in a tree.
+type TreeEntry = (String, Oid, git2::FileMode);
+
 pub struct LiveGit {
     repo: Repository,
     config: GitConfig,
-    tag_cache: RefCell<HashMap<Option<String>, HashMap<Oid, Vec<Tag>>>>,
-    temp_repo_clone: RefCell<Option<TempRepo>>,
+    tag_cache: RefCell<TagCache>,
 }
 
 impl Git for LiveGit {
@@ -113,6 +314,33 @@ impl Git for LiveGit {
         }
     }
 
+    fn read_git_file(&self, path: &str) -> Result<Option<String>, Box<dyn Error>> {
+        match std::fs::read_to_string(self.repo.path().join(path)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn remove_git_file(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        match std::fs::remove_file(self.repo.path().join(path)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn config_value(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        match self.repo.config()?.get_string(name) {
+            Ok(value) => Ok(Some(value)),
+            Err(ref e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Files are written to a temporary file in the Git directory and
+    // then renamed into place, so that a hook that runs at the same
+    // time never reads a half written file.
     #[cfg(windows)]
     fn write_git_file(
         &self,
@@ -121,10 +349,15 @@ impl Git for LiveGit {
         contents: &str,
     ) -> Result<(), Box<dyn Error>> {
         let dotgit_dir = self.repo.path();
-        let mut file = File::create(dotgit_dir.join(path))?;
-        file.write_all(contents.as_bytes())?;
+        let temp_path = dotgit_dir.join(format!("{}.{}.tmp", path, Uuid::new_v4()));
+        let result = File::create(&temp_path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .and_then(|_| std::fs::rename(&temp_path, dotgit_dir.join(path)));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
 
-        Ok(())
+        Ok(result?)
     }
 
     #[cfg(unix)]
@@ -137,12 +370,18 @@ impl Git for LiveGit {
         use std::os::unix::fs::PermissionsExt;
 
         let dotgit_dir = self.repo.path();
-        let mut file = File::create(dotgit_dir.join(path))?;
-        file.set_permissions(PermissionsExt::from_mode(file_mode))?;
-
-        file.write_all(contents.as_bytes())?;
+        let temp_path = dotgit_dir.join(format!("{}.{}.tmp", path, Uuid::new_v4()));
+        let result = File::create(&temp_path)
+            .and_then(|mut file| {
+                file.set_permissions(PermissionsExt::from_mode(file_mode))?;
+                file.write_all(contents.as_bytes())
+            })
+            .and_then(|_| std::fs::rename(&temp_path, dotgit_dir.join(path)));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
 
-        Ok(())
+        Ok(result?)
     }
 
     fn current_branch(&self) -> Result<String, Box<dyn Error>> {
@@ -161,20 +400,29 @@ impl Git for LiveGit {
     ) -> Result<Commit, Box<dyn Error>> {
         let commit = self.repo.find_commit(commit_id)?;
         let committer = commit.committer();
+        let committer_name = committer.name().map(|s| s.to_string());
         let committer_email = committer.email().map(|s| s.to_string());
         let author = commit.author();
+        let author_name = author.name().map(|s| s.to_string());
         let author_email = author.email().map(|s| s.to_string());
 
         let tags = self.get_tags(commit_id, override_tag_pattern);
 
         Ok(Commit {
             id: commit.id(),
-            author_email: author_email,
-            committer_email: committer_email,
+            author_name,
+            author_email,
+            committer_name,
+            committer_email,
+            raw_author: None,
+            raw_committer: None,
+            author_time: author.when().seconds(),
+            committer_time: committer.when().seconds(),
             is_merge_commit: commit.parent_count() > 1,
             is_identical_tree_to_any_parent: Self::is_identical_tree_to_any_parent(&commit),
-            tags: tags,
+            tags,
             parents: commit.parent_ids().collect(),
+            message: String::from_utf8_lossy(commit.message_bytes()).into_owned(),
         })
     }
 
@@ -194,7 +442,7 @@ impl Git for LiveGit {
         for mainline in &self.config.mainlines {
             if mainline == "HEAD" {
                 revwalk.hide_head()?;
-            } else if mainline.contains(|c| c == '?' || c == '*' || c == '[') {
+            } else if mainline.contains(['?', '*', '[']) {
                 revwalk.hide_glob(&format!("refs/heads/{}", mainline))?;
             } else {
                 match revwalk.hide_ref(&format!("refs/heads/{}", mainline)) {
@@ -208,7 +456,6 @@ impl Git for LiveGit {
         }
 
         let commits = revwalk
-            .into_iter()
             .map(|id| {
                 id.map_err(|e| e.into())
                     .and_then(|id| self.find_commit(id, override_tag_pattern))
@@ -246,22 +493,8 @@ impl Git for LiveGit {
             }
         };
 
-        let result = Command::new("git")
-            .current_dir(path)
-            .arg("verify-tag")
-            .arg("--raw")
-            .arg(tag_id.to_string())
-            .output()?;
-        debug!(
-            "Result from calling git verify-tag on {}: {:?}",
-            tag_id, result
-        );
-
-        let encoded = String::from_utf8(result.stderr)?;
-
-        let valid = encoded
-            .split('\n')
-            .any(|s| s.contains(&format!("VALIDSIG {}", expected_fingerprint)));
+        let valid = Self::valid_signature(path, "verify-tag", tag_id)?
+            .is_some_and(|signature| signature.is_signed_by(&expected_fingerprint));
 
         if valid {
             debug!("Tag {} was signed with a valid signature", tag_id);
@@ -300,22 +533,8 @@ impl Git for LiveGit {
             }
         };
 
-        let result = Command::new("git")
-            .current_dir(path)
-            .arg("verify-commit")
-            .arg("--raw")
-            .arg(commit_id.to_string())
-            .output()?;
-        debug!(
-            "Result from calling git verify-commit on {}: {:?}",
-            commit_id, result
-        );
-
-        let encoded = String::from_utf8(result.stderr)?;
-
-        let valid = encoded
-            .split('\n')
-            .any(|s| s.contains(&format!("VALIDSIG {}", expected_fingerprint)));
+        let valid = Self::valid_signature(path, "verify-commit", commit_id)?
+            .is_some_and(|signature| signature.is_signed_by(&expected_fingerprint));
 
         if valid {
             debug!("Commit {} was signed with a valid signature", commit_id);
@@ -326,6 +545,16 @@ impl Git for LiveGit {
         }
     }
 
+    fn commit_signer(path: &Path, commit: &Commit) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(Self::valid_signature(path, "verify-commit", &commit.id)?
+            .map(|signature| signature.primary_key))
+    }
+
+    fn commit_signing_key(path: &Path, commit: &Commit) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(Self::valid_signature(path, "verify-commit", &commit.id)?
+            .map(|signature| signature.signing_key))
+    }
+
     fn is_merge_commit(&self, commit_id: Oid) -> bool {
         self.repo
             .find_commit(commit_id)
@@ -337,29 +566,31 @@ impl Git for LiveGit {
         &self,
         verification_commit: &Commit,
     ) -> Result<bool, Box<dyn Error>> {
-        use git2::MergeOptions;
+        let commit = self.repo.find_commit(verification_commit.id)?;
+        let parents = commit.parents().collect::<Vec<_>>();
+        if parents.len() < 2 {
+            return Ok(false);
+        }
 
-        self.with_temp_repo_clone(|temp_repo| {
-            let commit = temp_repo.repo.find_commit(verification_commit.id)?;
-            let parents = commit.parents().collect::<Vec<_>>();
-            match &parents[..] {
-                [a, b] => {
-                    let expected_tree_id = commit.tree_id();
-                    let reproduced_tree_id = temp_repo
-                        .repo
-                        .merge_commits(&a, &b, Some(MergeOptions::new().fail_on_conflict(true)))
-                        .and_then(|mut index| index.write_tree_to(&temp_repo.repo));
-                    trace!("Checking for a trivial merge commit, expecting tree_id of {}, result of reproducing tree is {:?}", expected_tree_id, reproduced_tree_id);
-                    let matches = reproduced_tree_id
-                        .as_ref()
-                        .map(|id| *id == expected_tree_id)
-                        .unwrap_or(false);
-
-                    Ok(matches)
-                }
-                _ => Ok(false),
+        let scratch_repo = self.scratch_repo()?;
+        let matches = match Self::reproduce_merge(&scratch_repo, &parents) {
+            Ok(index) => Self::is_index_identical_to_tree(&index, &commit.tree()?)?,
+            Err(e) => {
+                trace!(
+                    "Merge of the parents of {} could not be reproduced: {}",
+                    commit.id(),
+                    e
+                );
+                false
             }
-        })
+        };
+        trace!(
+            "Checking for a trivial merge commit {}, reproduced merge matches: {}",
+            commit.id(),
+            matches
+        );
+
+        Ok(matches)
     }
 
     fn is_mainline(&self, ref_name: &str) -> Result<bool, Box<dyn Error>> {
@@ -371,10 +602,7 @@ impl Git for LiveGit {
             git.repo
                 .references_glob(&format!("refs/heads/{}", glob))?
                 .names()
-                .map(|name| name.map(|n| n == ref_name))
-                .fold(Ok(false), |acc, next| {
-                    acc.and_then(|a| next.map(|b| a || b).map_err(|e| e.into()))
-                })
+                .try_fold(false, |acc, name| Ok(acc || name? == ref_name))
         }
 
         self.config
@@ -387,9 +615,7 @@ impl Git for LiveGit {
                     matches_glob(self, ref_name, mainline_glob)
                 }
             })
-            .fold(Ok(false), |acc, next| {
-                acc.and_then(|a| next.map(|b| a || b))
-            })
+            .try_fold(false, |acc, next| next.map(|b| acc || b))
     }
 
     fn is_tag(&self, ref_name: &str) -> Result<bool, Box<dyn Error>> {
@@ -400,39 +626,528 @@ impl Git for LiveGit {
         }
     }
 
+    fn find_tag(&self, tag_id: Oid) -> Result<Option<Tag>, Box<dyn Error>> {
+        let object = self.repo.find_object(tag_id, None)?;
+        match object.as_tag() {
+            Some(tag) => Ok(Some(Tag {
+                id: tag.id(),
+                name: tag.name().map(|s| s.to_string()).unwrap_or(String::new()),
+                tagger_email: tag
+                    .tagger()
+                    .and_then(|signature| signature.email().map(|s| s.to_string())),
+                target_id: object.peel_to_commit()?.id(),
+            })),
+            None => Ok(None),
+        }
+    }
+
     fn is_descendent_of(&self, commit: Oid, ancestor: Oid) -> Result<bool, Box<dyn Error>> {
         self.repo
             .graph_descendant_of(commit, ancestor)
             .map_err(|e| e.into())
     }
+
+    fn head_commit_id(&self) -> Result<Option<Oid>, Box<dyn Error>> {
+        match self.repo.head() {
+            Ok(head) => Ok(Some(head.peel_to_commit()?.id())),
+            Err(ref e)
+                if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn read_file_at_commit(
+        &self,
+        commit_id: Oid,
+        path: &str,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let commit = match self.repo.find_commit(commit_id) {
+            Ok(commit) => commit,
+            Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let entry = match commit.tree()?.get_path(Path::new(path)) {
+            Ok(entry) => entry,
+            Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let object = entry.to_object(&self.repo)?;
+        Ok(object.as_blob().map(|blob| blob.content().to_vec()))
+    }
+
+    /// The patch introduced by a commit, compared to its first
+    /// parent.
+    fn commit_diff(&self, commit_id: Oid) -> Result<Option<String>, Box<dyn Error>> {
+        let commit = match self.repo.find_commit(commit_id) {
+            Ok(commit) => commit,
+            Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let parent_tree = match commit.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+        let diff =
+            self.repo
+                .diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+        let mut patch = Vec::new();
+        diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
+            if let origin @ ('+' | '-' | ' ') = line.origin() {
+                patch.push(origin as u8);
+            }
+            patch.extend_from_slice(line.content());
+            true
+        })?;
+        Ok(Some(String::from_utf8_lossy(&patch).into_owned()))
+    }
+
+    fn changed_paths(
+        &self,
+        old_commit_id: Oid,
+        new_commit_id: Oid,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let old_tree = self.repo.find_commit(old_commit_id)?.tree()?;
+        let new_tree = self.repo.find_commit(new_commit_id)?.tree()?;
+        let diff = self
+            .repo
+            .diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)?;
+        let mut paths = diff
+            .deltas()
+            .flat_map(|delta| vec![delta.old_file().path(), delta.new_file().path()])
+            .flatten()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    /// Files in a merge commit that match one of its other parents
+    /// were added by the merged branch, so they aren't included.
+    /// Submodules aren't files, so they aren't included either.
+    fn added_blobs(&self, commit_id: Oid) -> Result<Vec<AddedBlob>, Box<dyn Error>> {
+        let mut blobs = Vec::new();
+        for (path, id, mode) in self.added_entries(commit_id)? {
+            if mode == git2::FileMode::Commit {
+                continue;
+            }
+            let blob = self.repo.find_blob(id)?;
+            blobs.push(AddedBlob {
+                id: blob.id(),
+                path,
+                size: blob.size(),
+                is_binary: blob.is_binary(),
+            });
+        }
+        Ok(blobs)
+    }
+
+    /// Like added_blobs, submodules and symlinks that match one of a
+    /// merge commit's other parents aren't included.
+    fn added_links(&self, commit_id: Oid) -> Result<Vec<AddedLink>, Box<dyn Error>> {
+        let mut links = Vec::new();
+        for (path, id, mode) in self.added_entries(commit_id)? {
+            match mode {
+                git2::FileMode::Commit => links.push(AddedLink::Submodule {
+                    path,
+                    commit_id: id,
+                }),
+                git2::FileMode::Link => {
+                    let blob = self.repo.find_blob(id)?;
+                    links.push(AddedLink::Symlink {
+                        path,
+                        target: String::from_utf8_lossy(blob.content()).into_owned(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(links)
+    }
+
+    fn read_blob(&self, blob_id: Oid) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(self.repo.find_blob(blob_id)?.content().to_vec())
+    }
+
+    /// The paths of everything in a commit's tree, including
+    /// directories. Paths are bytes, since Git doesn't need them to be
+    /// UTF-8.
+    fn tree_paths(&self, commit_id: Oid) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let tree = self.repo.find_commit(commit_id)?.tree()?;
+        let mut paths = Vec::new();
+        self.collect_tree_paths(&tree, &[], &mut paths)?;
+        Ok(paths)
+    }
+
+    // Only added and deleted files can add or remove paths. Their
+    // directories are looked up in the other tree, since a directory
+    // is only added or removed with the first or last file in it.
+    fn path_changes(&self, commit_id: Oid) -> Result<PathChanges, Box<dyn Error>> {
+        let commit = self.repo.find_commit(commit_id)?;
+        let tree = commit.tree()?;
+        let parent_trees = commit
+            .parents()
+            .map(|parent| parent.tree())
+            .collect::<Result<Vec<_>, _>>()?;
+        let diff = self
+            .repo
+            .diff_tree_to_tree(parent_trees.first(), Some(&tree), None)?;
+
+        let mut added = BTreeSet::new();
+        let mut removed = BTreeSet::new();
+        for delta in diff.deltas() {
+            let (file, other_tree, paths) = match delta.status() {
+                git2::Delta::Added => (delta.new_file(), parent_trees.first(), &mut added),
+                git2::Delta::Deleted => (delta.old_file(), Some(&tree), &mut removed),
+                _ => continue,
+            };
+            let (path, path_bytes) = match (file.path(), file.path_bytes()) {
+                (Some(path), Some(path_bytes)) => (path, path_bytes),
+                _ => continue,
+            };
+            for (path, path_bytes) in path_with_directories(path, path_bytes) {
+                let is_in_other_tree =
+                    other_tree.is_some_and(|other_tree| other_tree.get_path(path).is_ok());
+                if !is_in_other_tree {
+                    paths.insert((path.to_path_buf(), path_bytes.to_vec()));
+                }
+            }
+        }
+
+        let merged = added
+            .iter()
+            .filter(|(path, _)| {
+                parent_trees
+                    .iter()
+                    .skip(1)
+                    .any(|parent_tree| parent_tree.get_path(path).is_ok())
+            })
+            .map(|(_, path_bytes)| path_bytes.clone())
+            .collect();
+        Ok(PathChanges {
+            added: added
+                .into_iter()
+                .map(|(_, path_bytes)| path_bytes)
+                .collect(),
+            removed: removed
+                .into_iter()
+                .map(|(_, path_bytes)| path_bytes)
+                .collect(),
+            merged,
+        })
+    }
+
+    fn resolve_commit(&self, revision: &str) -> Result<Oid, Box<dyn Error>> {
+        Ok(self.repo.revparse_single(revision)?.peel_to_commit()?.id())
+    }
+
+    fn read_note(&self, notes_ref: &str, commit_id: Oid) -> Result<Option<String>, Box<dyn Error>> {
+        match self.repo.find_note(Some(notes_ref), commit_id) {
+            Ok(note) => Ok(note.message().map(|m| m.to_string())),
+            Err(ref e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_notes<P: Gpg>(
+        &self,
+        notes_ref: &str,
+        notes: &[(Oid, String)],
+        gpg: &P,
+        signing_key: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let signature = git2::Signature::now("Captain Git Hook", "capn@localhost")?;
+        let previous_notes_commit = match self.repo.find_reference(notes_ref) {
+            Ok(reference) => Some(reference.peel_to_commit()?),
+            Err(ref e) if e.code() == ErrorCode::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        for (commit_id, note) in notes {
+            self.repo.note(
+                &signature,
+                &signature,
+                Some(notes_ref),
+                *commit_id,
+                note,
+                true,
+            )?;
+        }
+
+        // libgit2 can't sign notes commits, so the commits it made
+        // are replaced with a single signed commit with the same tree.
+        if let Some(signing_key) = signing_key {
+            let unsigned_commit = self.repo.find_reference(notes_ref)?.peel_to_commit()?;
+            let parents = previous_notes_commit.iter().collect::<Vec<_>>();
+            let buffer = self.repo.commit_create_buffer(
+                &signature,
+                &signature,
+                "Notes added by Captain Git Hook",
+                &unsigned_commit.tree()?,
+                &parents,
+            )?;
+            let buffer = str::from_utf8(&buffer)?;
+            let gpg_signature = gpg.sign(buffer, signing_key)?;
+            let signed_commit_id = self.repo.commit_signed(buffer, &gpg_signature, None)?;
+            self.repo.reference_matching(
+                notes_ref,
+                signed_commit_id,
+                true,
+                unsigned_commit.id(),
+                "capn: sign notes",
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The fingerprints from a valid signature. The signing key is a
+/// subkey if the primary key has a signing subkey, otherwise they're
+/// the same.
+struct ValidSignature {
+    signing_key: String,
+    primary_key: String,
+}
+
+impl ValidSignature {
+    // Team Fingerprints files can list either the primary key or the
+    // signing subkey.
+    fn is_signed_by(&self, fingerprint: &str) -> bool {
+        self.signing_key == fingerprint || self.primary_key == fingerprint
+    }
 }
 
 impl LiveGit {
+    /// The valid signature on a commit or tag, checked with `git
+    /// verify-commit` or `git verify-tag`, if it's signed with a key in
+    /// the local keyring.
+    fn valid_signature(
+        path: &Path,
+        verify_command: &str,
+        object_id: &Oid,
+    ) -> Result<Option<ValidSignature>, Box<dyn Error>> {
+        let result = Command::new("git")
+            .current_dir(path)
+            .arg(verify_command)
+            .arg("--raw")
+            .arg(object_id.to_string())
+            .output()?;
+        debug!(
+            "Result from calling git {} on {}: {:?}",
+            verify_command, object_id, result
+        );
+
+        // VALIDSIG <fingerprint> <date> <timestamp> <expires> <version>
+        // <reserved> <algorithm> <hash> <class> <primary key fingerprint>
+        let encoded = String::from_utf8(result.stderr)?;
+        Ok(encoded
+            .lines()
+            .filter_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+            .filter_map(|fields| {
+                let fields = fields.split_whitespace().collect::<Vec<_>>();
+                Some(ValidSignature {
+                    signing_key: fields.first()?.to_string(),
+                    primary_key: fields.last()?.to_string(),
+                })
+            })
+            .next())
+    }
+
     pub fn default(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
-        let repo = Repository::discover(path)?;
+        let repo = Self::open_repo(path)?;
         Ok(LiveGit {
             repo,
             config: GitConfig::default(),
             tag_cache: RefCell::new(HashMap::new()),
-            temp_repo_clone: RefCell::new(None),
         })
     }
 
     pub fn new(path: impl AsRef<Path>, config: GitConfig) -> Result<Self, Box<dyn Error>> {
-        let repo = Repository::discover(path)?;
+        let repo = Self::open_repo(path)?;
         Ok(LiveGit {
             repo,
             config,
             tag_cache: RefCell::new(HashMap::new()),
-            temp_repo_clone: RefCell::new(None),
         })
     }
 
+    fn open_repo(path: impl AsRef<Path>) -> Result<Repository, Box<dyn Error>> {
+        let repo = Repository::discover(path)?;
+        {
+            let odb = repo.odb()?;
+            for object_dir in quarantine_object_directories() {
+                trace!(
+                    "Adding object directory from environment: {}",
+                    object_dir.display()
+                );
+                odb.add_disk_alternate(object_dir.to_str().ok_or_else(|| {
+                    CapnError::new("Object directory from environment was not valid UTF-8")
+                })?)?;
+            }
+        }
+        Ok(repo)
+    }
+
+    /// Everything that a commit added or changed, compared to its first
+    /// parent, that doesn't match one of its other parents.
+    fn added_entries(&self, commit_id: Oid) -> Result<Vec<TreeEntry>, Box<dyn Error>> {
+        let commit = self.repo.find_commit(commit_id)?;
+        let parent_trees = commit
+            .parents()
+            .map(|parent| parent.tree())
+            .collect::<Result<Vec<_>, _>>()?;
+        let diff =
+            self.repo
+                .diff_tree_to_tree(parent_trees.first(), Some(&commit.tree()?), None)?;
+
+        let mut entries = Vec::new();
+        for delta in diff.deltas() {
+            let new_file = delta.new_file();
+            if delta.status() == git2::Delta::Deleted {
+                continue;
+            }
+            let path = match new_file.path() {
+                Some(path) => path,
+                None => continue,
+            };
+            let is_in_other_parent = parent_trees.iter().skip(1).any(|tree| {
+                tree.get_path(path)
+                    .is_ok_and(|entry| entry.id() == new_file.id())
+            });
+            if is_in_other_parent {
+                continue;
+            }
+            entries.push((
+                path.to_string_lossy().into_owned(),
+                new_file.id(),
+                new_file.mode(),
+            ));
+        }
+        Ok(entries)
+    }
+
+    fn collect_tree_paths(
+        &self,
+        tree: &git2::Tree<'_>,
+        prefix: &[u8],
+        paths: &mut Vec<Vec<u8>>,
+    ) -> Result<(), Box<dyn Error>> {
+        for entry in tree.iter() {
+            let mut path = prefix.to_vec();
+            if !path.is_empty() {
+                path.push(b'/');
+            }
+            path.extend_from_slice(entry.name_bytes());
+            if entry.kind() == Some(ObjectType::Tree) {
+                let subtree = self.repo.find_tree(entry.id())?;
+                self.collect_tree_paths(&subtree, &path, paths)?;
+            }
+            paths.push(path);
+        }
+        Ok(())
+    }
+
     fn is_identical_tree_to_any_parent(commit: &git2::Commit<'_>) -> bool {
         let tree_id = commit.tree_id();
         commit.parents().any(|p| p.tree_id() == tree_id)
     }
 
+    // Reproducing a merge writes the merged blobs, and the trees between
+    // the merges of an octopus merge, to the object database. This repo
+    // reads objects from the real one, but writes them to memory, so
+    // that checking a merge doesn't leave objects behind in the repo,
+    // or outside of the quarantine during pre-receive.
+    fn scratch_repo(&self) -> Result<Repository, Box<dyn Error>> {
+        let odb = git2::Odb::new()?;
+        odb.add_new_mempack_backend(1000)?;
+        let object_dirs =
+            std::iter::once(self.objects_dir()).chain(quarantine_object_directories());
+        for object_dir in object_dirs {
+            odb.add_disk_alternate(
+                object_dir
+                    .to_str()
+                    .ok_or_else(|| CapnError::new("Object directory was not valid UTF-8"))?,
+            )?;
+        }
+        Ok(Repository::from_odb(odb)?)
+    }
+
+    // Linked worktrees keep their objects in the main repo's git dir,
+    // which they point to with a commondir file.
+    fn objects_dir(&self) -> PathBuf {
+        let git_dir = self.repo.path();
+        let common_dir = match std::fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common_dir) => git_dir.join(common_dir.trim()),
+            Err(_) => git_dir.to_path_buf(),
+        };
+        common_dir.join("objects")
+    }
+
+    // Merges the parents one at a time, the way that Git's octopus
+    // strategy does, so that merges with more than two parents can be
+    // reproduced too. Each parent after the second is merged into the
+    // tree of the merges before it, using the merge base of that parent
+    // and all of the parents already merged.
+    fn reproduce_merge(
+        repo: &Repository,
+        parents: &[git2::Commit<'_>],
+    ) -> Result<git2::Index, git2::Error> {
+        use git2::MergeOptions;
+
+        let mut options = MergeOptions::new();
+        options.fail_on_conflict(true);
+
+        let parents = parents
+            .iter()
+            .map(|parent| repo.find_commit(parent.id()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut index = repo.merge_commits(&parents[0], &parents[1], Some(&options))?;
+        for (merged, parent) in parents.iter().enumerate().skip(2) {
+            let ours = repo.find_tree(index.write_tree_to(repo)?)?;
+            let mut merge_base_ids = vec![parent.id()];
+            merge_base_ids.extend(parents[..merged].iter().map(|p| p.id()));
+            let merge_base = repo.merge_base_many(&merge_base_ids)?;
+            let ancestor = repo.find_commit(merge_base)?.tree()?;
+            index = repo.merge_trees(&ancestor, &ours, &parent.tree()?, Some(&options))?;
+        }
+        Ok(index)
+    }
+
+    // Compares the entries of an in-memory index to a tree, without
+    // needing to write the index out to the object database as a tree.
+    fn is_index_identical_to_tree(
+        index: &git2::Index,
+        tree: &git2::Tree<'_>,
+    ) -> Result<bool, Box<dyn Error>> {
+        use git2::{TreeWalkMode, TreeWalkResult};
+
+        if index.has_conflicts() {
+            return Ok(false);
+        }
+
+        let mut tree_entries = Vec::new();
+        tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() != Some(ObjectType::Tree) {
+                let mut path = root.as_bytes().to_vec();
+                path.extend_from_slice(entry.name_bytes());
+                tree_entries.push((path, entry.filemode() as u32, entry.id()));
+            }
+            TreeWalkResult::Ok
+        })?;
+        tree_entries.sort();
+
+        let mut index_entries = index
+            .iter()
+            .map(|entry| (entry.path, entry.mode, entry.id))
+            .collect::<Vec<_>>();
+        index_entries.sort();
+
+        Ok(tree_entries == index_entries)
+    }
+
     fn get_tags(&self, commit_id: Oid, pattern: &Option<String>) -> Vec<Tag> {
         let mut tag_cache = self.tag_cache.borrow_mut();
 
@@ -453,13 +1168,14 @@ impl LiveGit {
                     .filter(|tag| tag.target_type() == Some(ObjectType::Commit))
                     .fold(HashMap::new(), |mut map, tag| {
                         map.entry(tag.target_id())
-                            .or_insert_with(|| Vec::new())
+                            .or_insert_with(Vec::new)
                             .push(Tag {
                                 id: tag.id(),
                                 name: tag.name().map(|s| s.to_string()).unwrap_or(String::new()),
                                 tagger_email: tag
                                     .tagger()
                                     .and_then(|signature| signature.email().map(|s| s.to_string())),
+                                target_id: tag.target_id(),
                             });
                         map
                     })
@@ -468,84 +1184,52 @@ impl LiveGit {
             .cloned()
             .unwrap_or(Vec::new())
     }
+}
 
-    fn with_temp_repo_clone<T>(
-        &self,
-        f: impl Fn(&TempRepo) -> Result<T, Box<dyn Error>>,
-    ) -> Result<T, Box<dyn Error>> {
-        let mut cache = self.temp_repo_clone.borrow_mut();
-        if let Some(temp_repo) = cache.as_ref() {
-            f(temp_repo)
-        } else {
-            let temp_repo = TempRepo::new(&self.repo)?;
-            let result = f(&temp_repo);
-            *cache = Some(temp_repo);
-            result
+const GIT_OBJECT_DIRECTORY: &str = "GIT_OBJECT_DIRECTORY";
+const GIT_ALTERNATE_OBJECT_DIRECTORIES: &str = "GIT_ALTERNATE_OBJECT_DIRECTORIES";
+const GIT_QUARANTINE_PATH: &str = "GIT_QUARANTINE_PATH";
+
+// While a pre-receive hook is running, Git keeps the pushed objects
+// in a quarantine directory, and only tells the hook about it through
+// environment variables. Git subprocesses inherit these, but libgit2
+// needs them to be added as alternates.
+fn quarantine_object_directories() -> Vec<PathBuf> {
+    let mut object_dirs: Vec<PathBuf> = Vec::new();
+    for var in &[GIT_QUARANTINE_PATH, GIT_OBJECT_DIRECTORY] {
+        if let Some(dir) = std::env::var_os(var) {
+            object_dirs.push(dir.into());
         }
     }
-}
-
-struct TempRepo {
-    repo: Repository,
-}
+    if let Some(dirs) = std::env::var_os(GIT_ALTERNATE_OBJECT_DIRECTORIES) {
+        object_dirs.extend(std::env::split_paths(&dirs));
+    }
 
-impl TempRepo {
-    fn new(src_repo: &Repository) -> Result<TempRepo, Box<dyn Error>> {
-        let max_attempts = 20;
-        let tmp_dir = std::env::temp_dir();
-
-        for attempt in 1..=max_attempts {
-            let tmp_repo_path = tmp_dir.join(format!("capn_tmp_{}.git", Uuid::new_v4()));
-            match std::fs::create_dir(&tmp_repo_path) {
-                Err(ref e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
-                Err(e) => return Err(Box::new(e)),
-                Ok(_) => {
-                    trace!(
-                        "Created temp repo for verification after {} attempts: {}",
-                        attempt,
-                        tmp_repo_path.display()
-                    );
-                    let src_path = src_repo.path().to_str().ok_or(Box::new(CapnError::new(
-                        "Path to the repo being verified was not valid UTF-8",
-                    )))?;
-
-                    let result = Command::new("git")
-                        .arg("clone")
-                        .arg("--bare")
-                        .arg("--shared")
-                        .arg(src_path)
-                        .arg(&tmp_repo_path)
-                        .output()?;
-
-                    return if result.status.success() {
-                        Ok(TempRepo {
-                            repo: Repository::open(tmp_repo_path)?,
-                        })
-                    } else {
-                        debug!("Git clone Stderr: {:?}", String::from_utf8(result.stderr));
-                        Err(Box::new(CapnError::new(format!(
-                            "Call to git clone while creating temp repo failed with code {:?}",
-                            result.status.code()
-                        ))))
-                    };
-                }
-            };
+    let mut unique_dirs = Vec::new();
+    for dir in object_dirs {
+        if dir.is_dir() && !unique_dirs.contains(&dir) {
+            unique_dirs.push(dir);
         }
-
-        Err(Box::new(CapnError::new(String::from(
-            "Max attempts exceeded looking for a new temp repo location",
-        ))))
     }
+    unique_dirs
 }
 
-impl Drop for TempRepo {
-    fn drop(&mut self) {
-        trace!("Cleaning up temp repo: {}", self.repo.path().display());
-        let drop_result = std::fs::remove_dir_all(&self.repo.path());
-        if let Err(e) = drop_result {
-            warn!("Failed to clean up temp repo: {}", e);
-        }
-    }
+/// A path, then each of the directories that it's in from the
+/// deepest up, as both the `Path` to look it up in a tree by and the
+/// bytes that Git stores.
+fn path_with_directories<'a>(
+    path: &'a Path,
+    path_bytes: &'a [u8],
+) -> impl Iterator<Item = (&'a Path, &'a [u8])> {
+    let directories = path_bytes
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, byte)| **byte == b'/')
+        .map(move |(i, _)| &path_bytes[..i]);
+    path.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .zip(std::iter::once(path_bytes).chain(directories))
 }
 
 #[cfg(test)]
@@ -557,15 +1241,15 @@ mod test {
     fn valid_mainlines(mainlines: &[String]) -> bool {
         mainlines
             .iter()
-            .all(|mainline| !mainline.contains('\u{0}') && Reference::is_valid_name(&mainline))
+            .all(|mainline| !mainline.contains('\u{0}') && Reference::is_valid_name(mainline))
     }
 
     #[test]
     fn is_mainline_with_default_config_only_identifies_head_branch() {
         let project_root = env!("CARGO_MANIFEST_DIR");
         let git = LiveGit::default(format!("{}/tests/test-repo.git", project_root)).unwrap();
-        assert_eq!(git.is_mainline("refs/heads/master").unwrap(), true);
-        assert_eq!(git.is_mainline("refs/heads/tagged-branch").unwrap(), false);
+        assert!(git.is_mainline("refs/heads/master").unwrap());
+        assert!(!git.is_mainline("refs/heads/tagged-branch").unwrap());
     }
 
     #[test]
@@ -578,8 +1262,8 @@ mod test {
             },
         )
         .unwrap();
-        assert_eq!(git.is_mainline("refs/heads/master").unwrap(), false);
-        assert_eq!(git.is_mainline("refs/heads/tagged-branch").unwrap(), true);
+        assert!(!git.is_mainline("refs/heads/master").unwrap());
+        assert!(git.is_mainline("refs/heads/tagged-branch").unwrap());
     }
 
     #[test]
@@ -592,8 +1276,8 @@ mod test {
             },
         )
         .unwrap();
-        assert_eq!(git.is_mainline("refs/heads/master").unwrap(), false);
-        assert_eq!(git.is_mainline("refs/heads/tagged-branch").unwrap(), true);
+        assert!(!git.is_mainline("refs/heads/master").unwrap());
+        assert!(git.is_mainline("refs/heads/tagged-branch").unwrap());
     }
 
     #[quickcheck]
@@ -619,8 +1303,8 @@ mod test {
             },
         )
         .unwrap();
-        assert_eq!(git.is_mainline("refs/heads/master").unwrap(), true);
-        assert_eq!(git.is_mainline("refs/heads/tagged-branch").unwrap(), true);
+        assert!(git.is_mainline("refs/heads/master").unwrap());
+        assert!(git.is_mainline("refs/heads/tagged-branch").unwrap());
     }
 
     #[quickcheck]
@@ -771,4 +1455,61 @@ mod test {
             assert_eq!(commits.len(), 2)
         }
     }
+
+    mod trailers {
+        use super::*;
+
+        fn commit_with_message(message: &str) -> Commit {
+            Commit {
+                id: Oid::zero(),
+                author_name: None,
+                author_email: None,
+                committer_name: None,
+                committer_email: None,
+                raw_author: None,
+                raw_committer: None,
+                author_time: 0,
+                committer_time: 0,
+                is_identical_tree_to_any_parent: false,
+                is_merge_commit: false,
+                tags: Vec::new(),
+                parents: Vec::new(),
+                message: message.to_string(),
+            }
+        }
+
+        #[test]
+        fn finds_trailers_in_the_last_paragraph() {
+            let commit = commit_with_message(
+                "Subject\n\nBody: not a trailer\n\nSigned-off-by: A <a@example.com>\nReviewed-by: B\n  <b@example.com>\n",
+            );
+            assert_eq!(
+                commit.trailers(),
+                vec![
+                    Trailer {
+                        key: "Signed-off-by".into(),
+                        value: "A <a@example.com>".into()
+                    },
+                    Trailer {
+                        key: "Reviewed-by".into(),
+                        value: "B <b@example.com>".into()
+                    }
+                ]
+            );
+        }
+
+        #[test]
+        fn subject_is_never_a_trailer() {
+            let commit = commit_with_message("fix: something\n");
+            assert!(commit.trailers().is_empty());
+        }
+
+        #[test]
+        fn last_paragraph_with_prose_has_no_trailers() {
+            let commit = commit_with_message(
+                "Subject\n\nSigned-off-by: A <a@example.com>\nThanks for the review\n",
+            );
+            assert!(commit.trailers().is_empty());
+        }
+    }
 }
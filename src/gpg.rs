@@ -1,9 +1,11 @@
-use crate::error::CapnError;
+use crate::error::{CapnError, OutOfTime};
 use crate::keyring::Keyring;
 use std::collections::HashSet;
 use std::error::Error;
+use std::io::prelude::*;
 use std::process::*;
-use std::time::Instant;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use log::*;
 use rayon::prelude::*;
@@ -14,6 +16,128 @@ pub trait Gpg {
         keyring: &mut Keyring,
         emails: &HashSet<&str>,
     ) -> Result<(), Box<dyn Error>>;
+
+    /// Receives keys like `receive_keys`, but gives up with `OutOfTime`
+    /// if the fetch is still running at the deadline.
+    fn receive_keys_before(
+        &self,
+        keyring: &mut Keyring,
+        emails: &HashSet<&str>,
+        deadline: Option<Instant>,
+    ) -> Result<(), Box<dyn Error>> {
+        OutOfTime::check(deadline, 0)?;
+        self.receive_keys(keyring, emails)
+    }
+
+    /// When the key or subkey with this fingerprint is valid. Returns
+    /// `None` if the key isn't in the local keyring, so its public key
+    /// needs to have been received already.
+    fn key_validity(&self, fingerprint: &str) -> Result<Option<KeyValidity>, Box<dyn Error>>;
+
+    /// An ASCII armored detached signature of `contents`, made with
+    /// the secret key for `signing_key` in the local keyring.
+    fn sign(&self, contents: &str, signing_key: &str) -> Result<String, Box<dyn Error>>;
+}
+
+impl<P: Gpg> Gpg for &P {
+    fn receive_keys(
+        &self,
+        keyring: &mut Keyring,
+        emails: &HashSet<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        (*self).receive_keys(keyring, emails)
+    }
+
+    fn receive_keys_before(
+        &self,
+        keyring: &mut Keyring,
+        emails: &HashSet<&str>,
+        deadline: Option<Instant>,
+    ) -> Result<(), Box<dyn Error>> {
+        (*self).receive_keys_before(keyring, emails, deadline)
+    }
+
+    fn key_validity(&self, fingerprint: &str) -> Result<Option<KeyValidity>, Box<dyn Error>> {
+        (*self).key_validity(fingerprint)
+    }
+
+    fn sign(&self, contents: &str, signing_key: &str) -> Result<String, Box<dyn Error>> {
+        (*self).sign(contents, signing_key)
+    }
+}
+
+/// Passes a deadline to every key fetch, so that code which only knows
+/// about `receive_keys` still stops at the deadline.
+pub struct GpgWithDeadline<'a, P: Gpg> {
+    pub gpg: &'a P,
+    pub deadline: Option<Instant>,
+}
+
+impl<P: Gpg> Gpg for GpgWithDeadline<'_, P> {
+    fn receive_keys(
+        &self,
+        keyring: &mut Keyring,
+        emails: &HashSet<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.gpg.receive_keys_before(keyring, emails, self.deadline)
+    }
+
+    fn key_validity(&self, fingerprint: &str) -> Result<Option<KeyValidity>, Box<dyn Error>> {
+        self.gpg.key_validity(fingerprint)
+    }
+
+    fn sign(&self, contents: &str, signing_key: &str) -> Result<String, Box<dyn Error>> {
+        self.gpg.sign(contents, signing_key)
+    }
+}
+
+/// When a key in the local keyring can be used, in seconds since the
+/// Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyValidity {
+    pub created: i64,
+    pub expires: Option<i64>,
+}
+
+impl KeyValidity {
+    pub fn contains(&self, time: i64) -> bool {
+        time >= self.created && self.expires.is_none_or(|expires| time < expires)
+    }
+}
+
+// Looks up when the key or subkey with this fingerprint is valid, from
+// the local keyring. Each `pub` or `sub` record in the listing is
+// followed by an `fpr` record with its fingerprint.
+fn local_key_validity(fingerprint: &str) -> Result<Option<KeyValidity>, Box<dyn Error>> {
+    let result = Command::new("gpg")
+        .args(["--with-colons", "--fixed-list-mode", "--list-keys"])
+        .arg(fingerprint)
+        .output()?;
+    if !result.status.success() {
+        debug!("GPG Stderr: {:?}", String::from_utf8(result.stderr));
+        return Ok(None);
+    }
+
+    let listing = String::from_utf8(result.stdout)?;
+    let mut key = None;
+    for fields in listing
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+    {
+        match fields.first() {
+            Some(&"pub") | Some(&"sub") => key = Some(fields),
+            Some(&"fpr") if fields.get(9) == Some(&fingerprint) => {
+                return Ok(key.and_then(|fields| {
+                    let created = fields.get(5)?.parse().ok()?;
+                    let expires = fields.get(6).and_then(|expires| expires.parse().ok());
+                    Some(KeyValidity { created, expires })
+                }));
+            }
+            Some(&"fpr") => key = None,
+            _ => {}
+        }
+    }
+    Ok(None)
 }
 
 pub struct LiveGpg {
@@ -26,6 +150,50 @@ impl Gpg for LiveGpg {
         &self,
         keyring: &mut Keyring,
         emails: &HashSet<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.receive_keys_before(keyring, emails, None)
+    }
+
+    fn key_validity(&self, fingerprint: &str) -> Result<Option<KeyValidity>, Box<dyn Error>> {
+        local_key_validity(fingerprint)
+    }
+
+    fn sign(&self, contents: &str, signing_key: &str) -> Result<String, Box<dyn Error>> {
+        let mut child = Command::new("gpg")
+            .args([
+                "--batch",
+                "--armor",
+                "--detach-sign",
+                "--local-user",
+                signing_key,
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| CapnError::new("Failed to open stdin for GPG"))?
+            .write_all(contents.as_bytes())?;
+        let result = child.wait_with_output()?;
+        if result.status.success() {
+            Ok(String::from_utf8(result.stdout)?)
+        } else {
+            debug!("GPG Stderr: {:?}", String::from_utf8(result.stderr));
+            Err(Box::new(CapnError::new(format!(
+                "Signing with GPG key {} failed with code {:?}",
+                signing_key,
+                result.status.code()
+            ))))
+        }
+    }
+
+    fn receive_keys_before(
+        &self,
+        keyring: &mut Keyring,
+        emails: &HashSet<&str>,
+        deadline: Option<Instant>,
     ) -> Result<(), Box<dyn Error>> {
         let start = Instant::now();
 
@@ -36,16 +204,21 @@ impl Gpg for LiveGpg {
             .collect();
 
         let fetch_result = if self.parallel_fetch {
-            if fingerprints
+            let results = fingerprints
                 .par_iter()
-                .all(|fp| match self.receive_key(fp) {
-                    Ok(_) => true,
-                    Err(e) => {
-                        error!("Error receiving key for {} : {}", fp, e);
-                        false
-                    }
+                .map(|fp| {
+                    self.receive_key(fp, deadline)
+                        .map_err(|e| (fp, e.to_string()))
                 })
-            {
+                .collect::<Vec<_>>();
+            OutOfTime::check(deadline, 0)?;
+            if results.iter().all(|result| match result {
+                Ok(_) => true,
+                Err((fp, e)) => {
+                    error!("Error receiving key for {} : {}", fp, e);
+                    false
+                }
+            }) {
                 Ok(())
             } else {
                 Err(Box::new(CapnError::new(
@@ -53,11 +226,13 @@ impl Gpg for LiveGpg {
                 )))
             }
         } else {
-            let result = Command::new("gpg")
-                .args(&["--keyserver", &self.keyserver])
-                .arg("--recv-keys")
-                .args(fingerprints)
-                .output()?;
+            let result = output_before(
+                Command::new("gpg")
+                    .args(["--keyserver", &self.keyserver])
+                    .arg("--recv-keys")
+                    .args(fingerprints),
+                deadline,
+            )?;
 
             if result.status.success() {
                 Ok(())
@@ -70,7 +245,7 @@ impl Gpg for LiveGpg {
             }
         };
 
-        if let Ok(_) = fetch_result {
+        if fetch_result.is_ok() {
             keyring.mark_public_keys_available(emails);
         }
 
@@ -85,14 +260,20 @@ impl Gpg for LiveGpg {
 }
 
 impl LiveGpg {
-    fn receive_key(&self, fingerprint: &str) -> Result<(), Box<dyn Error>> {
+    fn receive_key(
+        &self,
+        fingerprint: &str,
+        deadline: Option<Instant>,
+    ) -> Result<(), Box<dyn Error>> {
         debug!("Receiving key for fingerprint {:?}", fingerprint);
 
-        let result = Command::new("gpg")
-            .args(&["--keyserver", &self.keyserver])
-            .arg("--recv-keys")
-            .arg(fingerprint)
-            .output()?;
+        let result = output_before(
+            Command::new("gpg")
+                .args(["--keyserver", &self.keyserver])
+                .arg("--recv-keys")
+                .arg(fingerprint),
+            deadline,
+        )?;
 
         if result.status.success() {
             Ok(())
@@ -106,9 +287,45 @@ impl LiveGpg {
     }
 }
 
+// Runs the command like `Command::output`, but kills it if it's still
+// running at the deadline. Keyservers that don't answer would
+// otherwise hold up the push until gpg's own timeout.
+fn output_before(
+    command: &mut Command,
+    deadline: Option<Instant>,
+) -> Result<Output, Box<dyn Error>> {
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => return Ok(command.output()?),
+    };
+    OutOfTime::check(Some(deadline), 0)?;
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    loop {
+        if child.try_wait()?.is_some() {
+            return Ok(child.wait_with_output()?);
+        }
+        if Instant::now() >= deadline {
+            warn!("Killing gpg, the time budget ran out while receiving keys");
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Box::new(OutOfTime {
+                commits_verified: 0,
+            }));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
 pub mod test {
     use super::*;
 
+    /// Treats every key as received, and every key as valid from the
+    /// Unix epoch onwards, without looking at the local keyring. It
+    /// has no secret keys, so it can't sign.
     pub struct MockGpg;
     impl Gpg for MockGpg {
         fn receive_keys(
@@ -119,5 +336,39 @@ pub mod test {
             keyring.mark_public_keys_available(emails);
             Ok(())
         }
+
+        fn key_validity(&self, _fingerprint: &str) -> Result<Option<KeyValidity>, Box<dyn Error>> {
+            Ok(Some(KeyValidity {
+                created: 0,
+                expires: None,
+            }))
+        }
+
+        fn sign(&self, _contents: &str, signing_key: &str) -> Result<String, Box<dyn Error>> {
+            Err(Box::new(CapnError::new(format!(
+                "MockGpg has no secret key for {}",
+                signing_key
+            ))))
+        }
+    }
+
+    /// Like `MockGpg`, but every key has the same validity.
+    pub struct MockGpgWithKeyValidity(pub KeyValidity);
+    impl Gpg for MockGpgWithKeyValidity {
+        fn receive_keys(
+            &self,
+            keyring: &mut Keyring,
+            emails: &HashSet<&str>,
+        ) -> Result<(), Box<dyn Error>> {
+            MockGpg.receive_keys(keyring, emails)
+        }
+
+        fn key_validity(&self, _fingerprint: &str) -> Result<Option<KeyValidity>, Box<dyn Error>> {
+            Ok(Some(self.0))
+        }
+
+        fn sign(&self, contents: &str, signing_key: &str) -> Result<String, Box<dyn Error>> {
+            MockGpg.sign(contents, signing_key)
+        }
     }
 }
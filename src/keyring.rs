@@ -8,30 +8,35 @@ pub struct Fingerprint {
     pub id: String,
     pub name: String,
     pub email: String,
+    pub groups: Vec<String>,
     pub public_key_is_available_locally: bool,
 }
 
 impl Keyring {
+    /// Reads a Team Fingerprints file, a CSV file with the format GPG
+    /// Fingerprint, Name, Email, and optionally the space separated
+    /// groups that the person belongs to.
     pub fn from_team_fingerprints_file(file_contents: String) -> Keyring {
         let fingerprints: HashMap<String, Fingerprint> = file_contents
             .split('\n')
             .filter_map(|l| {
                 let line: Vec<&str> = l.split(',').collect();
-                match &line[..] {
-                    [fingerprint, name, email] => {
-                        let fingerprint = fingerprint.replace(char::is_whitespace, "");
-                        Some((
-                            email.to_string(),
-                            Fingerprint {
-                                id: fingerprint,
-                                name: name.to_string(),
-                                email: email.to_string(),
-                                public_key_is_available_locally: false,
-                            },
-                        ))
-                    }
-                    _ => None,
-                }
+                let (fingerprint, name, email, groups) = match &line[..] {
+                    [fingerprint, name, email] => (fingerprint, name, email, ""),
+                    [fingerprint, name, email, groups] => (fingerprint, name, email, *groups),
+                    _ => return None,
+                };
+                let fingerprint = fingerprint.replace(char::is_whitespace, "");
+                Some((
+                    email.to_string(),
+                    Fingerprint {
+                        id: fingerprint,
+                        name: name.to_string(),
+                        email: email.to_string(),
+                        groups: groups.split_whitespace().map(|g| g.to_string()).collect(),
+                        public_key_is_available_locally: false,
+                    },
+                ))
             })
             .collect();
         Keyring { fingerprints }
@@ -41,6 +46,33 @@ impl Keyring {
         self.fingerprints.get(email).map(|f| f.id.clone())
     }
 
+    pub fn email_from_fingerprint_id(&self, fingerprint_id: &str) -> Option<&str> {
+        self.fingerprints
+            .values()
+            .find(|f| f.id == fingerprint_id)
+            .map(|f| f.email.as_str())
+    }
+
+    pub fn name_from_email(&self, email: &str) -> Option<&str> {
+        self.fingerprints.get(email).map(|f| f.name.as_str())
+    }
+
+    pub fn contains_email_ignoring_case(&self, email: &str) -> bool {
+        self.fingerprints
+            .keys()
+            .any(|e| e.eq_ignore_ascii_case(email))
+    }
+
+    /// Email addresses are matched ignoring case, like
+    /// `contains_email_ignoring_case`, since the same address can be
+    /// written differently in commits, tags and CODEOWNERS.
+    pub fn is_in_group(&self, email: &str, group: &str) -> bool {
+        self.fingerprints
+            .values()
+            .filter(|f| f.email.eq_ignore_ascii_case(email))
+            .any(|f| f.groups.iter().any(|g| g == group))
+    }
+
     pub fn requires_public_key_download(&self, email: &str) -> bool {
         self.fingerprints
             .get(email)
@@ -50,9 +82,9 @@ impl Keyring {
 
     pub fn mark_public_keys_available(&mut self, emails: &HashSet<&str>) {
         for email in emails {
-            self.fingerprints
-                .get_mut(&email.to_string())
-                .map(|f| f.public_key_is_available_locally = true);
+            if let Some(f) = self.fingerprints.get_mut(*email) {
+                f.public_key_is_available_locally = true;
+            }
         }
     }
 }
@@ -9,9 +9,14 @@ use std::path::PathBuf;
 use structopt::StructOpt;
 
 use crate::config::Config;
+use crate::error::CapnError;
 use crate::fs::Fs;
 use crate::git::Git;
 use crate::gpg::Gpg;
+use crate::policies::audit::{
+    read_audit_record, write_audit_notes, AuditRecord, PendingAuditNotes,
+};
+use crate::policies::registry::{Hook, PolicyRegistry, RefUpdateContext};
 use crate::policies::{policy_result::PolicyResult, *};
 use crate::reference_update::ReferenceUpdate;
 
@@ -22,8 +27,10 @@ pub mod git;
 pub mod gpg;
 pub mod keyring;
 pub mod logger;
+pub mod mailmap;
 pub mod policies;
 pub mod reference_update;
+pub mod server;
 
 #[derive(Debug, StructOpt)]
 pub struct PrepareCommitMsg {
@@ -48,6 +55,13 @@ pub struct PrePush {
     pub remote_location: String,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct AuditShow {
+    /// The commit to show the audit note for
+    #[structopt()]
+    pub commit: String,
+}
+
 pub fn prepare_commit_msg<F: Fs, G: Git>(
     git: &G,
     opt: PrepareCommitMsg,
@@ -68,7 +82,35 @@ pub fn prepare_commit_msg<F: Fs, G: Git>(
     }
 }
 
-pub fn pre_push<G: Git, P: Gpg>(
+#[allow(clippy::too_many_arguments)]
+pub fn pre_push<G: Git + 'static, P: Gpg + 'static>(
+    git: &G,
+    gpg: P,
+    opt: &PrePush,
+    config: &Config,
+    local_ref: &str,
+    local_sha: &str,
+    remote_ref: &str,
+    remote_sha: &str,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    pre_push_with_registry(
+        &PolicyRegistry::with_builtin_policies(),
+        git,
+        gpg,
+        opt,
+        config,
+        local_ref,
+        local_sha,
+        remote_ref,
+        remote_sha,
+    )
+}
+
+/// Checks a pushed ref like `pre_push`, but with the policies in
+/// `registry` instead of the builtin ones.
+#[allow(clippy::too_many_arguments)]
+pub fn pre_push_with_registry<G: Git, P: Gpg>(
+    registry: &PolicyRegistry<G, P>,
     git: &G,
     gpg: P,
     _opt: &PrePush,
@@ -80,32 +122,84 @@ pub fn pre_push<G: Git, P: Gpg>(
 ) -> Result<PolicyResult, Box<dyn Error>> {
     let ref_update = ReferenceUpdate::from_git_hook_format(remote_sha, local_sha, local_ref)?;
 
-    vec![config
-        .verify_git_commits
-        .as_ref()
-        .map(|c| verify_git_commits::<G, P>(git, gpg, c, &ref_update))]
-    .into_iter()
-    .flatten()
-    .collect()
+    registry.check(&RefUpdateContext {
+        hook: Hook::PrePush,
+        git,
+        gpg: &gpg,
+        config,
+        ref_update: &ref_update,
+        audit_notes: None,
+    })
+}
+
+/// Checks a single received ref, and saves its audit notes for
+/// `post_receive` if it's accepted. Pushes of several refs should use
+/// `pre_receive_with_registry`, so that no notes are saved unless
+/// every ref is accepted.
+pub fn pre_receive<G: Git + 'static, P: Gpg + 'static>(
+    git: &G,
+    gpg: P,
+    config: &Config,
+    old_value: &str,
+    new_value: &str,
+    ref_name: &str,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    let audit_notes = PendingAuditNotes::default();
+    let result = pre_receive_with_registry(
+        &PolicyRegistry::with_builtin_policies(),
+        git,
+        gpg,
+        config,
+        old_value,
+        new_value,
+        ref_name,
+        &audit_notes,
+    )?;
+    if result.is_ok() {
+        audit_notes.save(git)?;
+    }
+    Ok(result)
 }
 
-pub fn pre_receive<G: Git, P: Gpg>(
+/// Checks a received ref like `pre_receive`, but with the policies in
+/// `registry` instead of the builtin ones. Audit notes are added to
+/// `audit_notes` rather than saved, so the caller needs to save them
+/// once every ref in the push has been accepted.
+#[allow(clippy::too_many_arguments)]
+pub fn pre_receive_with_registry<G: Git, P: Gpg>(
+    registry: &PolicyRegistry<G, P>,
     git: &G,
     gpg: P,
     config: &Config,
     old_value: &str,
     new_value: &str,
     ref_name: &str,
+    audit_notes: &PendingAuditNotes,
 ) -> Result<PolicyResult, Box<dyn Error>> {
     let ref_update = ReferenceUpdate::from_git_hook_format(old_value, new_value, ref_name)?;
 
-    vec![config
-        .verify_git_commits
-        .as_ref()
-        .map(|c| verify_git_commits::<G, P>(git, gpg, c, &ref_update))]
-    .into_iter()
-    .flatten()
-    .collect()
+    registry.check(&RefUpdateContext {
+        hook: Hook::PreReceive,
+        git,
+        gpg: &gpg,
+        config,
+        ref_update: &ref_update,
+        audit_notes: Some(audit_notes),
+    })
+}
+
+/// Writes the audit notes that pre-receive saved for a ref, once Git
+/// has updated it. Git only passes post-receive the updates that it
+/// applied, so refs that failed to update don't get notes.
+pub fn post_receive<G: Git, P: Gpg>(
+    git: &G,
+    gpg: P,
+    old_value: &str,
+    new_value: &str,
+    ref_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let ref_update = ReferenceUpdate::from_git_hook_format(old_value, new_value, ref_name)?;
+    write_audit_notes(git, &gpg, &ref_update)
 }
 
 pub fn install_hooks<G: Git>(git: &G) -> Result<(), Box<dyn Error>> {
@@ -125,3 +219,10 @@ capn pre-push "$@"
     )?;
     Ok(())
 }
+
+pub fn audit_show<G: Git>(git: &G, opt: &AuditShow) -> Result<AuditRecord, Box<dyn Error>> {
+    let commit_id = git.resolve_commit(&opt.commit)?;
+    read_audit_record(git, commit_id)?.ok_or_else(|| {
+        CapnError::new(format!("No audit note found for commit {}", commit_id)).into()
+    })
+}
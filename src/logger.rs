@@ -49,7 +49,7 @@ impl Log for Logger {
 
         eprintln!("{}", record.args());
 
-        self.tcp_stream.as_ref().map(|ref stream| {
+        if let Some(stream) = self.tcp_stream.as_ref() {
             stream
                 .lock()
                 .map_err(|e| e.to_string())
@@ -66,7 +66,7 @@ impl Log for Logger {
                     writeln!(stream, "{}", message_json).map_err(|e| e.to_string())
                 })
                 .unwrap_or_else(|e| eprintln!("Error: Failed to log over TCP - {}", e));
-        });
+        }
     }
 
     fn flush(&self) {
@@ -74,13 +74,13 @@ impl Log for Logger {
             .flush()
             .unwrap_or_else(|e| eprintln!("Error: Failed to flush StdErr logging stream - {}", e));
 
-        self.tcp_stream.as_ref().map(|ref stream| {
+        if let Some(stream) = self.tcp_stream.as_ref() {
             stream
                 .lock()
                 .map_err(|e| e.to_string())
                 .and_then(|mut stream| stream.flush().map_err(|e| e.to_string()))
                 .unwrap_or_else(|e| eprintln!("Error: Failed to flush TCP logging stream - {}", e));
-        });
+        }
     }
 }
 
@@ -110,7 +110,7 @@ impl Logger {
                 }
                 connect_result.ok()
             })
-            .map(|stream| Mutex::new(stream));
+            .map(Mutex::new);
 
         let log_set_result = log::set_boxed_logger(Box::new(Logger {
             tcp_stream,
@@ -0,0 +1,207 @@
+use crate::git::Commit;
+
+use std::fmt;
+
+/// The canonical names and emails from a `.mailmap` file. Each line
+/// maps a name and email written in commits to the ones that should be
+/// used instead, in one of these forms:
+///
+/// ```text
+/// Proper Name <commit@email>
+/// <proper@email> <commit@email>
+/// Proper Name <proper@email> <commit@email>
+/// Proper Name <proper@email> Commit Name <commit@email>
+/// ```
+///
+/// Like Git, commit names and emails are matched ignoring case, and
+/// later lines win over earlier ones.
+pub struct Mailmap {
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    proper_name: Option<String>,
+    proper_email: Option<String>,
+    commit_name: Option<String>,
+    commit_email: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}>",
+            self.name.as_deref().unwrap_or_default(),
+            self.email.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+impl Mailmap {
+    pub fn from_mailmap_file(file_contents: &str) -> Mailmap {
+        let entries = file_contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut pairs = Vec::new();
+                let mut rest = line;
+                while let Some((name, after_name)) = rest.split_once('<') {
+                    let (email, after_email) = after_name.split_once('>')?;
+                    pairs.push((non_empty(name), non_empty(email)));
+                    rest = after_email;
+                }
+                match &pairs[..] {
+                    [(proper_name, Some(commit_email))] => Some(Entry {
+                        proper_name: proper_name.clone(),
+                        proper_email: None,
+                        commit_name: None,
+                        commit_email: commit_email.clone(),
+                    }),
+                    [(proper_name, proper_email), (commit_name, Some(commit_email)), ..] => {
+                        Some(Entry {
+                            proper_name: proper_name.clone(),
+                            proper_email: proper_email.clone(),
+                            commit_name: commit_name.clone(),
+                            commit_email: commit_email.clone(),
+                        })
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        Mailmap { entries }
+    }
+
+    pub fn canonical(&self, identity: &Identity) -> Identity {
+        let email = match &identity.email {
+            Some(email) => email,
+            None => return identity.clone(),
+        };
+        let matches_name = |entry: &&Entry| match (&entry.commit_name, &identity.name) {
+            (None, _) => true,
+            (Some(commit_name), Some(name)) => commit_name.eq_ignore_ascii_case(name),
+            (Some(_), None) => false,
+        };
+        let entries = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.commit_email.eq_ignore_ascii_case(email));
+        let entry = entries
+            .clone()
+            .find(|entry| entry.commit_name.is_some() && matches_name(entry))
+            .or_else(|| entries.clone().find(|entry| entry.commit_name.is_none()));
+        match entry {
+            Some(entry) => Identity {
+                name: entry.proper_name.clone().or_else(|| identity.name.clone()),
+                email: entry
+                    .proper_email
+                    .clone()
+                    .or_else(|| identity.email.clone()),
+            },
+            None => identity.clone(),
+        }
+    }
+
+    /// Replaces the author, committer and taggers of the commit with
+    /// their canonical identities. The identities that were replaced
+    /// are kept on the commit, so that they can be reported.
+    pub fn apply(&self, mut commit: Commit) -> Commit {
+        let author = commit.author();
+        let canonical_author = self.canonical(&author);
+        if canonical_author != author {
+            commit.raw_author = Some(author);
+        }
+        commit.author_name = canonical_author.name;
+        commit.author_email = canonical_author.email;
+
+        let committer = commit.committer();
+        let canonical_committer = self.canonical(&committer);
+        if canonical_committer != committer {
+            commit.raw_committer = Some(committer);
+        }
+        commit.committer_name = canonical_committer.name;
+        commit.committer_email = canonical_committer.email;
+
+        for tag in &mut commit.tags {
+            tag.tagger_email = self
+                .canonical(&Identity {
+                    name: None,
+                    email: tag.tagger_email.take(),
+                })
+                .email;
+        }
+        commit
+    }
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    Some(s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn canonical(mailmap: &Mailmap, name: &str, email: &str) -> String {
+        mailmap
+            .canonical(&Identity {
+                name: Some(name.to_string()),
+                email: Some(email.to_string()),
+            })
+            .to_string()
+    }
+
+    #[test]
+    fn maps_names_and_emails() {
+        let mailmap = Mailmap::from_mailmap_file(
+            "# Comment\n\
+             Alice Smith <alice@old.com>\n\
+             <bob@jemstep.com> <Bob@Old.com>\n\
+             Carol Jones <carol@jemstep.com> <carol@old.com>\n\
+             Dan Brown <dan@jemstep.com> dan <dan@old.com>\n",
+        );
+        assert_eq!(
+            canonical(&mailmap, "alice", "alice@old.com"),
+            "Alice Smith <alice@old.com>"
+        );
+        assert_eq!(
+            canonical(&mailmap, "Bob", "bob@old.com"),
+            "Bob <bob@jemstep.com>"
+        );
+        assert_eq!(
+            canonical(&mailmap, "carol", "carol@old.com"),
+            "Carol Jones <carol@jemstep.com>"
+        );
+        assert_eq!(
+            canonical(&mailmap, "DAN", "dan@old.com"),
+            "Dan Brown <dan@jemstep.com>"
+        );
+        assert_eq!(
+            canonical(&mailmap, "Daniel", "dan@old.com"),
+            "Daniel <dan@old.com>"
+        );
+        assert_eq!(
+            canonical(&mailmap, "Eve", "eve@old.com"),
+            "Eve <eve@old.com>"
+        );
+    }
+
+    #[test]
+    fn later_lines_win() {
+        let mailmap = Mailmap::from_mailmap_file(
+            "<alice@first.com> <alice@old.com>\n<alice@second.com> <alice@old.com>\n",
+        );
+        assert_eq!(
+            canonical(&mailmap, "Alice", "alice@old.com"),
+            "Alice <alice@second.com>"
+        );
+    }
+}
@@ -8,7 +8,10 @@ use capn::git::{Git, LiveGit};
 use capn::gpg::{Gpg, LiveGpg};
 use capn::logger;
 use capn::logger::{Logger, LoggingOpt};
+use capn::policies::audit::PendingAuditNotes;
 use capn::policies::policy_result::PolicyResult;
+use capn::policies::registry::PolicyRegistry;
+use capn::server::{install_server_hooks, ServerInstall};
 use capn::*;
 
 use log::*;
@@ -42,9 +45,35 @@ enum Command {
     #[structopt(name = "pre-receive")]
     PreReceive,
 
+    /// Git hook called on the server after references have been updated
+    #[structopt(name = "post-receive")]
+    PostReceive,
+
     /// Installs the required Git Hooks in the current repo
     #[structopt(name = "install-hooks")]
     InstallHooks,
+
+    /// Commands for managing Captain Git Hook on a Git server
+    #[structopt(name = "server")]
+    Server(ServerCommand),
+
+    /// Commands for reading the audit notes written by the post-receive hook
+    #[structopt(name = "audit")]
+    Audit(AuditCommand),
+}
+
+#[derive(Debug, StructOpt)]
+enum ServerCommand {
+    /// Installs or updates the pre-receive hook in all bare repos under a directory
+    #[structopt(name = "install")]
+    Install(ServerInstall),
+}
+
+#[derive(Debug, StructOpt)]
+enum AuditCommand {
+    /// Shows why a commit was accepted, from its audit note
+    #[structopt(name = "show")]
+    Show(AuditShow),
 }
 
 // This function intentionally doesn't return 'error', it's meant to
@@ -55,6 +84,16 @@ fn main() {
     let quiet = opt.logging.quiet;
     Logger::init(opt.logging);
 
+    // Post-receive, server and audit commands don't check any commits,
+    // so they report their own results rather than accepting or
+    // rejecting.
+    let command = match opt.command {
+        Command::PostReceive => return exit_on_error(execute_post_receive()),
+        Command::Server(command) => return exit_on_error(execute_server_command(command)),
+        Command::Audit(command) => return exit_on_error(execute_audit_command(command)),
+        command => command,
+    };
+
     logger::print_header(
         format!(
             "Ahoy, maties! Welcome to Capn Githook {}!",
@@ -73,7 +112,7 @@ fn main() {
 
     debug!("Configuration: {:#?}\n", config);
 
-    match execute_command(opt.command, config) {
+    match execute_command(command, config) {
         Ok(PolicyResult::Ok) => {
             info!("Checks passed - commits accepted");
             logger::print_header("Aye, me hearties! Welcome aboard!", quiet);
@@ -91,6 +130,13 @@ fn main() {
     }
 }
 
+fn exit_on_error(result: Result<(), Box<dyn Error>>) {
+    if let Err(e) = result {
+        error!("Command failed - reason: {}", e);
+        exit(1);
+    }
+}
+
 fn load_config() -> Result<Config, Box<dyn Error>> {
     // This is a necessary bootstrapping step, because we need a Git
     // object to load the config, which is used to initialize the Git
@@ -109,7 +155,7 @@ fn execute_command(command: Command, config: Config) -> Result<PolicyResult, Box
         Command::PrePush(args) => {
             info!("Calling pre-push");
             stdin().lock().lines()
-                .map(|raw_line| raw_line.map(|line| {
+                .flat_map(|raw_line| raw_line.map(|line| {
                     let mut fields = line.split(' ');
                     match (fields.next(), fields.next(), fields.next(), fields.next()) {
                         (Some(local_ref), Some(local_sha), Some(remote_ref), Some(remote_sha)) => {
@@ -122,18 +168,19 @@ fn execute_command(command: Command, config: Config) -> Result<PolicyResult, Box
                         }
                     }
                 }))
-                .flatten()
                 .collect()
         }
         Command::PreReceive => {
             info!("Calling pre-receive");
-            stdin().lock().lines()
-                .map(|raw_line| raw_line.map(|line| {
+            let registry = PolicyRegistry::with_builtin_policies();
+            let audit_notes = PendingAuditNotes::default();
+            let result = stdin().lock().lines()
+                .flat_map(|raw_line| raw_line.map(|line| {
                     let mut fields = line.split(' ');
                     match (fields.next(), fields.next(), fields.next()) {
                         (Some(old_value), Some(new_value), Some(ref_name)) => {
                             info!("Running pre-receive for: {} {} {}", old_value, new_value, ref_name);
-                            pre_receive::<LiveGit, _>(&git, build_gpg_client(&config), &config, old_value, new_value, ref_name)
+                            pre_receive_with_registry(&registry, &git, build_gpg_client(&config), &config, old_value, new_value, ref_name, &audit_notes)
                         },
                         _ => {
                             warn!("Expected parameters not received on stdin. Line received was: {}", line);
@@ -141,10 +188,77 @@ fn execute_command(command: Command, config: Config) -> Result<PolicyResult, Box
                         }
                     }
                 }))
-                .flatten()
-                .collect()
+                .collect::<Result<PolicyResult, _>>()?;
+            // Notes are only saved once every ref has been accepted,
+            // and written by post-receive once the refs are updated
+            if result.is_ok() {
+                audit_notes.save(&git)?;
+            }
+            Ok(result)
         }
         Command::InstallHooks => install_hooks(&git).map(|_| PolicyResult::Ok),
+        Command::PostReceive | Command::Server(_) | Command::Audit(_) => {
+            unreachable!("Post-receive, server and audit commands run without a repo config")
+        }
+    }
+}
+
+fn execute_post_receive() -> Result<(), Box<dyn Error>> {
+    info!("Calling post-receive");
+    let git = LiveGit::default("./")?;
+    // Only used to sign audit notes, with a key from the local keyring
+    let gpg = LiveGpg {
+        parallel_fetch: false,
+        keyserver: String::new(),
+    };
+    for line in stdin().lock().lines() {
+        let line = line?;
+        let mut fields = line.split(' ');
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(old_value), Some(new_value), Some(ref_name)) => {
+                info!(
+                    "Running post-receive for: {} {} {}",
+                    old_value, new_value, ref_name
+                );
+                post_receive(&git, &gpg, old_value, new_value, ref_name)?;
+            }
+            _ => warn!(
+                "Expected parameters not received on stdin. Line received was: {}",
+                line
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn execute_server_command(command: ServerCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        ServerCommand::Install(args) => {
+            info!("Installing pre-receive hooks under {}", args.root.display());
+            let report = install_server_hooks(&args)?;
+            info!(
+                "Installed: {}, updated: {}, skipped: {}",
+                report.installed.len(),
+                report.updated.len(),
+                report.skipped_unmanaged_hook.len()
+            );
+            for repo in &report.missing_config {
+                warn!("Repo has no .capn config: {}", repo.display());
+            }
+            info!("Server hooks are up to date");
+            Ok(())
+        }
+    }
+}
+
+fn execute_audit_command(command: AuditCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        AuditCommand::Show(args) => {
+            let git = LiveGit::default("./")?;
+            let record = audit_show(&git, &args)?;
+            println!("{}", serde_json::to_string_pretty(&record)?);
+            Ok(())
+        }
     }
 }
 
@@ -159,6 +273,13 @@ fn build_gpg_client(config: &Config) -> impl Gpg {
             .verify_git_commits
             .as_ref()
             .map(|c| c.keyserver.clone())
+            .or_else(|| config.verify_tags.as_ref().map(|c| c.keyserver.clone()))
+            .or_else(|| {
+                config
+                    .verify_submodules_and_symlinks
+                    .as_ref()
+                    .map(|c| c.keyserver.clone())
+            })
             .unwrap_or("".to_string()),
     }
 }
@@ -1,20 +1,45 @@
+pub mod audit;
+mod code_owners;
+pub mod commit_checks;
+pub mod external;
+mod filenames;
+mod lfs;
 pub mod policy_result;
+pub mod registry;
+mod submodules;
+mod verification_cache;
+pub mod wasm;
 
-use crate::config::VerifyGitCommitsConfig;
+use crate::config::{
+    EmailDomains, LinearHistoryConfig, SignerRequirement, TimeBudgetOutcome, VerifyFileSizesConfig,
+    VerifyFilenamesConfig, VerifyGitCommitsConfig, VerifyParentCountConfig, VerifySignOffConfig,
+    VerifySubjectsConfig, VerifySubmodulesAndSymlinksConfig, VerifyTagsConfig,
+};
+use crate::error::OutOfTime;
 use crate::fs::*;
 use crate::git::*;
 use crate::gpg::*;
 use crate::keyring::*;
+use crate::mailmap::Mailmap;
 use crate::reference_update::ReferenceUpdate;
 
+use self::audit::{AuditTrail, PendingAuditNotes};
+use self::code_owners::{path_matchers, CodeOwners};
+use self::commit_checks::{CommitCheck, CommitCheckContext, CommitChecks};
+use self::filenames::{unicode_problem, windows_problem};
+use self::lfs::{is_lfs_pointer, LfsAttributes, MAX_POINTER_SIZE};
 use self::policy_result::PolicyResult;
+use self::submodules::{escapes_repo, parse_gitmodules};
+use self::verification_cache::VerificationCache;
 
 use git2::Oid;
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
 use rayon::prelude::*;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::error::Error;
+use std::iter;
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use log::*;
 
@@ -35,71 +60,616 @@ pub fn verify_git_commits<G: Git, P: Gpg>(
     config: &VerifyGitCommitsConfig,
     ref_update: &ReferenceUpdate,
 ) -> Result<PolicyResult, Box<dyn Error>> {
-    info!("Executing policy: verify_git_commits");
-    let start = Instant::now();
+    verify_git_commits_with_checks(
+        &CommitChecks::with_builtin_checks(),
+        git,
+        &gpg,
+        config,
+        ref_update,
+    )
+}
 
-    let mut policy_result = PolicyResult::Ok;
+/// Verifies the commits like `verify_git_commits`, but runs the given
+/// commit checks instead of the builtin ones.
+pub fn verify_git_commits_with_checks<G: Git, P: Gpg>(
+    commit_checks: &CommitChecks<G, P>,
+    git: &G,
+    gpg: &P,
+    config: &VerifyGitCommitsConfig,
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    verify_git_commits_with_audit_trail(
+        commit_checks,
+        git,
+        gpg,
+        config,
+        ref_update,
+        &mut AuditTrail::disabled(),
+    )
+}
+
+/// Verifies the commits like `verify_git_commits_with_checks`, and if
+/// the update is accepted and `audit_notes` is enabled, adds why each
+/// commit passed to `audit_notes`. This is meant for the server, which
+/// writes them as notes in `refs/notes/capn-audit` once the whole push
+/// has been accepted.
+pub fn verify_git_commits_with_audit_notes<G: Git, P: Gpg>(
+    commit_checks: &CommitChecks<G, P>,
+    git: &G,
+    gpg: &P,
+    config: &VerifyGitCommitsConfig,
+    ref_update: &ReferenceUpdate,
+    audit_notes: &PendingAuditNotes,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    let mut audit_trail = AuditTrail::new(git, config, ref_update)?;
+    let policy_result = verify_git_commits_with_audit_trail(
+        commit_checks,
+        git,
+        gpg,
+        config,
+        ref_update,
+        &mut audit_trail,
+    )?;
+    if policy_result.is_ok() {
+        audit_notes.add(audit_trail, config.audit_signing_key.clone());
+    }
+    Ok(policy_result)
+}
+
+/// Verifies pushed tags, which `verify_git_commits` skips. Updates to
+/// other refs always pass.
+pub fn verify_tags<G: Git, P: Gpg>(
+    git: &G,
+    gpg: P,
+    config: &VerifyTagsConfig,
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    info!("Executing policy: verify_tags");
+
+    let ref_name = ref_update.ref_name();
+    if !git.is_tag(ref_name)? {
+        debug!("Not a tag, no tags to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+    let tag_name = ref_name.trim_start_matches("refs/tags/").to_string();
+
+    let new_tag_id = match ref_update {
+        ReferenceUpdate::Delete { .. } if config.allow_deleting_tags => {
+            return Ok(PolicyResult::Ok)
+        }
+        ReferenceUpdate::Delete { .. } => return Ok(PolicyResult::TagDeleted(tag_name)),
+        ReferenceUpdate::Update { .. } if !config.allow_moving_tags => {
+            return Ok(PolicyResult::TagMoved(tag_name))
+        }
+        ReferenceUpdate::New { new_commit_id, .. }
+        | ReferenceUpdate::Update { new_commit_id, .. } => *new_commit_id,
+    };
+
+    if let Some(pattern) = &config.tag_name_pattern {
+        if !Glob::new(pattern)?.compile_matcher().is_match(&tag_name) {
+            return Ok(PolicyResult::InvalidTagName(tag_name, pattern.clone()));
+        }
+    }
+
+    let tag = match git.find_tag(new_tag_id)? {
+        Some(tag) => tag,
+        None => return Ok(PolicyResult::LightweightTag(tag_name)),
+    };
+
+    if config.verify_tag_signatures {
+        let team_fingerprints_file = git.read_file(&config.team_fingerprints_file)?;
+        let mut keyring = Keyring::from_team_fingerprints_file(team_fingerprints_file);
+        gpg.receive_keys(
+            &mut keyring,
+            &tag.tagger_email.iter().map(String::as_str).collect(),
+        )?;
+        let signers = verified_taggers::<G>(git.path(), iter::once(&tag), &keyring);
+        if signers.is_empty() {
+            return Ok(PolicyResult::UnsignedTag(tag_name));
+        }
+        if let Some(group) = &config.signer_group {
+            if !signers
+                .iter()
+                .any(|signer| keyring.is_in_group(signer, group))
+            {
+                return Ok(PolicyResult::TagSignerNotInGroup(tag_name, group.clone()));
+            }
+        }
+    }
+
+    if config.verify_reachable_from_mainline
+        && !git
+            .find_new_commits(&[], &[tag.target_id], &None)?
+            .is_empty()
+    {
+        return Ok(PolicyResult::TagNotOnMainline(tag_name, tag.target_id));
+    }
+
+    Ok(PolicyResult::Ok)
+}
+
+pub fn verify_file_sizes<G: Git>(
+    git: &G,
+    config: &VerifyFileSizesConfig,
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    info!("Executing policy: verify_file_sizes");
 
     if let ReferenceUpdate::Delete { .. } = ref_update {
-        debug!("Delete branch detected, no commits to verify.")
-    } else if git.is_tag(ref_update.ref_name())? {
-        debug!("Tag detected, no commits to verify.")
-    } else {
-        let all_commits = commits_to_verify(git, &ref_update, &config.override_tag_pattern)?;
+        debug!("Delete branch detected, no files to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+    if git.is_tag(ref_update.ref_name())? {
+        debug!("Tag detected, no files to verify.");
+        return Ok(PolicyResult::Ok);
+    }
 
-        debug!("Number of commits to verify {} : ", all_commits.len());
-        for commit in &all_commits {
-            debug!("{:?}", commit);
+    let lfs_required = path_matcher_list(&config.lfs_required)?;
+
+    for commit in commits_to_verify(git, ref_update, &None)? {
+        let blobs = git.added_blobs(commit.id)?;
+        let lfs_attributes = if config.verify_lfs_pointers {
+            read_lfs_attributes(git, commit.id, &blobs)?
+        } else {
+            LfsAttributes::default()
+        };
+
+        for blob in blobs {
+            if let Some(max_size) = config.max_file_size_bytes {
+                if blob.size as u64 > max_size {
+                    error!(
+                        "File size verification failed for {}: {} is {} bytes",
+                        commit.id, blob.path, blob.size
+                    );
+                    return Ok(PolicyResult::FileTooLarge(
+                        commit.id, blob.path, blob.size, max_size,
+                    ));
+                }
+            }
+
+            if lfs_attributes.is_tracked(&blob.path)
+                && (blob.size >= MAX_POINTER_SIZE || !is_lfs_pointer(&git.read_blob(blob.id)?))
+            {
+                error!(
+                    "File size verification failed for {}: {} is not an LFS pointer",
+                    commit.id, blob.path
+                );
+                return Ok(PolicyResult::InvalidLfsPointer(commit.id, blob.path));
+            }
+
+            // LFS pointers are text, so a binary file is never stored
+            // in Git LFS.
+            if blob.is_binary && lfs_required.iter().any(|m| m.is_match(&blob.path)) {
+                error!(
+                    "File size verification failed for {}: binary file {} is not in Git LFS",
+                    commit.id, blob.path
+                );
+                return Ok(PolicyResult::BinaryFileNotInLfs(commit.id, blob.path));
+            }
+        }
+    }
+
+    info!("File size verification passed");
+    Ok(PolicyResult::Ok)
+}
+
+// Files are tracked by the .gitattributes files in the same commit,
+// since that's what the Git LFS client on the pushing machine would
+// have used. Only the directories that have added blobs in them can
+// affect the check.
+fn read_lfs_attributes<G: Git>(
+    git: &G,
+    commit_id: Oid,
+    blobs: &[AddedBlob],
+) -> Result<LfsAttributes, Box<dyn Error>> {
+    let mut directories = blobs
+        .iter()
+        .flat_map(|blob| {
+            blob.path
+                .match_indices('/')
+                .map(move |(i, _)| &blob.path[..i])
+                .chain(std::iter::once(""))
+        })
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    directories.sort_by_key(|directory| {
+        (
+            directory.split('/').filter(|d| !d.is_empty()).count(),
+            *directory,
+        )
+    });
+
+    let mut attributes = LfsAttributes::default();
+    for directory in directories {
+        let path = if directory.is_empty() {
+            ".gitattributes".to_string()
+        } else {
+            format!("{}/.gitattributes", directory)
+        };
+        if let Some(contents) = git.read_file_at_commit(commit_id, &path)? {
+            attributes.add(directory, &String::from_utf8_lossy(&contents))?;
+        }
+    }
+    Ok(attributes)
+}
+
+pub fn verify_filenames<G: Git>(
+    git: &G,
+    config: &VerifyFilenamesConfig,
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    info!("Executing policy: verify_filenames");
+
+    if let ReferenceUpdate::Delete { .. } = ref_update {
+        debug!("Delete branch detected, no filenames to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+    if git.is_tag(ref_update.ref_name())? {
+        debug!("Tag detected, no filenames to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+
+    let deadline = config
+        .time_budget_ms
+        .map(|budget| Instant::now() + Duration::from_millis(budget));
+
+    // Commits are checked oldest first, so that problems are reported
+    // against the commit that introduced them. Each commit's paths are
+    // the ones in its first parent, with its changes applied, so the
+    // whole tree is only read again when the history branches.
+    let mut commits = commits_to_verify(git, ref_update, &None)?;
+    commits.reverse();
+    let mut paths_by_lowercase = PathsByLowercase::default();
+    let mut indexed_commit = None;
+    for (verified, commit) in commits.iter().enumerate() {
+        OutOfTime::check(deadline, verified)?;
+        let first_parent = commit.parents.first().copied();
+        if indexed_commit != first_parent {
+            paths_by_lowercase = PathsByLowercase::default();
+            if let Some(first_parent) = first_parent {
+                for path in git.tree_paths(first_parent)? {
+                    paths_by_lowercase.insert(path);
+                }
+            }
+        }
+        indexed_commit = Some(commit.id);
+
+        let changes = git.path_changes(commit.id)?;
+        for path in &changes.removed {
+            paths_by_lowercase.remove(path);
+        }
+        for path in &changes.added {
+            paths_by_lowercase.insert(path.clone());
+        }
+
+        let introduced = changes
+            .added
+            .iter()
+            .filter(|path| !changes.merged.contains(*path));
+        for path in introduced {
+            let display_path = String::from_utf8_lossy(path).into_owned();
+            if config.verify_unicode {
+                if let Some(reason) = unicode_problem(path) {
+                    return Ok(invalid_filename(commit.id, display_path, reason));
+                }
+            }
+            if config.verify_windows_names {
+                if let Some(reason) = windows_problem(&display_path) {
+                    return Ok(invalid_filename(commit.id, display_path, reason));
+                }
+            }
+            if let Some(max_path_length) = config.max_path_length {
+                let length = display_path.chars().count();
+                if length > max_path_length {
+                    error!(
+                        "Filename verification failed for {}: {} is too long",
+                        commit.id, display_path
+                    );
+                    return Ok(PolicyResult::PathTooLong(
+                        commit.id,
+                        display_path,
+                        length,
+                        max_path_length,
+                    ));
+                }
+            }
+            if config.verify_case_collisions {
+                let other = paths_by_lowercase.other(path);
+                if let Some(other) = other {
+                    error!(
+                        "Filename verification failed for {}: {} collides with {}",
+                        commit.id,
+                        display_path,
+                        String::from_utf8_lossy(other)
+                    );
+                    return Ok(PolicyResult::FilenameCaseCollision(
+                        commit.id,
+                        display_path,
+                        String::from_utf8_lossy(other).into_owned(),
+                    ));
+                }
+            }
         }
+    }
 
-        let mut keyring =
-            Keyring::from_team_fingerprints_file(git.read_file(&config.team_fingerprints_file)?);
+    info!("Filename verification passed");
+    Ok(PolicyResult::Ok)
+}
+
+/// The paths in a tree, by their lowercase form, so that paths that
+/// only differ by case can be found without reading the tree again.
+#[derive(Default)]
+struct PathsByLowercase {
+    paths: HashMap<String, Vec<Vec<u8>>>,
+}
 
-        let manually_verified_commmits = find_and_verify_override_tags(
+impl PathsByLowercase {
+    fn insert(&mut self, path: Vec<u8>) {
+        let paths = self.paths.entry(lowercase_path(&path)).or_default();
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    fn remove(&mut self, path: &[u8]) {
+        let key = lowercase_path(path);
+        if let Some(paths) = self.paths.get_mut(&key) {
+            paths.retain(|other| other != path);
+            if paths.is_empty() {
+                self.paths.remove(&key);
+            }
+        }
+    }
+
+    /// Another path that differs from `path` only by case.
+    fn other(&self, path: &[u8]) -> Option<&Vec<u8>> {
+        self.paths
+            .get(&lowercase_path(path))
+            .and_then(|paths| paths.iter().find(|other| *other != path))
+    }
+}
+
+fn lowercase_path(path: &[u8]) -> String {
+    String::from_utf8_lossy(path).to_lowercase()
+}
+
+fn invalid_filename(commit_id: Oid, path: String, reason: String) -> PolicyResult {
+    error!(
+        "Filename verification failed for {}: {} {}",
+        commit_id, path, reason
+    );
+    PolicyResult::InvalidFilename(commit_id, path, reason)
+}
+
+pub fn verify_submodules_and_symlinks<G: Git, P: Gpg>(
+    git: &G,
+    gpg: &P,
+    config: &VerifySubmodulesAndSymlinksConfig,
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    info!("Executing policy: verify_submodules_and_symlinks");
+
+    if let ReferenceUpdate::Delete { .. } = ref_update {
+        debug!("Delete branch detected, no submodules or symlinks to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+    if git.is_tag(ref_update.ref_name())? {
+        debug!("Tag detected, no submodules or symlinks to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+
+    let allowed_submodule_paths = path_matcher_list(&config.allowed_submodule_paths)?;
+    let allowed_symlink_paths = path_matcher_list(&config.allowed_symlink_paths)?;
+    let is_allowed =
+        |matchers: &[GlobMatcher], path: &str| matchers.iter().any(|m| m.is_match(path));
+
+    let all_commits = commits_to_verify(git, ref_update, &config.override_tag_pattern)?;
+    let mut commits = if config.override_tag_pattern.is_some() {
+        let team_fingerprints_file = git.read_file(&config.team_fingerprints_file)?;
+        let mut keyring = Keyring::from_team_fingerprints_file(team_fingerprints_file);
+        let manually_verified_commits = find_and_verify_override_tags(
             git,
-            &gpg,
+            gpg,
             &all_commits,
-            config.override_tags_required,
+            &config.override_tags_required,
             &mut keyring,
         )?;
-        let not_manually_verified_commits = commits_to_verify_excluding_manually_verified(
+        commits_to_verify_excluding_manually_verified(
             git,
-            &ref_update,
-            manually_verified_commmits,
+            ref_update,
+            manually_verified_commits,
             &config.override_tag_pattern,
-        )?;
+        )?
+    } else {
+        all_commits
+    };
 
-        if config.verify_email_addresses {
-            policy_result = policy_result.and_then(|| {
-                Ok(verify_email_addresses(
-                    &config.author_domain,
-                    &config.committer_domain,
-                    &not_manually_verified_commits,
-                ))
-            })?;
+    // Commits are checked oldest first, so that problems are reported
+    // against the commit that introduced them.
+    commits.reverse();
+    for commit in commits {
+        for link in git.added_links(commit.id)? {
+            match link {
+                AddedLink::Submodule { path, .. }
+                    if !is_allowed(&allowed_submodule_paths, &path) =>
+                {
+                    error!(
+                        "Submodule verification failed for {}: {} was added or changed",
+                        commit.id, path
+                    );
+                    return Ok(PolicyResult::SubmoduleNotAllowed(commit.id, path));
+                }
+                AddedLink::Symlink { path, target }
+                    if escapes_repo(&path, &target)
+                        && !is_allowed(&allowed_symlink_paths, &path) =>
+                {
+                    error!(
+                        "Symlink verification failed for {}: {} points to {}",
+                        commit.id, path, target
+                    );
+                    return Ok(PolicyResult::SymlinkEscapesRepo(commit.id, path, target));
+                }
+                _ => {}
+            }
         }
 
-        if config.verify_rebased {
-            policy_result = policy_result.and_then(|| {
-                verify_rebased::<G>(&all_commits, git, &ref_update, &config.override_tag_pattern)
-            })?;
+        // A URL only counts as changed if none of the parents already
+        // had it, so merging a branch doesn't report it again.
+        let read_gitmodules = |commit_id| -> Result<_, Box<dyn Error>> {
+            Ok(git
+                .read_file_at_commit(commit_id, ".gitmodules")?
+                .map(|contents| parse_gitmodules(&String::from_utf8_lossy(&contents)))
+                .unwrap_or_default())
+        };
+        let parent_submodules = commit
+            .parents
+            .iter()
+            .map(|parent| read_gitmodules(*parent))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (name, submodule) in read_gitmodules(commit.id)? {
+            let url = match submodule.url {
+                Some(url) => url,
+                None => continue,
+            };
+            let is_unchanged = parent_submodules.iter().any(|submodules| {
+                submodules
+                    .get(&name)
+                    .is_some_and(|parent| parent.url.as_ref() == Some(&url))
+            });
+            let path = submodule.path.unwrap_or(name);
+            if !is_unchanged && !is_allowed(&allowed_submodule_paths, &path) {
+                error!(
+                    "Submodule verification failed for {}: {} was pointed at {}",
+                    commit.id, path, url
+                );
+                return Ok(PolicyResult::SubmoduleUrlChanged(commit.id, path, url));
+            }
         }
+    }
+
+    info!("Submodule and symlink verification passed");
+    Ok(PolicyResult::Ok)
+}
+
+fn path_matcher_list(patterns: &[String]) -> Result<Vec<GlobMatcher>, Box<dyn Error>> {
+    Ok(patterns
+        .iter()
+        .map(|pattern| path_matchers(pattern))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+fn verify_git_commits_with_audit_trail<G: Git, P: Gpg>(
+    commit_checks: &CommitChecks<G, P>,
+    git: &G,
+    gpg: &P,
+    config: &VerifyGitCommitsConfig,
+    ref_update: &ReferenceUpdate,
+    audit_trail: &mut AuditTrail,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    info!("Executing policy: verify_git_commits");
+    let start = Instant::now();
 
-        if config.verify_different_authors {
-            policy_result = policy_result
-                .and_then(|| verify_different_authors::<G>(&all_commits, git, &ref_update))?;
+    let mut policy_result = PolicyResult::Ok;
+
+    if let ReferenceUpdate::Delete { .. } = ref_update {
+        debug!("Delete branch detected, no commits to verify.")
+    } else if git.is_tag(ref_update.ref_name())? {
+        debug!("Tag detected, no commits to verify.")
+    } else {
+        let mailmap_file = match &config.mailmap_file {
+            Some(mailmap_file) => git.read_file(mailmap_file)?,
+            None => String::new(),
+        };
+        let mailmap = Mailmap::from_mailmap_file(&mailmap_file);
+        let all_commits = commits_to_verify(git, ref_update, &config.override_tag_pattern)?
+            .into_iter()
+            .map(|commit| mailmap.apply(commit))
+            .collect::<Vec<_>>();
+
+        debug!("Number of commits to verify {} : ", all_commits.len());
+        for commit in &all_commits {
+            debug!("{:?}", commit);
+        }
+        for commit in &all_commits {
+            let mailmapped = [
+                ("author", &commit.raw_author, commit.author()),
+                ("committer", &commit.raw_committer, commit.committer()),
+            ];
+            for (role, raw, canonical) in &mailmapped {
+                if let Some(raw) = raw {
+                    let outcome = format!("{} {} is {}", role, raw, canonical);
+                    info!("Mailmap applied to {}: {}", commit.id, outcome);
+                    audit_trail.record(commit.id, "mailmap", &outcome);
+                }
+            }
         }
 
-        if config.verify_commit_signatures {
-            policy_result = policy_result.and_then(|| {
-                verify_commit_signatures::<G, P>(
-                    git,
-                    &gpg,
-                    &not_manually_verified_commits,
-                    &mut keyring,
-                )
-            })?;
+        let team_fingerprints_file = git.read_file(&config.team_fingerprints_file)?;
+        let mut cache =
+            VerificationCache::load(git, config, &team_fingerprints_file, &mailmap_file)?;
+        let mut keyring = Keyring::from_team_fingerprints_file(team_fingerprints_file);
+
+        let deadline = config
+            .time_budget_ms
+            .map(|budget| start + Duration::from_millis(budget));
+        let mut context = CommitCheckContext::new(
+            git,
+            gpg,
+            config,
+            ref_update,
+            deadline,
+            &all_commits,
+            &mut keyring,
+            audit_trail,
+            &mut cache,
+        );
+        let checks = commit_checks.enabled(&context)?;
+        for (completed, check) in checks.iter().enumerate() {
+            if policy_result.is_err() {
+                break;
+            }
+            let out_of_time = match OutOfTime::check(deadline, 0) {
+                Ok(()) => match check.check(&mut context) {
+                    Ok(result) => {
+                        policy_result = result;
+                        None
+                    }
+                    Err(e) => match e.downcast::<OutOfTime>() {
+                        Ok(out_of_time) => Some(*out_of_time),
+                        Err(e) => return Err(e),
+                    },
+                },
+                Err(out_of_time) => Some(out_of_time),
+            };
+            if let Some(out_of_time) = out_of_time {
+                for not_run in &checks[completed..] {
+                    context.audit_trail.record_all(
+                        &all_commits,
+                        not_run.name(),
+                        "not run, time budget exceeded",
+                    );
+                }
+                cache.save(git);
+                return Ok(time_budget_exceeded(
+                    config,
+                    ref_update,
+                    &checks[..completed],
+                    &checks[completed..],
+                    out_of_time.commits_verified,
+                    start.elapsed(),
+                ));
+            }
+            debug!(
+                "Check {} completed after {} ms",
+                check.name(),
+                start.elapsed().as_millis()
+            );
         }
+        cache.save(git);
     }
 
     info!(
@@ -110,6 +680,49 @@ pub fn verify_git_commits<G: Git, P: Gpg>(
     Ok(policy_result)
 }
 
+fn time_budget_exceeded<G: Git, P: Gpg>(
+    config: &VerifyGitCommitsConfig,
+    ref_update: &ReferenceUpdate,
+    completed: &[&dyn CommitCheck<G, P>],
+    not_run: &[&dyn CommitCheck<G, P>],
+    commits_verified: usize,
+    elapsed: Duration,
+) -> PolicyResult {
+    let completed = completed
+        .iter()
+        .map(|c| c.name().to_string())
+        .collect::<Vec<_>>();
+    let not_run = not_run
+        .iter()
+        .map(|c| c.name().to_string())
+        .collect::<Vec<_>>();
+    match config.on_time_budget_exceeded {
+        TimeBudgetOutcome::FailClosed => {
+            error!(
+                "Time budget exceeded for {} after {} ms. Completed checks: {:?}. Checks not run: {:?}. Commits verified: {}",
+                ref_update.ref_name(),
+                elapsed.as_millis(),
+                completed,
+                not_run,
+                commits_verified
+            );
+            PolicyResult::TimeBudgetExceeded(completed, not_run, commits_verified)
+        }
+        TimeBudgetOutcome::FailOpen => {
+            warn!(
+                "AUDIT: Time budget exceeded for {} after {} ms, accepting the update WITHOUT running all checks. Completed checks: {:?}. Checks not run: {:?}. Commits verified: {}. New commit: {:?}",
+                ref_update.ref_name(),
+                elapsed.as_millis(),
+                completed,
+                not_run,
+                commits_verified,
+                ref_update.new_commit_id()
+            );
+            PolicyResult::Ok
+        }
+    }
+}
+
 fn commits_to_verify<G: Git>(
     git: &G,
     ref_update: &ReferenceUpdate,
@@ -137,8 +750,8 @@ fn commits_to_verify_excluding_manually_verified<G: Git>(
 fn find_and_verify_override_tags<G: Git, P: Gpg>(
     git: &G,
     gpg: &P,
-    commits: &Vec<Commit>,
-    required_tags: u8,
+    commits: &[Commit],
+    required_tags: &SignerRequirement,
     keyring: &mut Keyring,
 ) -> Result<Vec<Oid>, Box<dyn Error>> {
     let repo_path = git.path();
@@ -146,23 +759,18 @@ fn find_and_verify_override_tags<G: Git, P: Gpg>(
         keyring,
         &commits
             .iter()
-            .filter(|c| c.tags.len() >= required_tags.into())
+            .filter(|c| c.tags.len() >= required_tags.signers().into())
             .flat_map(|c| c.tags.iter().flat_map(|t| t.tagger_email.as_deref()))
             .collect(),
     )?;
 
     let tagged_commits = commits
         .iter()
-        .filter(|c| c.tags.len() >= required_tags.into())
+        .filter(|c| c.tags.len() >= required_tags.signers().into())
         .filter_map(|c| {
-            let verified_taggers = c
-                .tags
-                .iter()
-                .filter(|t| verify_tag_logging_errors::<G>(&repo_path, t, keyring))
-                .filter_map(|t| t.tagger_email.as_ref())
-                .collect::<HashSet<_>>();
+            let verified_taggers = verified_taggers::<G>(repo_path, &c.tags, keyring);
 
-            if verified_taggers.len() >= required_tags.into() {
+            if required_tags.is_met_by(&verified_taggers, keyring) {
                 info!("Override tags found for {}. Tags created by {:?}. This commit, and it's ancestors, do not require validation.", c.id, verified_taggers);
                 Some(c.id)
             } else {
@@ -174,6 +782,17 @@ fn find_and_verify_override_tags<G: Git, P: Gpg>(
     Ok(tagged_commits)
 }
 
+fn verified_taggers<'a, G: Git>(
+    repo_path: &std::path::Path,
+    tags: impl IntoIterator<Item = &'a Tag>,
+    keyring: &Keyring,
+) -> HashSet<&'a String> {
+    tags.into_iter()
+        .filter(|t| verify_tag_logging_errors::<G>(repo_path, t, keyring))
+        .filter_map(|t| t.tagger_email.as_ref())
+        .collect()
+}
+
 fn verify_tag_logging_errors<G: Git>(
     repo_path: &std::path::Path,
     tag: &Tag,
@@ -191,11 +810,33 @@ fn verify_tag_logging_errors<G: Git>(
     }
 }
 
+fn verify_commit_logging_errors<G: Git>(
+    repo_path: &std::path::Path,
+    commit: &Commit,
+    keyring: &Keyring,
+) -> bool {
+    match G::verify_commit_signature(repo_path, commit, keyring) {
+        Ok(result) => result,
+        Err(e) => {
+            error!(
+                "Technical error occurred while trying to validate commit signature {}. Error: {}",
+                commit.id, e
+            );
+            false
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn verify_commit_signatures<G: Git, P: Gpg>(
     git: &G,
     gpg: &P,
     commits: &[Commit],
     keyring: &mut Keyring,
+    verify_signing_key_validity: bool,
+    check: &str,
+    audit_trail: &mut AuditTrail,
+    deadline: Option<Instant>,
 ) -> Result<PolicyResult, Box<dyn Error>> {
     gpg.receive_keys(
         keyring,
@@ -206,33 +847,55 @@ fn verify_commit_signatures<G: Git, P: Gpg>(
     )?;
 
     let repo_path = git.path();
-    let commits_with_verified_signatures: HashSet<Oid> = commits
+    // Running git verify-commit is the slow part, so the deadline is
+    // checked before each one. Commits that weren't reached are None.
+    let signature_results = commits
         .par_iter()
-        .filter(|commit| {
-            match G::verify_commit_signature(repo_path, &commit, keyring) {
-                Ok(result) => result,
-                Err(e) => {
-                    error!(
-                        "Technical error occurred while trying to validate commit signature {}. Error: {}",
-                           commit.id, e
-                    );
-                    false
-                }
-            }
+        .map(|commit| {
+            OutOfTime::check(deadline, 0).ok()?;
+            Some((
+                commit.id,
+                verify_commit_logging_errors::<G>(repo_path, commit, keyring),
+            ))
         })
-        .map(|commit| commit.id)
+        .collect::<Vec<_>>();
+    let commits_verified = signature_results.iter().flatten().count();
+    if commits_verified < commits.len() {
+        return Err(Box::new(OutOfTime { commits_verified }));
+    }
+    let commits_with_verified_signatures: HashSet<Oid> = signature_results
+        .into_iter()
+        .flatten()
+        .filter(|(_, verified)| *verified)
+        .map(|(id, _)| id)
         .collect();
 
     commits.iter()
-        .map(|commit| {
+        .enumerate()
+        .map(|(verified, commit)| {
+            OutOfTime::check(deadline, verified)?;
             if commit.is_identical_tree_to_any_parent {
                 info!("Signature verification passed for {}: verified identical to one of its parents, no signature required", commit.id);
+                audit_trail.record(commit.id, check, "identical tree to a parent");
                 Ok(PolicyResult::Ok)
             } else if commits_with_verified_signatures.contains(&commit.id) {
+                let fingerprint = commit.committer_email.as_ref().and_then(|email| keyring.fingerprint_id_from_email(email));
+                if verify_signing_key_validity {
+                    // The key that signed can be a subkey, with its own validity
+                    if let Some(signing_key) = G::commit_signing_key(repo_path, commit)? {
+                        if gpg.key_validity(&signing_key)?.is_some_and(|validity| !validity.contains(commit.committer_time)) {
+                            error!("Signature verification failed for {}: committed outside of when key {} is valid", commit.id, signing_key);
+                            return Ok(PolicyResult::SignedOutsideKeyValidity(commit.id, signing_key));
+                        }
+                    }
+                }
                 info!("Signature verification passed for {}: verified with a valid signature", commit.id);
+                audit_trail.record(commit.id, check, "valid signature");
+                audit_trail.record_signer(commit.id, fingerprint);
                 Ok(PolicyResult::Ok)
             } else if git.is_trivial_merge_commit(commit)? {
                 info!("Signature verification passed for {}: verified to be a trivial merge of its parents, no signature required", commit.id);
+                audit_trail.record(commit.id, check, "trivial merge");
                 Ok(PolicyResult::Ok)
             }  else {
                 error!("Signature verification failed for {}", commit.id);
@@ -246,9 +909,240 @@ fn verify_commit_signatures<G: Git, P: Gpg>(
         .collect()
 }
 
-fn verify_different_authors<G: Git>(
+/// Rejects new commits whose subjects match one of the
+/// `rejected_subjects` globs, on updates to a mainline. Subjects are
+/// matched case insensitively, so that "WIP*" also catches "wip: half
+/// done".
+pub fn verify_subjects<G: Git>(
+    git: &G,
+    config: &VerifySubjectsConfig,
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    info!("Executing policy: verify_subjects");
+
+    if let ReferenceUpdate::Delete { .. } = ref_update {
+        debug!("Delete branch detected, no subjects to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+    if git.is_tag(ref_update.ref_name())? {
+        debug!("Tag detected, no subjects to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+    if !git.is_mainline(ref_update.ref_name())? {
+        info!("Subject verification passed: Not updating a mainline branch");
+        return Ok(PolicyResult::Ok);
+    }
+
+    let mut globs = GlobSetBuilder::new();
+    for pattern in &config.rejected_subjects {
+        globs.add(GlobBuilder::new(pattern).case_insensitive(true).build()?);
+    }
+    let globs = globs.build()?;
+
+    Ok(commits_to_verify(git, ref_update, &None)?
+        .iter()
+        .find(|commit| globs.is_match(commit.subject()))
+        .map(|commit| {
+            error!(
+                "Subject verification failed for {}: {:?}",
+                commit.id,
+                commit.subject()
+            );
+            PolicyResult::RejectedSubject(commit.id, commit.subject().to_string())
+        })
+        .unwrap_or(PolicyResult::Ok))
+}
+
+pub fn verify_sign_off<G: Git>(
+    git: &G,
+    config: &VerifySignOffConfig,
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    info!("Executing policy: verify_sign_off");
+
+    if let ReferenceUpdate::Delete { .. } = ref_update {
+        debug!("Delete branch detected, no sign off to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+    if git.is_tag(ref_update.ref_name())? {
+        debug!("Tag detected, no sign off to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+
+    let mailmap_file = match &config.mailmap_file {
+        Some(mailmap_file) => git.read_file(mailmap_file)?,
+        None => String::new(),
+    };
+    let mailmap = Mailmap::from_mailmap_file(&mailmap_file);
+
+    commits_to_verify(git, ref_update, &None)?
+        .into_iter()
+        .map(|commit| mailmap.apply(commit))
+        .map(|commit| {
+            let author = format!(
+                "{} <{}>",
+                commit.author_name.as_deref().unwrap_or_default(),
+                commit.author_email.as_deref().unwrap_or_default()
+            );
+            if commit.is_merge_commit && commit.is_identical_tree_to_any_parent {
+                info!("Sign off verification passed for {}: merge commit verified identical to one of its parents, no sign off required", commit.id);
+                Ok(PolicyResult::Ok)
+            } else if commit.trailers().iter().any(|t| {
+                // Authors can sign off as the identity they committed
+                // with, before the mailmap changed it
+                t.is("Signed-off-by")
+                    && (is_same_identity(&t.value, &author)
+                        || commit
+                            .raw_author
+                            .as_ref()
+                            .is_some_and(|raw| is_same_identity(&t.value, &raw.to_string())))
+            }) {
+                info!("Sign off verification passed for {}: signed off by its author", commit.id);
+                Ok(PolicyResult::Ok)
+            } else if commit.is_merge_commit && git.is_trivial_merge_commit(&commit)? {
+                info!("Sign off verification passed for {}: verified to be a trivial merge of its parents, no sign off required", commit.id);
+                Ok(PolicyResult::Ok)
+            } else {
+                error!(
+                    "Sign off verification failed for {}: no Signed-off-by trailer for its author, {}",
+                    commit.id, author
+                );
+                Ok(PolicyResult::MissingSignOff(commit.id, author))
+            }
+        })
+        .collect()
+}
+
+fn verify_identities<G: Git, P: Gpg>(
+    commits: &[Commit],
+    git: &G,
+    gpg: &P,
+    config: &VerifyGitCommitsConfig,
+    keyring: &mut Keyring,
+    deadline: Option<Instant>,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    if config.verify_signer_is_committer {
+        gpg.receive_keys(
+            keyring,
+            &commits
+                .iter()
+                .flat_map(|c| vec![c.author_email.as_deref(), c.committer_email.as_deref()])
+                .flatten()
+                .collect(),
+        )?;
+    }
+
+    for (verified, commit) in commits.iter().enumerate() {
+        OutOfTime::check(deadline, verified)?;
+        if config.verify_signer_is_committer {
+            // Unsigned commits are left to the signature check, which
+            // knows which of them don't need a signature.
+            if let Some(signer) = G::commit_signer(git.path(), commit)? {
+                let signer_email = keyring
+                    .email_from_fingerprint_id(&signer)
+                    .unwrap_or(&signer);
+                if commit.committer_email.as_deref() != Some(signer_email) {
+                    error!(
+                        "Identity verification failed for {}: signed by {}",
+                        commit.id, signer_email
+                    );
+                    return Ok(PolicyResult::SignerIsNotCommitter(
+                        commit.id,
+                        commit.reported_committer_email(),
+                        signer_email.to_string(),
+                    ));
+                }
+            }
+        }
+
+        if config.verify_identity_names {
+            let identities = [
+                (
+                    &commit.author_name,
+                    &commit.author_email,
+                    &commit.raw_author,
+                ),
+                (
+                    &commit.committer_name,
+                    &commit.committer_email,
+                    &commit.raw_committer,
+                ),
+            ];
+            for (name, email, raw) in &identities {
+                let reported_name = reported(name, raw.as_ref().map(|raw| &raw.name));
+                let name = name.as_deref().unwrap_or_default();
+                let expected = email
+                    .as_deref()
+                    .and_then(|email| keyring.name_from_email(email));
+                if let Some(expected) = expected {
+                    if !is_same_name(name, expected, config.normalise_identity_names) {
+                        error!(
+                            "Identity verification failed for {}: {:?} is not {:?}",
+                            commit.id, name, expected
+                        );
+                        return Ok(PolicyResult::NameDoesNotMatchKeyring(
+                            commit.id,
+                            reported_name,
+                            expected.to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        if config.verify_author_is_committer
+            && commit.author_email != commit.committer_email
+            && !commit.message.contains("(cherry picked from commit ")
+        {
+            error!(
+                "Identity verification failed for {}: author is not the committer",
+                commit.id
+            );
+            return Ok(PolicyResult::AuthorIsNotCommitter(commit.id));
+        }
+    }
+
+    info!("Identity verification passed");
+    Ok(PolicyResult::Ok)
+}
+
+// Normalised names ignore case and differences in whitespace.
+fn is_same_name(name: &str, expected: &str, normalise: bool) -> bool {
+    if normalise {
+        let normalised = |name: &str| {
+            name.split_whitespace()
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        normalised(name) == normalised(expected)
+    } else {
+        name == expected
+    }
+}
+
+// Compares identities in the `Name <email>` format. Email addresses
+// are compared case insensitively, names exactly.
+fn is_same_identity(a: &str, b: &str) -> bool {
+    match (split_identity(a), split_identity(b)) {
+        (Some((a_name, a_email)), Some((b_name, b_email))) => {
+            a_name == b_name && a_email.eq_ignore_ascii_case(b_email)
+        }
+        _ => false,
+    }
+}
+
+fn split_identity(identity: &str) -> Option<(&str, &str)> {
+    let (name, email) = identity.trim().strip_suffix('>')?.rsplit_once('<')?;
+    Some((name.trim(), email.trim()))
+}
+
+fn verify_different_authors<G: Git, P: Gpg>(
     commits: &[Commit],
     git: &G,
+    gpg: &P,
+    config: &VerifyGitCommitsConfig,
+    keyring: &mut Keyring,
     ref_update: &ReferenceUpdate,
 ) -> Result<PolicyResult, Box<dyn Error>> {
     match ref_update {
@@ -274,7 +1168,7 @@ fn verify_different_authors<G: Git>(
             } else if !is_merge {
                 info!("Multiple author verification passed for {}: Not a merge commit, does not require multiple authors", new_commit_id);
                 Ok(PolicyResult::Ok)
-            } else if commits.len() == 0 {
+            } else if commits.is_empty() {
                 info!("Multiple author verification passed for {}: No new commits pushed, does not require multiple authors", new_commit_id);
                 Ok(PolicyResult::Ok)
             } else if commits.len() == 1 && commits[0].is_identical_tree_to_any_parent {
@@ -284,7 +1178,7 @@ fn verify_different_authors<G: Git>(
                 info!("Multiple author verification passed for {}: There is only one commit and it is a trivial merge between mainline branches", new_commit_id);
                 Ok(PolicyResult::Ok)
             } else {
-                let authors: HashSet<_> = commits
+                let mut authors: HashSet<String> = commits
                     .iter()
                     .flat_map(|c| {
                         c.tags
@@ -292,7 +1186,9 @@ fn verify_different_authors<G: Git>(
                             .filter_map(|t| t.tagger_email.as_ref())
                             .chain(c.author_email.as_ref())
                     })
+                    .cloned()
                     .collect();
+                authors.extend(trailer_authors(commits, git, gpg, config, keyring)?);
                 if authors.len() <= 1 {
                     error!(
                 "Multiple author verification failed for {}: requires multiple authors, found {:?}",
@@ -311,6 +1207,464 @@ fn verify_different_authors<G: Git>(
     }
 }
 
+/// Emails from the `Co-authored-by` and `Reviewed-by` trailers that
+/// are configured to count as authors. With `verify_trailer_authors`,
+/// a trailer only counts if that person also signed one of the
+/// commits, or one of the override tags, being pushed.
+fn trailer_authors<G: Git, P: Gpg>(
+    commits: &[Commit],
+    git: &G,
+    gpg: &P,
+    config: &VerifyGitCommitsConfig,
+    keyring: &mut Keyring,
+) -> Result<HashSet<String>, Box<dyn Error>> {
+    let mut counted_trailers = Vec::new();
+    if config.count_co_authored_by {
+        counted_trailers.push("Co-authored-by");
+    }
+    if config.count_reviewed_by {
+        counted_trailers.push("Reviewed-by");
+    }
+
+    let trailer_emails: HashSet<String> = commits
+        .iter()
+        .flat_map(|c| c.trailers())
+        .filter(|t| counted_trailers.iter().any(|key| t.is(key)))
+        .filter_map(|t| split_identity(&t.value).map(|(_, email)| email.to_string()))
+        .collect();
+    if !config.verify_trailer_authors || trailer_emails.is_empty() {
+        return Ok(trailer_emails);
+    }
+
+    gpg.receive_keys(
+        keyring,
+        &trailer_emails.iter().map(String::as_str).collect(),
+    )?;
+    let repo_path = git.path();
+    let signed_commit_emails = commits
+        .iter()
+        .filter(|c| {
+            c.committer_email
+                .as_ref()
+                .is_some_and(|email| trailer_emails.contains(email))
+        })
+        .filter(|c| verify_commit_logging_errors::<G>(repo_path, c, keyring))
+        .filter_map(|c| c.committer_email.as_ref());
+    let signed_tag_emails = commits
+        .iter()
+        .flat_map(|c| c.tags.iter())
+        .filter(|t| {
+            t.tagger_email
+                .as_ref()
+                .is_some_and(|email| trailer_emails.contains(email))
+        })
+        .filter(|t| verify_tag_logging_errors::<G>(repo_path, t, keyring))
+        .filter_map(|t| t.tagger_email.as_ref());
+    let signers: HashSet<&String> = signed_commit_emails.chain(signed_tag_emails).collect();
+
+    Ok(trailer_emails
+        .iter()
+        .filter(|email| {
+            let is_signer = signers.contains(email);
+            if !is_signer {
+                warn!(
+                    "Not counting {} as an author: trailer is not backed by a signed commit or tag from them",
+                    email
+                );
+            }
+            is_signer
+        })
+        .cloned()
+        .collect())
+}
+
+/// Requires the update to a mainline to be approved by signed tags
+/// from enough members of the team, other than the author of the new
+/// commit. Approval tags can be on the new commit, or on the tip of a
+/// branch that it merges.
+fn verify_approvals<G: Git, P: Gpg>(
+    commits: &[Commit],
+    git: &G,
+    gpg: &P,
+    config: &VerifyGitCommitsConfig,
+    keyring: &mut Keyring,
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    let (new_commit_id, ref_name) = match ref_update {
+        ReferenceUpdate::Delete { .. } => {
+            info!("Approval verification passed: No checks required for deleting a branch");
+            return Ok(PolicyResult::Ok);
+        }
+        ReferenceUpdate::New {
+            new_commit_id,
+            ref_name,
+        }
+        | ReferenceUpdate::Update {
+            new_commit_id,
+            ref_name,
+            ..
+        } => (*new_commit_id, ref_name),
+    };
+
+    if !git.is_mainline(ref_name)? {
+        info!(
+            "Approval verification passed for {}: Not updating a mainline branch",
+            new_commit_id
+        );
+        return Ok(PolicyResult::Ok);
+    } else if commits.is_empty() {
+        info!(
+            "Approval verification passed for {}: No new commits pushed",
+            new_commit_id
+        );
+        return Ok(PolicyResult::Ok);
+    }
+
+    let new_commit = git.find_commit(new_commit_id, &Some(config.approval_tag_pattern.clone()))?;
+    let approval_tags = approval_tags(git, config, &new_commit)?;
+
+    gpg.receive_keys(
+        keyring,
+        &approval_tags
+            .iter()
+            .filter_map(|t| t.tagger_email.as_deref())
+            .chain(new_commit.committer_email.as_deref())
+            .collect(),
+    )?;
+    // Whoever wrote, committed or signed the new commit can't approve
+    // it, under their own or their mailmapped email address.
+    let mut not_approvers = commits
+        .iter()
+        .filter(|c| c.id == new_commit_id)
+        .chain(iter::once(&new_commit))
+        .flat_map(|c| [c.author_email.clone(), c.committer_email.clone()])
+        .flatten()
+        .collect::<Vec<_>>();
+    if let Some(signer) = G::commit_signer(git.path(), &new_commit)? {
+        not_approvers.push(
+            keyring
+                .email_from_fingerprint_id(&signer)
+                .map(|email| email.to_string())
+                .unwrap_or(signer),
+        );
+    }
+    let approvers = verified_taggers::<G>(git.path(), &approval_tags, keyring)
+        .into_iter()
+        .filter(|approver| {
+            !not_approvers
+                .iter()
+                .any(|email| email.eq_ignore_ascii_case(approver))
+        })
+        .collect::<HashSet<_>>();
+    let is_approved = config.required_approvals.is_met_by(&approvers, keyring);
+    let mut approvers = approvers.into_iter().cloned().collect::<Vec<_>>();
+    approvers.sort();
+
+    if is_approved {
+        info!(
+            "Approval verification passed for {}: approved by {:?}",
+            new_commit_id, approvers
+        );
+        Ok(PolicyResult::Ok)
+    } else {
+        error!(
+            "Approval verification failed for {}: requires {} approvals, found {:?}",
+            new_commit_id, config.required_approvals, approvers
+        );
+        Ok(PolicyResult::NotEnoughApprovals(
+            new_commit_id,
+            config.required_approvals.to_string(),
+            approvers,
+        ))
+    }
+}
+
+/// Tags matching the approval tag pattern, on the new commit or on the
+/// tip of a branch that it merges.
+fn approval_tags<G: Git>(
+    git: &G,
+    config: &VerifyGitCommitsConfig,
+    new_commit: &Commit,
+) -> Result<Vec<Tag>, Box<dyn Error>> {
+    let mut approval_tags = new_commit.tags.clone();
+    for branch_tip in new_commit.parents.iter().skip(1) {
+        approval_tags.extend(
+            git.find_commit(*branch_tip, &Some(config.approval_tag_pattern.clone()))?
+                .tags,
+        );
+    }
+    Ok(approval_tags)
+}
+
+/// Requires every path changed by an update to a mainline to be
+/// approved by one of its owners, from the CODEOWNERS file on the
+/// mainline before the update. Owners approve with a signed approval
+/// tag, or by signing one of the new commits.
+fn verify_code_owners<G: Git, P: Gpg>(
+    commits: &[Commit],
+    git: &G,
+    gpg: &P,
+    config: &VerifyGitCommitsConfig,
+    code_owners_file: &str,
+    keyring: &mut Keyring,
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    let (old_commit_id, new_commit_id, ref_name) = match ref_update {
+        ReferenceUpdate::Update {
+            old_commit_id,
+            new_commit_id,
+            ref_name,
+        } => (*old_commit_id, *new_commit_id, ref_name),
+        _ => {
+            info!("Code owner verification passed: Only updates to existing branches have changes to approve");
+            return Ok(PolicyResult::Ok);
+        }
+    };
+
+    if !git.is_mainline(ref_name)? {
+        info!(
+            "Code owner verification passed for {}: Not updating a mainline branch",
+            new_commit_id
+        );
+        return Ok(PolicyResult::Ok);
+    } else if commits.is_empty() {
+        info!(
+            "Code owner verification passed for {}: No new commits pushed",
+            new_commit_id
+        );
+        return Ok(PolicyResult::Ok);
+    }
+
+    let code_owners = match git.read_file_at_commit(old_commit_id, code_owners_file)? {
+        Some(contents) => CodeOwners::parse(&String::from_utf8_lossy(&contents))?,
+        None => {
+            warn!(
+                "Code owner verification passed for {}: {} does not exist on the mainline",
+                new_commit_id, code_owners_file
+            );
+            return Ok(PolicyResult::Ok);
+        }
+    };
+
+    let new_commit = git.find_commit(new_commit_id, &Some(config.approval_tag_pattern.clone()))?;
+    let approval_tags = approval_tags(git, config, &new_commit)?;
+    gpg.receive_keys(
+        keyring,
+        &approval_tags
+            .iter()
+            .filter_map(|t| t.tagger_email.as_deref())
+            .chain(commits.iter().filter_map(|c| c.committer_email.as_deref()))
+            .collect(),
+    )?;
+    let repo_path = git.path();
+    let approvers = verified_taggers::<G>(repo_path, &approval_tags, keyring)
+        .into_iter()
+        .chain(
+            commits
+                .iter()
+                .filter(|c| verify_commit_logging_errors::<G>(repo_path, c, keyring))
+                .filter_map(|c| c.committer_email.as_ref()),
+        )
+        .collect::<HashSet<_>>();
+
+    let mut missing_owners = BTreeSet::new();
+    for path in git.changed_paths(old_commit_id, new_commit_id)? {
+        if let Some(owners) = code_owners.owners_of(&path) {
+            let is_approved = owners
+                .iter()
+                .any(|owner| approvers.iter().any(|a| owner.includes(a, keyring)));
+            if !is_approved {
+                let owners = owners
+                    .iter()
+                    .map(|o| o.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" or ");
+                error!(
+                    "Code owner verification failed for {}: {} needs approval from {}",
+                    new_commit_id, path, owners
+                );
+                missing_owners.insert(owners);
+            }
+        }
+    }
+
+    if missing_owners.is_empty() {
+        info!(
+            "Code owner verification passed for {}: approved by {:?}",
+            new_commit_id, approvers
+        );
+        Ok(PolicyResult::Ok)
+    } else {
+        Ok(PolicyResult::MissingCodeOwnerApprovals(
+            new_commit_id,
+            missing_owners.into_iter().collect(),
+        ))
+    }
+}
+
+/// Rejects new commits with more than `max_parents` parents, like
+/// octopus merges.
+pub fn verify_parent_count<G: Git>(
+    git: &G,
+    config: &VerifyParentCountConfig,
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    info!("Executing policy: verify_parent_count");
+
+    if let ReferenceUpdate::Delete { .. } = ref_update {
+        debug!("Delete branch detected, no parents to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+    if git.is_tag(ref_update.ref_name())? {
+        debug!("Tag detected, no parents to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+
+    let max_parents = config.max_parents;
+    Ok(commits_to_verify(git, ref_update, &None)?
+        .iter()
+        .find(|c| c.parents.len() > max_parents.into())
+        .map(|c| {
+            error!(
+                "Parent count verification failed for {}: {} parents",
+                c.id,
+                c.parents.len()
+            );
+            PolicyResult::TooManyParents(c.id, c.parents.len(), max_parents)
+        })
+        .unwrap_or(PolicyResult::Ok))
+}
+
+// Commits can be a little out of order or ahead of the server's clock,
+// since they're made on different machines, so both checks have a
+// tolerance.
+fn verify_timestamps<G: Git>(
+    commits: &[Commit],
+    git: &G,
+    config: &VerifyGitCommitsConfig,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    if let Some(max_future_skew) = config.max_future_skew_seconds {
+        let now = chrono::Utc::now().timestamp();
+        let latest = now + max_future_skew;
+        for commit in commits {
+            let time = commit.author_time.max(commit.committer_time);
+            if time > latest {
+                error!(
+                    "Timestamp verification failed for {}: dated {} seconds in the future",
+                    commit.id,
+                    time - now
+                );
+                return Ok(PolicyResult::CommitDatedInFuture(
+                    commit.id,
+                    format_time(time),
+                ));
+            }
+        }
+    }
+
+    if let Some(max_parent_skew) = config.max_parent_skew_seconds {
+        for commit in commits {
+            for parent_id in &commit.parents {
+                let parent = git.find_commit(*parent_id, &None)?;
+                if commit.committer_time < parent.committer_time - max_parent_skew {
+                    error!(
+                        "Timestamp verification failed for {}: committed {} seconds before its parent {}",
+                        commit.id,
+                        parent.committer_time - commit.committer_time,
+                        parent.id
+                    );
+                    return Ok(PolicyResult::CommittedBeforeParent(commit.id, parent.id));
+                }
+            }
+        }
+    }
+
+    info!("Timestamp verification passed");
+    Ok(PolicyResult::Ok)
+}
+
+fn format_time(time: i64) -> String {
+    chrono::NaiveDateTime::from_timestamp_opt(time, 0)
+        .map(|time| chrono::DateTime::<chrono::Utc>::from_utc(time, chrono::Utc).to_rfc3339())
+        .unwrap_or_else(|| time.to_string())
+}
+
+/// Rejects merge commits pushed to the refs named by the first
+/// `verify_linear_history` entry that matches the updated ref, and
+/// updates to them that aren't fast forwards if the entry asks for it.
+pub fn verify_linear_history<G: Git>(
+    git: &G,
+    config: &[LinearHistoryConfig],
+    ref_update: &ReferenceUpdate,
+) -> Result<PolicyResult, Box<dyn Error>> {
+    info!("Executing policy: verify_linear_history");
+
+    if let ReferenceUpdate::Delete { .. } = ref_update {
+        debug!("Delete branch detected, no history to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+    let ref_name = ref_update.ref_name();
+    if git.is_tag(ref_name)? {
+        debug!("Tag detected, no history to verify.");
+        return Ok(PolicyResult::Ok);
+    }
+    let config = match linear_history_config(config, ref_name)? {
+        Some(config) => config,
+        None => {
+            debug!("{} doesn't need a linear history.", ref_name);
+            return Ok(PolicyResult::Ok);
+        }
+    };
+
+    if let ReferenceUpdate::Update {
+        old_commit_id,
+        new_commit_id,
+        ..
+    } = ref_update
+    {
+        if config.fast_forward_only
+            && old_commit_id != new_commit_id
+            && !git.is_descendent_of(*new_commit_id, *old_commit_id)?
+        {
+            return Ok(PolicyResult::NotFastForward(
+                ref_name.to_string(),
+                *new_commit_id,
+            ));
+        }
+    }
+
+    let merge_commits = commits_to_verify(git, ref_update, &None)?
+        .iter()
+        .filter(|c| c.is_merge_commit)
+        .map(|c| c.id)
+        .collect::<Vec<_>>();
+    if merge_commits.is_empty() {
+        info!("Linear history verification passed for {}", ref_name);
+        Ok(PolicyResult::Ok)
+    } else {
+        Ok(PolicyResult::MergeCommitsNotAllowed(
+            ref_name.to_string(),
+            merge_commits,
+        ))
+    }
+}
+
+/// The first `verify_linear_history` entry that matches the ref.
+fn linear_history_config<'a>(
+    config: &'a [LinearHistoryConfig],
+    ref_name: &str,
+) -> Result<Option<&'a LinearHistoryConfig>, Box<dyn Error>> {
+    for linear_history in config {
+        let glob = GlobBuilder::new(&linear_history.refs)
+            .literal_separator(true)
+            .build()?;
+        if glob.compile_matcher().is_match(ref_name) {
+            return Ok(Some(linear_history));
+        }
+    }
+    Ok(None)
+}
+
 fn verify_rebased<G: Git>(
     commits: &[Commit],
     git: &G,
@@ -347,7 +1701,7 @@ fn verify_rebased<G: Git>(
                     new_commit_id
                 );
                 Ok(PolicyResult::Ok)
-            } else if commits.len() == 0 {
+            } else if commits.is_empty() {
                 info!(
                     "Rebase verification passed for {}: No new commits pushed",
                     new_commit_id
@@ -384,11 +1738,13 @@ fn verify_rebased<G: Git>(
 }
 
 fn verify_email_addresses(
-    author_domain: &str,
-    committer_domain: &str,
+    config: &VerifyGitCommitsConfig,
+    keyring: &Keyring,
     commits: &[Commit],
-) -> PolicyResult {
-    commits
+) -> Result<PolicyResult, Box<dyn Error>> {
+    let author_rule = EmailRule::new(config, &config.author_domain, keyring)?;
+    let committer_rule = EmailRule::new(config, &config.committer_domain, keyring)?;
+    Ok(commits
         .iter()
         .map(
             |commit| match (&commit.author_email, &commit.committer_email) {
@@ -406,27 +1762,103 @@ fn verify_email_addresses(
                     );
                     PolicyResult::MissingCommitterEmail(commit.id)
                 }
-                (Some(s), _) if !s.ends_with(&format!("@{}", author_domain)) => {
-                    error!(
-                        "Email address verification failed for {}: invalid author email {}",
-                        commit.id,
-                        s.to_string()
-                    );
-                    PolicyResult::InvalidAuthorEmail(commit.id, s.to_string())
-                }
-                (_, Some(s)) if !s.ends_with(&format!("@{}", committer_domain)) => {
-                    error!(
-                        "Email address verification failed for {}: invalid committer email {}",
-                        commit.id,
-                        s.to_string()
-                    );
-                    PolicyResult::InvalidCommitterEmail(commit.id, s.to_string())
-                }
-                _ => {
-                    info!("Email address verification passed for {}", commit.id);
-                    PolicyResult::Ok
+                (Some(author), Some(committer)) => {
+                    match (author_rule.check(author), committer_rule.check(committer)) {
+                        (Err(rule), _) => {
+                            error!(
+                                "Email address verification failed for {}: invalid author email {}, {}",
+                                commit.id,
+                                commit.reported_author_email(),
+                                rule
+                            );
+                            PolicyResult::InvalidAuthorEmail(
+                                commit.id,
+                                commit.reported_author_email(),
+                                rule,
+                            )
+                        }
+                        (_, Err(rule)) => {
+                            error!(
+                                "Email address verification failed for {}: invalid committer email {}, {}",
+                                commit.id,
+                                commit.reported_committer_email(),
+                                rule
+                            );
+                            PolicyResult::InvalidCommitterEmail(
+                                commit.id,
+                                commit.reported_committer_email(),
+                                rule,
+                            )
+                        }
+                        (Ok(author_reason), Ok(committer_reason)) => {
+                            info!(
+                                "Email address verification passed for {}: author email {} is allowed because {}, committer email {} is allowed because {}",
+                                commit.id,
+                                commit.reported_author_email(),
+                                author_reason,
+                                commit.reported_committer_email(),
+                                committer_reason
+                            );
+                            PolicyResult::Ok
+                        }
+                    }
                 }
             },
         )
-        .collect()
+        .collect())
+}
+
+/// The rules for one kind of email address, author or committer. An
+/// address is allowed if its domain matches one of the domains, if
+/// it's one of the allowed emails, or, if keyring emails are
+/// allowed, if it's in the Team Fingerprints file.
+struct EmailRule<'a> {
+    domains: &'a EmailDomains,
+    domain_globs: GlobSet,
+    allowed_emails: &'a [String],
+    keyring: Option<&'a Keyring>,
+}
+
+impl<'a> EmailRule<'a> {
+    fn new(
+        config: &'a VerifyGitCommitsConfig,
+        domains: &'a EmailDomains,
+        keyring: &'a Keyring,
+    ) -> Result<EmailRule<'a>, Box<dyn Error>> {
+        let mut domain_globs = GlobSetBuilder::new();
+        for domain in domains.patterns() {
+            domain_globs.add(GlobBuilder::new(domain).case_insensitive(true).build()?);
+        }
+        Ok(EmailRule {
+            domains,
+            domain_globs: domain_globs.build()?,
+            allowed_emails: &config.allowed_emails,
+            keyring: Some(keyring).filter(|_| config.allow_keyring_emails),
+        })
+    }
+
+    /// Returns the rule that allowed the email, or the reason that
+    /// it was rejected.
+    fn check(&self, email: &str) -> Result<String, String> {
+        if self
+            .allowed_emails
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(email))
+        {
+            return Ok("it is in allowed_emails".to_string());
+        }
+        if self
+            .keyring
+            .is_some_and(|keyring| keyring.contains_email_ignoring_case(email))
+        {
+            return Ok("it is in the Team Fingerprints file".to_string());
+        }
+        match email.rsplit_once('@') {
+            Some((_, domain)) if self.domain_globs.is_match(domain) => {
+                Ok(format!("its domain matches {}", self.domains))
+            }
+            Some(_) => Err(format!("its domain does not match {}", self.domains)),
+            None => Err("it has no domain".to_string()),
+        }
+    }
 }
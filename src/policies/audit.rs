@@ -0,0 +1,215 @@
+use crate::config::VerifyGitCommitsConfig;
+use crate::git::{Commit, Git};
+use crate::gpg::Gpg;
+use crate::reference_update::ReferenceUpdate;
+
+use git2::{ObjectType, Oid};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use log::*;
+
+pub const AUDIT_NOTES_REF: &str = "refs/notes/capn-audit";
+
+/// The reasons that a commit was accepted, as stored in its audit
+/// note. Outcomes are keyed by the name of the check.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    pub ref_name: String,
+    pub verified_at: String,
+    pub config_revision: Option<String>,
+    pub signer_fingerprint: Option<String>,
+    pub outcomes: BTreeMap<String, String>,
+}
+
+/// Collects the outcome of each check for each commit while the
+/// checks are running, so that they can be written as notes once the
+/// update has been accepted.
+pub struct AuditTrail {
+    enabled: bool,
+    ref_update: Option<ReferenceUpdate>,
+    ref_name: String,
+    config_revision: Option<String>,
+    records: BTreeMap<Oid, AuditRecord>,
+}
+
+impl AuditTrail {
+    pub fn disabled() -> AuditTrail {
+        AuditTrail {
+            enabled: false,
+            ref_update: None,
+            ref_name: String::new(),
+            config_revision: None,
+            records: BTreeMap::new(),
+        }
+    }
+
+    pub fn new<G: Git>(
+        git: &G,
+        config: &VerifyGitCommitsConfig,
+        ref_update: &ReferenceUpdate,
+    ) -> Result<AuditTrail, Box<dyn Error>> {
+        if !config.audit_notes {
+            return Ok(AuditTrail::disabled());
+        }
+        Ok(AuditTrail {
+            enabled: true,
+            ref_update: Some(ref_update.clone()),
+            ref_name: ref_update.ref_name().to_string(),
+            config_revision: git.head_commit_id()?.map(|id| id.to_string()),
+            records: BTreeMap::new(),
+        })
+    }
+
+    pub fn record(&mut self, commit_id: Oid, check: &str, outcome: &str) {
+        if !self.enabled {
+            return;
+        }
+        self.record_for(commit_id)
+            .outcomes
+            .insert(check.to_string(), outcome.to_string());
+    }
+
+    pub fn record_all(&mut self, commits: &[Commit], check: &str, outcome: &str) {
+        for commit in commits {
+            self.record(commit.id, check, outcome);
+        }
+    }
+
+    pub fn record_signer(&mut self, commit_id: Oid, fingerprint: Option<String>) {
+        if !self.enabled {
+            return;
+        }
+        self.record_for(commit_id).signer_fingerprint = fingerprint;
+    }
+
+    fn record_for(&mut self, commit_id: Oid) -> &mut AuditRecord {
+        let ref_name = &self.ref_name;
+        let config_revision = &self.config_revision;
+        self.records
+            .entry(commit_id)
+            .or_insert_with(|| AuditRecord {
+                ref_name: ref_name.clone(),
+                verified_at: chrono::Utc::now().to_rfc3339(),
+                config_revision: config_revision.clone(),
+                signer_fingerprint: None,
+                outcomes: BTreeMap::new(),
+            })
+    }
+
+    /// Saves the records in the Git directory, where the post-receive
+    /// hook picks them up once Git has applied the update.
+    fn save<G: Git>(self, git: &G, signing_key: Option<String>) -> Result<(), Box<dyn Error>> {
+        let ref_update = match self.ref_update {
+            Some(ref_update) if self.enabled && !self.records.is_empty() => ref_update,
+            _ => return Ok(()),
+        };
+        let saved = SavedAuditNotes {
+            signing_key,
+            records: self
+                .records
+                .into_iter()
+                .map(|(commit_id, record)| (commit_id.to_string(), record))
+                .collect(),
+        };
+        git.write_git_file(
+            &saved_notes_file(&ref_update)?,
+            0o640,
+            &serde_json::to_string(&saved)?,
+        )
+    }
+}
+
+/// The records that pre-receive saved for a ref update, until
+/// post-receive writes them as notes.
+#[derive(Serialize, Deserialize)]
+struct SavedAuditNotes {
+    signing_key: Option<String>,
+    records: BTreeMap<String, AuditRecord>,
+}
+
+// Each update gets its own file, named after the update, so that
+// concurrent pushes don't overwrite each other's records.
+fn saved_notes_file(ref_update: &ReferenceUpdate) -> Result<String, Box<dyn Error>> {
+    let update = format!(
+        "{} {} {}",
+        ref_update.old_commit_id().unwrap_or_else(Oid::zero),
+        ref_update.new_commit_id().unwrap_or_else(Oid::zero),
+        ref_update.ref_name()
+    );
+    Ok(format!(
+        "capn-audit-notes-{}.json",
+        Oid::hash_object(ObjectType::Blob, update.as_bytes())?
+    ))
+}
+
+/// Audit trails of updates that `verify_git_commits` accepted, held
+/// back until every policy has accepted every ref in the push, so that
+/// rejected pushes don't leave notes behind.
+#[derive(Default)]
+pub struct PendingAuditNotes {
+    trails: RefCell<Vec<(AuditTrail, Option<String>)>>,
+}
+
+impl PendingAuditNotes {
+    pub fn add(&self, audit_trail: AuditTrail, signing_key: Option<String>) {
+        self.trails.borrow_mut().push((audit_trail, signing_key));
+    }
+
+    /// Saves the audit trails for `write_audit_notes` to pick up. The
+    /// refs haven't been updated yet during pre-receive, so the notes
+    /// are only written once Git has applied the updates.
+    pub fn save<G: Git>(self, git: &G) -> Result<(), Box<dyn Error>> {
+        for (audit_trail, signing_key) in self.trails.into_inner() {
+            audit_trail.save(git, signing_key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the notes that pre-receive saved for an update that Git has
+/// applied, signing them with `gpg` if the config named a signing key.
+pub fn write_audit_notes<G: Git, P: Gpg>(
+    git: &G,
+    gpg: &P,
+    ref_update: &ReferenceUpdate,
+) -> Result<(), Box<dyn Error>> {
+    let saved_notes_file = saved_notes_file(ref_update)?;
+    let saved = match git.read_git_file(&saved_notes_file)? {
+        Some(json) => serde_json::from_str::<SavedAuditNotes>(&json)?,
+        None => {
+            debug!("No audit notes saved for {}", ref_update.ref_name());
+            return Ok(());
+        }
+    };
+    let notes = saved
+        .records
+        .iter()
+        .map(|(commit_id, record)| {
+            Ok((
+                Oid::from_str(commit_id)?,
+                serde_json::to_string_pretty(record)?,
+            ))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    git.write_notes(AUDIT_NOTES_REF, &notes, gpg, saved.signing_key.as_deref())?;
+    git.remove_git_file(&saved_notes_file)?;
+    info!(
+        "Audit notes written to {} for {} commits",
+        AUDIT_NOTES_REF,
+        notes.len()
+    );
+    Ok(())
+}
+
+pub fn read_audit_record<G: Git>(
+    git: &G,
+    commit_id: Oid,
+) -> Result<Option<AuditRecord>, Box<dyn Error>> {
+    match git.read_note(AUDIT_NOTES_REF, commit_id)? {
+        Some(note) => Ok(Some(serde_json::from_str(&note)?)),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,180 @@
+use crate::error::CapnError;
+use crate::keyring::Keyring;
+
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use std::error::Error;
+use std::fmt;
+
+/// The rules from a CODEOWNERS file. Each line is a path pattern,
+/// followed by the owners of the matching paths. Owners are the email
+/// addresses of people in the Team Fingerprints file, matched ignoring
+/// case, or `@group` for everyone in one of its groups. Like GitHub, the last rule that
+/// matches a path wins, so a rule with no owners removes the owners of
+/// the paths that it matches.
+pub struct CodeOwners {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    matchers: Vec<GlobMatcher>,
+    owners: Vec<Owner>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Owner {
+    Email(String),
+    Group(String),
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Owner::Email(email) => write!(f, "{}", email),
+            Owner::Group(group) => write!(f, "@{}", group),
+        }
+    }
+}
+
+impl Owner {
+    pub fn includes(&self, email: &str, keyring: &Keyring) -> bool {
+        match self {
+            Owner::Email(owner) => owner.eq_ignore_ascii_case(email),
+            Owner::Group(group) => keyring.is_in_group(email, group),
+        }
+    }
+}
+
+impl CodeOwners {
+    pub fn parse(file_contents: &str) -> Result<CodeOwners, Box<dyn Error>> {
+        let rules = file_contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut words = line.split_whitespace();
+                let pattern = words.next().expect("Line is not empty");
+                let owners = words
+                    .take_while(|word| !word.starts_with('#'))
+                    .map(|word| match word.strip_prefix('@') {
+                        Some(group) => Owner::Group(group.to_string()),
+                        None => Owner::Email(word.to_string()),
+                    })
+                    .collect();
+                Ok(Rule {
+                    matchers: path_matchers(pattern).map_err(|e| {
+                        CapnError::new(format!("Invalid CODEOWNERS pattern {}: {}", pattern, e))
+                    })?,
+                    owners,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        Ok(CodeOwners { rules })
+    }
+
+    /// The owners of a path, or None if it has no owners.
+    pub fn owners_of(&self, path: &str) -> Option<&[Owner]> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matchers.iter().any(|m| m.is_match(path)))
+            .map(|rule| &rule.owners[..])
+            .filter(|owners| !owners.is_empty())
+    }
+}
+
+// Patterns follow the .gitignore rules. They match anywhere in the
+// repo, unless they contain a slash other than a trailing one. A
+// pattern that names a directory matches everything inside it.
+pub(crate) fn path_matchers(pattern: &str) -> Result<Vec<GlobMatcher>, globset::Error> {
+    let is_anchored = pattern.trim_end_matches('/').contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    let pattern = if is_anchored {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+
+    let mut globs = Vec::new();
+    if let Some(directory) = pattern.strip_suffix('/') {
+        globs.push(format!("{}/**", directory));
+    } else {
+        globs.push(format!("{}/**", pattern));
+        globs.push(pattern);
+    }
+    globs
+        .iter()
+        .map(|glob| {
+            let glob: Glob = GlobBuilder::new(glob).literal_separator(true).build()?;
+            Ok(glob.compile_matcher())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keyring::Fingerprint;
+    use std::collections::HashMap;
+
+    fn owners(code_owners: &CodeOwners, path: &str) -> Vec<String> {
+        code_owners
+            .owners_of(path)
+            .unwrap_or_default()
+            .iter()
+            .map(|o| o.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let code_owners = CodeOwners::parse(
+            "# Everything\n* a@example.com\n\n*.rs @rust b@example.com\n/docs/ c@example.com\n",
+        )
+        .unwrap();
+        assert_eq!(owners(&code_owners, "README.md"), vec!["a@example.com"]);
+        assert_eq!(
+            owners(&code_owners, "src/lib.rs"),
+            vec!["@rust", "b@example.com"]
+        );
+        assert_eq!(owners(&code_owners, "docs/lib.rs"), vec!["c@example.com"]);
+        assert_eq!(owners(&code_owners, "src/docs/a.md"), vec!["a@example.com"]);
+    }
+
+    #[test]
+    fn unanchored_directory_matches_anywhere() {
+        let code_owners = CodeOwners::parse("vendor a@example.com\n").unwrap();
+        assert_eq!(owners(&code_owners, "vendor/lib.c"), vec!["a@example.com"]);
+        assert_eq!(
+            owners(&code_owners, "web/vendor/lib.js"),
+            vec!["a@example.com"]
+        );
+        assert!(code_owners.owners_of("vendored/lib.c").is_none());
+    }
+
+    #[test]
+    fn owners_match_emails_ignoring_case() {
+        let mut keyring = Keyring {
+            fingerprints: HashMap::new(),
+        };
+        keyring.fingerprints.insert(
+            "B@Example.com".to_string(),
+            Fingerprint {
+                id: "1212121212121212112".to_string(),
+                name: "B".to_string(),
+                email: "B@Example.com".to_string(),
+                groups: vec!["rust".to_string()],
+                public_key_is_available_locally: false,
+            },
+        );
+
+        assert!(Owner::Email("A@Example.com".to_string()).includes("a@example.COM", &keyring));
+        assert!(Owner::Group("rust".to_string()).includes("b@example.com", &keyring));
+        assert!(!Owner::Group("rust".to_string()).includes("a@example.com", &keyring));
+    }
+
+    #[test]
+    fn rule_without_owners_removes_ownership() {
+        let code_owners = CodeOwners::parse("* a@example.com\n/generated/\n").unwrap();
+        assert!(code_owners.owners_of("generated/schema.rs").is_none());
+    }
+}
@@ -0,0 +1,462 @@
+use crate::config::VerifyGitCommitsConfig;
+use crate::git::{Commit, Git};
+use crate::gpg::{Gpg, GpgWithDeadline};
+use crate::keyring::Keyring;
+use crate::policies::audit::AuditTrail;
+use crate::policies::policy_result::PolicyResult;
+use crate::policies::verification_cache::VerificationCache;
+use crate::policies::*;
+use crate::reference_update::ReferenceUpdate;
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::time::Instant;
+
+/// Everything a commit check gets to look at while `verify_git_commits`
+/// checks the new commits in a reference update. The keyring, cache and
+/// audit trail are shared by all of the checks.
+pub struct CommitCheckContext<'a, G: Git, P: Gpg> {
+    pub git: &'a G,
+    pub gpg: &'a P,
+    pub config: &'a VerifyGitCommitsConfig,
+    pub ref_update: &'a ReferenceUpdate,
+    /// When the `time_budget_ms` runs out. Checks that take a while
+    /// should give up with `OutOfTime` once it has passed.
+    pub deadline: Option<Instant>,
+    /// The new commits in the update, with the mailmap applied.
+    pub commits: &'a [Commit],
+    pub keyring: &'a mut Keyring,
+    pub audit_trail: &'a mut AuditTrail,
+    pub(crate) cache: &'a mut VerificationCache,
+    not_manually_verified_commits: Option<Vec<Commit>>,
+}
+
+impl<'a, G: Git, P: Gpg> CommitCheckContext<'a, G, P> {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        git: &'a G,
+        gpg: &'a P,
+        config: &'a VerifyGitCommitsConfig,
+        ref_update: &'a ReferenceUpdate,
+        deadline: Option<Instant>,
+        commits: &'a [Commit],
+        keyring: &'a mut Keyring,
+        audit_trail: &'a mut AuditTrail,
+        cache: &'a mut VerificationCache,
+    ) -> Self {
+        CommitCheckContext {
+            git,
+            gpg,
+            config,
+            ref_update,
+            deadline,
+            commits,
+            keyring,
+            audit_trail,
+            cache,
+            not_manually_verified_commits: None,
+        }
+    }
+
+    /// GPG that gives up fetching keys at the deadline.
+    pub fn gpg_with_deadline(&self) -> GpgWithDeadline<'a, P> {
+        GpgWithDeadline {
+            gpg: self.gpg,
+            deadline: self.deadline,
+        }
+    }
+
+    /// The new commits that haven't been verified by enough override
+    /// tags, on them or on one of their descendents. Checks that can be
+    /// overridden verify these instead of `commits`. The override tags
+    /// are only verified the first time that a check asks for these,
+    /// since verifying them needs the keyserver and GPG.
+    pub fn not_manually_verified_commits(&mut self) -> Result<Vec<Commit>, Box<dyn Error>> {
+        if let Some(commits) = &self.not_manually_verified_commits {
+            return Ok(commits.clone());
+        }
+
+        let has_override_candidates = self
+            .commits
+            .iter()
+            .any(|c| c.tags.len() >= self.config.override_tags_required.signers().into());
+        let commits = if has_override_candidates {
+            let manually_verified_commits = find_and_verify_override_tags(
+                self.git,
+                &self.gpg_with_deadline(),
+                self.commits,
+                &self.config.override_tags_required,
+                self.keyring,
+            )?;
+            let not_manually_verified_ids = commits_to_verify_excluding_manually_verified(
+                self.git,
+                self.ref_update,
+                manually_verified_commits,
+                &self.config.override_tag_pattern,
+            )?
+            .into_iter()
+            .map(|c| c.id)
+            .collect::<HashSet<_>>();
+            let (not_manually_verified, manually_verified): (Vec<_>, Vec<_>) = self
+                .commits
+                .iter()
+                .cloned()
+                .partition(|c| not_manually_verified_ids.contains(&c.id));
+            self.audit_trail.record_all(
+                &manually_verified,
+                "override tags",
+                "verified by override tags",
+            );
+            not_manually_verified
+        } else {
+            self.commits.to_vec()
+        };
+        self.not_manually_verified_commits = Some(commits.clone());
+        Ok(commits)
+    }
+
+    /// Records that the commits skipped by the verification cache
+    /// passed `check`, since it only sees the `checked` ones.
+    fn record_earlier_passes(&mut self, check: &str, commits: &[Commit], checked: &[Commit]) {
+        let checked_ids = checked.iter().map(|c| c.id).collect::<HashSet<_>>();
+        for commit in commits {
+            if !checked_ids.contains(&commit.id) {
+                self.audit_trail
+                    .record(commit.id, check, "passed in an earlier verification");
+            }
+        }
+    }
+}
+
+/// One of the checks that `verify_git_commits` runs on the new commits
+/// in a reference update. Checks are turned on by the
+/// `verify_git_commits` config, so `is_enabled` returns false if the
+/// check shouldn't run. Each check chooses whether override tags apply
+/// to it, by checking `not_manually_verified_commits` or `commits`.
+pub trait CommitCheck<G: Git, P: Gpg> {
+    fn name(&self) -> &str;
+    fn is_enabled(&self, context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>>;
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>>;
+}
+
+pub struct CommitChecks<G: Git, P: Gpg> {
+    checks: Vec<Box<dyn CommitCheck<G, P>>>,
+}
+
+impl<G: Git, P: Gpg> Default for CommitChecks<G, P> {
+    fn default() -> Self {
+        CommitChecks { checks: Vec::new() }
+    }
+}
+
+impl<G: Git, P: Gpg> CommitChecks<G, P> {
+    /// The checks that ship with Captain Git Hook. They're ordered so
+    /// that the cheap ones run first, and the ones that need the
+    /// keyserver and GPG run last.
+    pub fn with_builtin_checks() -> Self {
+        let mut checks = CommitChecks::default();
+        checks.register(EmailAddressesCheck);
+        checks.register(RebasedCheck);
+        checks.register(TimestampsCheck);
+        checks.register(DifferentAuthorsCheck);
+        checks.register(IdentitiesCheck);
+        checks.register(ApprovalsCheck);
+        checks.register(CodeOwnersCheck);
+        checks.register(CommitSignaturesCheck);
+        checks
+    }
+
+    /// Checks run in the order that they were registered.
+    pub fn register(&mut self, check: impl CommitCheck<G, P> + 'static) {
+        self.checks.push(Box::new(check));
+    }
+
+    pub fn check_names(&self) -> Vec<&str> {
+        self.checks.iter().map(|c| c.name()).collect()
+    }
+
+    pub(crate) fn enabled(
+        &self,
+        context: &CommitCheckContext<'_, G, P>,
+    ) -> Result<Vec<&dyn CommitCheck<G, P>>, Box<dyn Error>> {
+        let mut enabled = Vec::new();
+        for check in &self.checks {
+            if check.is_enabled(context)? {
+                enabled.push(check.as_ref());
+            }
+        }
+        Ok(enabled)
+    }
+}
+
+pub struct EmailAddressesCheck;
+
+impl<G: Git, P: Gpg> CommitCheck<G, P> for EmailAddressesCheck {
+    fn name(&self) -> &str {
+        "email addresses"
+    }
+
+    fn is_enabled(&self, context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>> {
+        Ok(context.config.verify_email_addresses)
+    }
+
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        let check = CommitCheck::<G, P>::name(self);
+        let not_manually_verified_commits = context.not_manually_verified_commits()?;
+        let commits = context
+            .cache
+            .uncached(check, &not_manually_verified_commits);
+        let result = verify_email_addresses(context.config, context.keyring, &commits)?;
+        if result.is_ok() {
+            context.cache.record_passes(check, &commits);
+            context.audit_trail.record_all(&commits, check, "passed");
+            context.record_earlier_passes(check, &not_manually_verified_commits, &commits);
+        }
+        Ok(result)
+    }
+}
+
+pub struct RebasedCheck;
+
+impl<G: Git, P: Gpg> CommitCheck<G, P> for RebasedCheck {
+    fn name(&self) -> &str {
+        "rebased"
+    }
+
+    fn is_enabled(&self, context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>> {
+        Ok(context.config.verify_rebased)
+    }
+
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        let result = verify_rebased::<G>(
+            context.commits,
+            context.git,
+            context.ref_update,
+            &context.config.override_tag_pattern,
+        )?;
+        if result.is_ok() {
+            context.audit_trail.record_all(
+                context.commits,
+                CommitCheck::<G, P>::name(self),
+                "passed",
+            );
+        }
+        Ok(result)
+    }
+}
+
+pub struct TimestampsCheck;
+
+impl<G: Git, P: Gpg> CommitCheck<G, P> for TimestampsCheck {
+    fn name(&self) -> &str {
+        "timestamps"
+    }
+
+    fn is_enabled(&self, context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>> {
+        Ok(context.config.max_future_skew_seconds.is_some()
+            || context.config.max_parent_skew_seconds.is_some())
+    }
+
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        let result = verify_timestamps(context.commits, context.git, context.config)?;
+        if result.is_ok() {
+            context.audit_trail.record_all(
+                context.commits,
+                CommitCheck::<G, P>::name(self),
+                "passed",
+            );
+        }
+        Ok(result)
+    }
+}
+
+pub struct DifferentAuthorsCheck;
+
+impl<G: Git, P: Gpg> CommitCheck<G, P> for DifferentAuthorsCheck {
+    fn name(&self) -> &str {
+        "different authors"
+    }
+
+    fn is_enabled(&self, context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>> {
+        Ok(context.config.verify_different_authors)
+    }
+
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        let result = verify_different_authors::<G, _>(
+            context.commits,
+            context.git,
+            &context.gpg_with_deadline(),
+            context.config,
+            context.keyring,
+            context.ref_update,
+        )?;
+        if result.is_ok() {
+            context.audit_trail.record_all(
+                context.commits,
+                CommitCheck::<G, P>::name(self),
+                "passed",
+            );
+        }
+        Ok(result)
+    }
+}
+
+pub struct IdentitiesCheck;
+
+impl<G: Git, P: Gpg> CommitCheck<G, P> for IdentitiesCheck {
+    fn name(&self) -> &str {
+        "identities"
+    }
+
+    fn is_enabled(&self, context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>> {
+        Ok(context.config.verify_signer_is_committer
+            || context.config.verify_identity_names
+            || context.config.verify_author_is_committer)
+    }
+
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        let commits = context.not_manually_verified_commits()?;
+        let result = verify_identities::<G, _>(
+            &commits,
+            context.git,
+            &context.gpg_with_deadline(),
+            context.config,
+            context.keyring,
+            context.deadline,
+        )?;
+        if result.is_ok() {
+            context
+                .audit_trail
+                .record_all(&commits, CommitCheck::<G, P>::name(self), "passed");
+        }
+        Ok(result)
+    }
+}
+
+pub struct ApprovalsCheck;
+
+impl<G: Git, P: Gpg> CommitCheck<G, P> for ApprovalsCheck {
+    fn name(&self) -> &str {
+        "approvals"
+    }
+
+    fn is_enabled(&self, context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>> {
+        Ok(context.config.required_approvals.is_required())
+    }
+
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        let result = verify_approvals::<G, _>(
+            context.commits,
+            context.git,
+            &context.gpg_with_deadline(),
+            context.config,
+            context.keyring,
+            context.ref_update,
+        )?;
+        if result.is_ok() {
+            context.audit_trail.record_all(
+                context.commits,
+                CommitCheck::<G, P>::name(self),
+                "passed",
+            );
+        }
+        Ok(result)
+    }
+}
+
+pub struct CodeOwnersCheck;
+
+impl<G: Git, P: Gpg> CommitCheck<G, P> for CodeOwnersCheck {
+    fn name(&self) -> &str {
+        "code owners"
+    }
+
+    fn is_enabled(&self, context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>> {
+        Ok(context.config.code_owners_file.is_some())
+    }
+
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        let result = match &context.config.code_owners_file {
+            Some(code_owners_file) => verify_code_owners::<G, _>(
+                context.commits,
+                context.git,
+                &context.gpg_with_deadline(),
+                context.config,
+                code_owners_file,
+                context.keyring,
+                context.ref_update,
+            )?,
+            None => PolicyResult::Ok,
+        };
+        if result.is_ok() {
+            context.audit_trail.record_all(
+                context.commits,
+                CommitCheck::<G, P>::name(self),
+                "passed",
+            );
+        }
+        Ok(result)
+    }
+}
+
+pub struct CommitSignaturesCheck;
+
+impl<G: Git, P: Gpg> CommitCheck<G, P> for CommitSignaturesCheck {
+    fn name(&self) -> &str {
+        "commit signatures"
+    }
+
+    fn is_enabled(&self, context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>> {
+        Ok(context.config.verify_commit_signatures)
+    }
+
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        let check = CommitCheck::<G, P>::name(self);
+        let not_manually_verified_commits = context.not_manually_verified_commits()?;
+        let commits = context
+            .cache
+            .uncached(check, &not_manually_verified_commits);
+        let result = verify_commit_signatures::<G, _>(
+            context.git,
+            &context.gpg_with_deadline(),
+            &commits,
+            context.keyring,
+            context.config.verify_signing_key_validity,
+            check,
+            context.audit_trail,
+            context.deadline,
+        )?;
+        if result.is_ok() {
+            context.cache.record_passes(check, &commits);
+            context.record_earlier_passes(check, &not_manually_verified_commits, &commits);
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,260 @@
+use crate::config::{Config, ExternalPolicyConfig};
+use crate::error::CapnError;
+use crate::git::{Commit, Git};
+use crate::gpg::Gpg;
+use crate::policies::policy_result::PolicyResult;
+use crate::policies::registry::{Hook, Policy, PolicyReport, RefUpdateContext};
+use crate::reference_update::ReferenceUpdate;
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::prelude::*;
+use std::process::*;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::*;
+
+/// Runs the `[[external_policy]]` executables from the .capn file.
+///
+/// Each executable is sent an `ExternalPolicyInput` as JSON on stdin,
+/// and must write an `ExternalPolicyVerdict` as JSON to stdout and
+/// exit successfully. Crashes, timeouts and unreadable verdicts are
+/// errors, so they reject the update.
+pub struct ExternalPolicies;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExternalPolicyInput {
+    pub hook: String,
+    pub ref_update: ExternalRefUpdate,
+    pub commits: Vec<ExternalCommit>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExternalRefUpdate {
+    pub ref_name: String,
+    pub old_commit_id: Option<String>,
+    pub new_commit_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExternalCommit {
+    pub id: String,
+    pub author_email: Option<String>,
+    pub committer_email: Option<String>,
+    pub parents: Vec<String>,
+    pub is_merge_commit: bool,
+    pub is_identical_tree_to_any_parent: bool,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExternalPolicyVerdict {
+    #[serde(default)]
+    pub violations: Vec<ExternalPolicyViolation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExternalPolicyViolation {
+    #[serde(default)]
+    pub commit: Option<String>,
+    pub message: String,
+}
+
+impl<G: Git, P: Gpg> Policy<G, P> for ExternalPolicies {
+    type Config = Vec<ExternalPolicyConfig>;
+
+    fn name(&self) -> &str {
+        "external_policy"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<Vec<ExternalPolicyConfig>>, Box<dyn Error>> {
+        if config.external_policy.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(config.external_policy.clone()))
+        }
+    }
+
+    fn check(
+        &self,
+        config: &Vec<ExternalPolicyConfig>,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let input = external_policy_input(context.git, context.hook, context.ref_update)?;
+        let input = serde_json::to_string(&input)?;
+
+        for policy in config {
+            info!("Executing external policy: {}", policy.name);
+            let verdict = run_external_policy(policy, &input)?;
+            if !verdict.violations.is_empty() {
+                let violations = verdict
+                    .violations
+                    .into_iter()
+                    .map(|v| match v.commit {
+                        Some(commit) => format!("{}: {}", commit, v.message),
+                        None => v.message,
+                    })
+                    .collect::<Vec<_>>();
+                for violation in &violations {
+                    error!("External policy {} failed: {}", policy.name, violation);
+                }
+                return Ok(PolicyReport::new(
+                    Policy::<G, P>::name(self),
+                    PolicyResult::ExternalPolicyViolations(policy.name.clone(), violations),
+                ));
+            }
+            info!("External policy {} passed", policy.name);
+        }
+        Ok(PolicyReport::new(
+            Policy::<G, P>::name(self),
+            PolicyResult::Ok,
+        ))
+    }
+}
+
+pub(crate) fn external_policy_input<G: Git>(
+    git: &G,
+    hook: Hook,
+    ref_update: &ReferenceUpdate,
+) -> Result<ExternalPolicyInput, Box<dyn Error>> {
+    let commits = match ref_update.new_commit_id() {
+        Some(new_commit_id) => git.find_new_commits(
+            &ref_update.old_commit_id().into_iter().collect::<Vec<_>>(),
+            &[new_commit_id],
+            &None,
+        )?,
+        None => Vec::new(),
+    };
+    Ok(ExternalPolicyInput {
+        hook: match hook {
+            Hook::PrePush => "pre-push",
+            Hook::PreReceive => "pre-receive",
+        }
+        .to_string(),
+        ref_update: ExternalRefUpdate {
+            ref_name: ref_update.ref_name().to_string(),
+            old_commit_id: ref_update.old_commit_id().map(|id| id.to_string()),
+            new_commit_id: ref_update.new_commit_id().map(|id| id.to_string()),
+        },
+        commits: commits.iter().map(external_commit).collect(),
+    })
+}
+
+fn external_commit(commit: &Commit) -> ExternalCommit {
+    ExternalCommit {
+        id: commit.id.to_string(),
+        author_email: commit.author_email.clone(),
+        committer_email: commit.committer_email.clone(),
+        parents: commit.parents.iter().map(|id| id.to_string()).collect(),
+        is_merge_commit: commit.is_merge_commit,
+        is_identical_tree_to_any_parent: commit.is_identical_tree_to_any_parent,
+        tags: commit.tags.iter().map(|t| t.name.clone()).collect(),
+    }
+}
+
+fn run_external_policy(
+    policy: &ExternalPolicyConfig,
+    input: &str,
+) -> Result<ExternalPolicyVerdict, Box<dyn Error>> {
+    let start = Instant::now();
+    let timeout = Duration::from_millis(policy.timeout_ms);
+
+    let mut child = Command::new(&policy.command)
+        .args(&policy.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            CapnError::new(format!(
+                "Failed to start external policy {} ({}): {}",
+                policy.name, policy.command, e
+            ))
+        })?;
+
+    // Stdin and stdout are handled on their own threads, so that a
+    // plugin that doesn't read its input, or writes a lot of output,
+    // can't block the timeout. The threads are never joined: if the
+    // plugin leaves a child process running that holds on to its
+    // pipes, they would block until that process exits.
+    let mut stdin = child.stdin.take().expect("stdin was piped");
+    let input = input.to_string();
+    thread::spawn(move || {
+        // The plugin is allowed to exit without reading its input
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    let mut stdout = child.stdout.take().expect("stdout was piped");
+    let (stdout_sender, stdout_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout_sender.send(stdout.read_to_end(&mut output).map(|_| output));
+    });
+    let mut stderr = child.stderr.take().expect("stderr was piped");
+    let (stderr_sender, stderr_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stderr.read_to_end(&mut output);
+        let _ = stderr_sender.send(output);
+    });
+
+    let timed_out = || {
+        CapnError::new(format!(
+            "External policy {} did not finish within {} ms",
+            policy.name, policy.timeout_ms
+        ))
+    };
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Box::new(timed_out()));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let stdout = match stdout_receiver.recv_timeout(timeout.saturating_sub(start.elapsed())) {
+        Ok(stdout) => stdout?,
+        Err(RecvTimeoutError::Timeout) => return Err(Box::new(timed_out())),
+        Err(RecvTimeoutError::Disconnected) => {
+            return Err(Box::new(CapnError::new(
+                "Failed to read external policy output",
+            )))
+        }
+    };
+    let stderr = stderr_receiver
+        .recv_timeout(timeout.saturating_sub(start.elapsed()))
+        .unwrap_or_default();
+    debug!(
+        "External policy {} completed in {} ms. Stderr: {}",
+        policy.name,
+        start.elapsed().as_millis(),
+        String::from_utf8_lossy(&stderr)
+    );
+
+    if !status.success() {
+        return Err(Box::new(CapnError::new(format!(
+            "External policy {} crashed ({}). Stderr: {}",
+            policy.name,
+            status,
+            String::from_utf8_lossy(&stderr).trim()
+        ))));
+    }
+
+    serde_json::from_slice(&stdout).map_err(|e| {
+        CapnError::new(format!(
+            "External policy {} returned an invalid verdict: {}",
+            policy.name, e
+        ))
+        .into()
+    })
+}
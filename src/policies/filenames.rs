@@ -0,0 +1,75 @@
+use std::str;
+use unicode_normalization::is_nfc;
+
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const WINDOWS_RESERVED_CHARACTERS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+/// Why a path can't be checked out on Windows, if it can't. Windows
+/// doesn't allow some characters in names, removes dots and spaces
+/// from the end of names, and treats some names as devices, even with
+/// an extension, like `aux.txt`.
+pub fn windows_problem(path: &str) -> Option<String> {
+    path.split('/').find_map(|name| {
+        if let Some(c) = name
+            .chars()
+            .find(|c| WINDOWS_RESERVED_CHARACTERS.contains(c) || c.is_ascii_control())
+        {
+            return Some(format!(
+                "{:?} contains {:?}, which Windows does not allow",
+                name, c
+            ));
+        }
+        if name.ends_with('.') || name.ends_with(' ') {
+            return Some(format!(
+                "{:?} ends with a dot or space, which Windows removes",
+                name
+            ));
+        }
+        let stem = name.split('.').next().unwrap_or_default().trim_end();
+        if WINDOWS_RESERVED_NAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+        {
+            return Some(format!("{:?} is a reserved name on Windows", name));
+        }
+        None
+    })
+}
+
+/// Why a path isn't portable Unicode, if it isn't. macOS normalises
+/// names, so names that aren't NFC can turn into a different file when
+/// they are checked out and committed again.
+pub fn unicode_problem(path: &[u8]) -> Option<String> {
+    match str::from_utf8(path) {
+        Err(_) => Some("is not valid UTF-8".to_string()),
+        Ok(path) if !is_nfc(path) => Some("is not NFC normalised".to_string()),
+        Ok(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_windows_problems() {
+        assert_eq!(windows_problem("src/lib.rs"), None);
+        assert_eq!(windows_problem("docs/auxiliary.md"), None);
+        assert!(windows_problem("aux.txt").is_some());
+        assert!(windows_problem("drivers/COM1/readme").is_some());
+        assert!(windows_problem("notes/what?.txt").is_some());
+        assert!(windows_problem("notes/trailing.").is_some());
+        assert!(windows_problem("tab\there").is_some());
+    }
+
+    #[test]
+    fn finds_unicode_problems() {
+        assert_eq!(unicode_problem("caf\u{e9}.txt".as_bytes()), None);
+        assert!(unicode_problem("cafe\u{301}.txt".as_bytes()).is_some());
+        assert!(unicode_problem(b"caf\xe9.txt").is_some());
+    }
+}
@@ -0,0 +1,183 @@
+use crate::error::CapnError;
+
+use globset::{GlobBuilder, GlobMatcher};
+use std::error::Error;
+use std::str;
+
+/// Git LFS pointer files are always smaller than this.
+pub const MAX_POINTER_SIZE: usize = 1024;
+
+const POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// The paths that .gitattributes files send through the Git LFS
+/// filter, like `*.zip filter=lfs diff=lfs merge=lfs -text`. Like Git,
+/// the last line that sets or unsets the filter for a path wins, and
+/// files in subdirectories come after the ones above them.
+#[derive(Default)]
+pub struct LfsAttributes {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    /// The directory of the .gitattributes file, which its patterns
+    /// are relative to, or "" for the root.
+    directory: String,
+    matcher: GlobMatcher,
+    is_lfs: bool,
+}
+
+impl LfsAttributes {
+    /// Adds the rules from the .gitattributes file in `directory`, or
+    /// "" for the root.
+    /// Files have to be added from the root down, so that deeper
+    /// files take precedence.
+    pub fn add(&mut self, directory: &str, file_contents: &str) -> Result<(), Box<dyn Error>> {
+        let rules = file_contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter(|line| !line.starts_with("[attr]"))
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                let pattern = words.next().expect("Line is not empty");
+                let is_lfs = words.rev().find_map(|attribute| match attribute {
+                    "-filter" | "!filter" => Some(false),
+                    _ => attribute
+                        .strip_prefix("filter=")
+                        .map(|filter| filter == "lfs"),
+                })?;
+                Some((pattern, is_lfs))
+            })
+            .map(|(pattern, is_lfs)| {
+                Ok(Rule {
+                    directory: directory.to_string(),
+                    matcher: path_matcher(pattern).map_err(|e| {
+                        CapnError::new(format!("Invalid .gitattributes pattern {}: {}", pattern, e))
+                    })?,
+                    is_lfs,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        self.rules.extend(rules);
+        Ok(())
+    }
+
+    pub fn is_tracked(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                relative_path(&rule.directory, path)
+                    .is_some_and(|relative| rule.matcher.is_match(relative))
+            })
+            .is_some_and(|rule| rule.is_lfs)
+    }
+}
+
+fn relative_path<'a>(directory: &str, path: &'a str) -> Option<&'a str> {
+    if directory.is_empty() {
+        Some(path)
+    } else {
+        path.strip_prefix(directory)?.strip_prefix('/')
+    }
+}
+
+// Unlike .gitignore patterns, .gitattributes patterns don't match the
+// files inside a directory that they match.
+fn path_matcher(pattern: &str) -> Result<GlobMatcher, globset::Error> {
+    let pattern = if pattern.contains('/') {
+        pattern.trim_start_matches('/').to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+    Ok(GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()?
+        .compile_matcher())
+}
+
+/// Whether a file is a Git LFS pointer, rather than the content that
+/// it points to. Pointers start with the spec version, and have the
+/// SHA-256 and size of the content.
+pub fn is_lfs_pointer(contents: &[u8]) -> bool {
+    if contents.len() >= MAX_POINTER_SIZE {
+        return false;
+    }
+    let mut lines = match str::from_utf8(contents) {
+        Ok(text) => text.lines(),
+        Err(_) => return false,
+    };
+    if lines.next() != Some(POINTER_VERSION) {
+        return false;
+    }
+
+    let mut has_oid = false;
+    let mut has_size = false;
+    for line in lines {
+        match line.split_once(' ') {
+            Some(("oid", oid)) => {
+                has_oid = oid.strip_prefix("sha256:").is_some_and(|hash| {
+                    hash.len() == 64
+                        && hash
+                            .chars()
+                            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+                })
+            }
+            Some(("size", size)) => has_size = size.parse::<u64>().is_ok(),
+            Some(_) => {}
+            None => return false,
+        }
+    }
+    has_oid && has_size
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const POINTER: &str = "version https://git-lfs.github.com/spec/v1\noid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\nsize 12345\n";
+
+    #[test]
+    fn last_matching_line_wins() {
+        let mut attributes = LfsAttributes::default();
+        attributes
+            .add(
+                "",
+                "# Binaries\n*.zip filter=lfs diff=lfs merge=lfs -text\n*.txt text\nsmall/*.zip -filter\n/assets/*.png filter=lfs\n",
+            )
+            .unwrap();
+        assert!(attributes.is_tracked("build.zip"));
+        assert!(attributes.is_tracked("out/build.zip"));
+        assert!(!attributes.is_tracked("small/build.zip"));
+        assert!(attributes.is_tracked("assets/logo.png"));
+        assert!(!attributes.is_tracked("docs/assets/logo.png"));
+        assert!(!attributes.is_tracked("readme.txt"));
+    }
+
+    #[test]
+    fn files_in_subdirectories_override_the_ones_above() {
+        let mut attributes = LfsAttributes::default();
+        attributes.add("", "*.zip filter=lfs\n").unwrap();
+        attributes
+            .add("vendor", "*.zip -filter\n/assets/*.png filter=lfs\n")
+            .unwrap();
+        assert!(attributes.is_tracked("build.zip"));
+        assert!(!attributes.is_tracked("vendor/build.zip"));
+        assert!(!attributes.is_tracked("vendor/lib/build.zip"));
+        assert!(attributes.is_tracked("vendored/build.zip"));
+        assert!(attributes.is_tracked("vendor/assets/logo.png"));
+        assert!(!attributes.is_tracked("assets/logo.png"));
+    }
+
+    #[test]
+    fn recognises_pointer_files() {
+        assert!(is_lfs_pointer(POINTER.as_bytes()));
+        assert!(!is_lfs_pointer(b"PK\x03\x04 not a pointer"));
+        assert!(!is_lfs_pointer(
+            POINTER.replace("size 12345", "size big").as_bytes()
+        ));
+        assert!(!is_lfs_pointer(
+            POINTER.replace("sha256:4d7a", "sha256:").as_bytes()
+        ));
+    }
+}
@@ -9,11 +9,43 @@ pub enum PolicyResult {
     UnsignedCommit(Oid),
     UnsignedMergeCommit(Oid),
     NotEnoughAuthors(Oid),
-    InvalidAuthorEmail(Oid, String),
+    NotEnoughApprovals(Oid, String, Vec<String>),
+    MissingCodeOwnerApprovals(Oid, Vec<String>),
+    InvalidAuthorEmail(Oid, String, String),
     MissingAuthorEmail(Oid),
-    InvalidCommitterEmail(Oid, String),
+    InvalidCommitterEmail(Oid, String, String),
     MissingCommitterEmail(Oid),
     NotRebased(Oid),
+    MissingSignOff(Oid, String),
+    SignerIsNotCommitter(Oid, String, String),
+    NameDoesNotMatchKeyring(Oid, String, String),
+    AuthorIsNotCommitter(Oid),
+    TooManyParents(Oid, usize, u8),
+    CommitDatedInFuture(Oid, String),
+    CommittedBeforeParent(Oid, Oid),
+    SignedOutsideKeyValidity(Oid, String),
+    MergeCommitsNotAllowed(String, Vec<Oid>),
+    RejectedSubject(Oid, String),
+    NotFastForward(String, Oid),
+    TagDeleted(String),
+    TagMoved(String),
+    InvalidTagName(String, String),
+    LightweightTag(String),
+    UnsignedTag(String),
+    TagSignerNotInGroup(String, String),
+    TagNotOnMainline(String, Oid),
+    FileTooLarge(Oid, String, usize, u64),
+    BinaryFileNotInLfs(Oid, String),
+    InvalidLfsPointer(Oid, String),
+    FilenameCaseCollision(Oid, String, String),
+    InvalidFilename(Oid, String, String),
+    PathTooLong(Oid, String, usize, usize),
+    SubmoduleNotAllowed(Oid, String),
+    SubmoduleUrlChanged(Oid, String, String),
+    SymlinkEscapesRepo(Oid, String, String),
+    TimeBudgetExceeded(Vec<String>, Vec<String>, usize),
+    PolicyViolation(String, String),
+    ExternalPolicyViolations(String, Vec<String>),
 }
 
 impl PolicyResult {
@@ -33,10 +65,7 @@ impl PolicyResult {
         }
     }
     pub fn is_ok(&self) -> bool {
-        match self {
-            PolicyResult::Ok => true,
-            _ => false,
-        }
+        matches!(self, PolicyResult::Ok)
     }
     pub fn is_err(&self) -> bool {
         !self.is_ok()
@@ -52,11 +81,43 @@ impl fmt::Display for PolicyResult {
             UnsignedCommit(id) => write!(f, "Commit does not have a valid GPG signature: {}", id),
             UnsignedMergeCommit(id) => write!(f, "Commit does not have a valid GPG signature: {}. This is a merge commit, please note that if there were conflicts that needed to be resolved then the commit needs a signature.", id),
             NotEnoughAuthors(id) => write!(f, "Merge commit needs to have multiple authors in the branch: {}", id),
-            InvalidAuthorEmail(id, email) => write!(f, "Commit has an invalid author email ({}): {}", email, id),
+            NotEnoughApprovals(id, required, approvers) => write!(f, "Update to the mainline needs signed approvals from {} in the team, not including its author: {}. Approved by: {:?}", required, id, approvers),
+            MissingCodeOwnerApprovals(id, owners) => write!(f, "Update to the mainline changes paths that need a signed approval from their code owners: {}. Missing approval from: {}", id, owners.join(", ")),
+            InvalidAuthorEmail(id, email, rule) => write!(f, "Commit has an invalid author email ({}, {}): {}", email, rule, id),
             MissingAuthorEmail(id) => write!(f, "Commit does not have an author email: {}", id),
-            InvalidCommitterEmail(id, email) => write!(f, "Commit has an invalid committer email ({}): {}", email, id),
+            InvalidCommitterEmail(id, email, rule) => write!(f, "Commit has an invalid committer email ({}, {}): {}", email, rule, id),
             MissingCommitterEmail(id) => write!(f, "Commit does not have a committer email: {}", id),
-            NotRebased(id) => write!(f, "Merge commit needs to be rebased on the mainline before it can be merged: {}", id)
+            NotRebased(id) => write!(f, "Merge commit needs to be rebased on the mainline before it can be merged: {}", id),
+            MissingSignOff(id, author) => write!(f, "Commit does not have a Signed-off-by trailer matching its author ({}): {}", author, id),
+            CommitDatedInFuture(id, time) => write!(f, "Commit is dated in the future ({}), check the clock of the machine that made it: {}", time, id),
+            CommittedBeforeParent(id, parent) => write!(f, "Commit was committed before its parent {}: {}", parent, id),
+            SignedOutsideKeyValidity(id, fingerprint) => write!(f, "Commit is dated outside of when its signing key {} is valid: {}", fingerprint, id),
+            SignerIsNotCommitter(id, committer, signer) => write!(f, "Commit was signed by the key of {}, but committed by {}: {}", signer, committer, id),
+            NameDoesNotMatchKeyring(id, name, expected) => write!(f, "Commit has the name {:?}, but the Team Fingerprints file has {:?} for that email address: {}", name, expected, id),
+            AuthorIsNotCommitter(id) => write!(f, "Commit needs to be committed by its author, unless it is a cherry-pick: {}", id),
+            TooManyParents(id, parents, max) => write!(f, "Commit has {} parents, but at most {} are allowed: {}", parents, max, id),
+            MergeCommitsNotAllowed(ref_name, ids) => write!(f, "Merge commits can not be pushed to {}, which needs a linear history: {}", ref_name, ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ")),
+            RejectedSubject(id, subject) => write!(f, "Commit subject is not allowed on the mainline ({:?}), it may need to be squashed or reworded: {}", subject, id),
+            NotFastForward(ref_name, id) => write!(f, "Updates to {} need to be fast forwards: {}", ref_name, id),
+            TagDeleted(tag) => write!(f, "Tags can not be deleted: {}", tag),
+            TagMoved(tag) => write!(f, "Tags can not be moved to a different commit: {}", tag),
+            InvalidTagName(tag, pattern) => write!(f, "Tag name does not match the pattern {}: {}", pattern, tag),
+            LightweightTag(tag) => write!(f, "Tag needs to be an annotated tag, not a lightweight tag: {}", tag),
+            UnsignedTag(tag) => write!(f, "Tag does not have a valid GPG signature from the team: {}", tag),
+            TagSignerNotInGroup(tag, group) => write!(f, "Tag needs to be signed by someone in @{}: {}", group, tag),
+            TagNotOnMainline(tag, id) => write!(f, "Tag points to a commit that is not on a mainline ({}): {}", id, tag),
+            FileTooLarge(id, path, size, max) => write!(f, "File {} is {} bytes, but files can be at most {} bytes: {}", path, size, max, id),
+            BinaryFileNotInLfs(id, path) => write!(f, "Binary file {} needs to be stored in Git LFS: {}", path, id),
+            InvalidLfsPointer(id, path) => write!(f, "File {} is tracked by Git LFS in .gitattributes, but is not an LFS pointer. Check that Git LFS is installed: {}", path, id),
+            FilenameCaseCollision(id, path, other) => write!(f, "Path {} differs from {} only by case, so they are the same file on Windows and macOS: {}", path, other, id),
+            InvalidFilename(id, path, reason) => write!(f, "Path {} can not be checked out on every platform, {}: {}", path, reason, id),
+            PathTooLong(id, path, length, max) => write!(f, "Path {} is {} characters, but paths can be at most {} characters: {}", path, length, max, id),
+            SubmoduleNotAllowed(id, path) => write!(f, "Submodule {} was added or changed, but submodules are not allowed at that path: {}", path, id),
+            SubmoduleUrlChanged(id, path, url) => write!(f, "Submodule {} was pointed at {}, but submodules are not allowed at that path: {}", path, url, id),
+            SymlinkEscapesRepo(id, path, target) => write!(f, "Symlink {} points to {}, which is outside the repo: {}", path, target, id),
+            TimeBudgetExceeded(completed, not_run, commits_verified) => write!(f, "Verification did not complete within the configured time budget. Completed checks: {:?}. Checks not run: {:?}. Commits verified by {:?} before it ran out of time: {}", completed, not_run, not_run.first().map(String::as_str).unwrap_or_default(), commits_verified),
+            PolicyViolation(policy, reason) => write!(f, "Policy {} failed: {}", policy, reason),
+            ExternalPolicyViolations(policy, violations) => write!(f, "External policy {} failed:\n{}", policy, violations.join("\n")),
         }
     }
 }
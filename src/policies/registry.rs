@@ -0,0 +1,465 @@
+use crate::config::{
+    Config, LinearHistoryConfig, VerifyFileSizesConfig, VerifyFilenamesConfig,
+    VerifyGitCommitsConfig, VerifyParentCountConfig, VerifySignOffConfig, VerifySubjectsConfig,
+    VerifySubmodulesAndSymlinksConfig, VerifyTagsConfig,
+};
+use crate::git::Git;
+use crate::gpg::Gpg;
+use crate::policies::audit::PendingAuditNotes;
+use crate::policies::commit_checks::CommitChecks;
+use crate::policies::external::ExternalPolicies;
+use crate::policies::policy_result::PolicyResult;
+use crate::policies::wasm::WasmPolicies;
+use crate::policies::{
+    verify_file_sizes, verify_filenames, verify_git_commits_with_audit_notes,
+    verify_git_commits_with_checks, verify_linear_history, verify_parent_count, verify_sign_off,
+    verify_subjects, verify_submodules_and_symlinks, verify_tags,
+};
+use crate::reference_update::ReferenceUpdate;
+
+use std::error::Error;
+use std::time::Instant;
+
+use log::*;
+
+/// The hooks that check reference updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    PrePush,
+    PreReceive,
+}
+
+/// Everything a policy gets to look at while checking a single
+/// reference update.
+pub struct RefUpdateContext<'a, G: Git, P: Gpg> {
+    pub hook: Hook,
+    pub git: &'a G,
+    pub gpg: &'a P,
+    pub config: &'a Config,
+    pub ref_update: &'a ReferenceUpdate,
+    /// Where to put audit notes for the update, on hooks that keep
+    /// them. They're only written once the whole push is accepted.
+    pub audit_notes: Option<&'a PendingAuditNotes>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PolicyReport {
+    pub policy: String,
+    pub result: PolicyResult,
+}
+
+impl PolicyReport {
+    pub fn new(policy: impl Into<String>, result: PolicyResult) -> PolicyReport {
+        PolicyReport {
+            policy: policy.into(),
+            result,
+        }
+    }
+}
+
+/// A policy that checks reference updates. Policies are turned on by
+/// having their config in the .capn file, so `config` returns `None`
+/// if the policy shouldn't run.
+///
+/// Policies from other crates can read their own section of the .capn
+/// file with `Config::section`.
+pub trait Policy<G: Git, P: Gpg> {
+    type Config;
+
+    fn name(&self) -> &str;
+    fn hooks(&self) -> &[Hook];
+    fn config(&self, config: &Config) -> Result<Option<Self::Config>, Box<dyn Error>>;
+    fn check(
+        &self,
+        config: &Self::Config,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>>;
+}
+
+// Hides each policy's config type, so that policies with different
+// config types can be stored together in the registry.
+trait RegisteredPolicy<G: Git, P: Gpg> {
+    fn name(&self) -> &str;
+    fn hooks(&self) -> &[Hook];
+    fn check_if_configured(
+        &self,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<Option<PolicyReport>, Box<dyn Error>>;
+}
+
+impl<G: Git, P: Gpg, T: Policy<G, P>> RegisteredPolicy<G, P> for T {
+    fn name(&self) -> &str {
+        Policy::name(self)
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        Policy::hooks(self)
+    }
+
+    fn check_if_configured(
+        &self,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<Option<PolicyReport>, Box<dyn Error>> {
+        match self.config(context.config)? {
+            Some(config) => self.check(&config, context).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+pub struct PolicyRegistry<G: Git, P: Gpg> {
+    policies: Vec<Box<dyn RegisteredPolicy<G, P>>>,
+}
+
+impl<G: Git, P: Gpg> Default for PolicyRegistry<G, P> {
+    fn default() -> Self {
+        PolicyRegistry {
+            policies: Vec::new(),
+        }
+    }
+}
+
+impl<G: Git + 'static, P: Gpg + 'static> PolicyRegistry<G, P> {
+    /// A registry with all of the policies that ship with Captain Git
+    /// Hook.
+    pub fn with_builtin_policies() -> Self {
+        let mut registry = PolicyRegistry::default();
+        registry.register(VerifyGitCommitsPolicy::default());
+        registry.register(VerifyTagsPolicy);
+        registry.register(VerifyFileSizesPolicy);
+        registry.register(VerifyFilenamesPolicy);
+        registry.register(VerifySignOffPolicy);
+        registry.register(VerifyLinearHistoryPolicy);
+        registry.register(VerifySubjectsPolicy);
+        registry.register(VerifyParentCountPolicy);
+        registry.register(VerifySubmodulesAndSymlinksPolicy);
+        registry.register(ExternalPolicies);
+        registry.register(WasmPolicies);
+        registry
+    }
+}
+
+impl<G: Git, P: Gpg> PolicyRegistry<G, P> {
+    /// Policies run in the order that they were registered.
+    pub fn register(&mut self, policy: impl Policy<G, P> + 'static) {
+        self.policies.push(Box::new(policy));
+    }
+
+    pub fn policy_names(&self) -> Vec<&str> {
+        self.policies.iter().map(|p| p.name()).collect()
+    }
+
+    /// Runs the configured policies for the context's hook, stopping
+    /// at the first one that fails.
+    pub fn check(
+        &self,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        for policy in self
+            .policies
+            .iter()
+            .filter(|p| p.hooks().contains(&context.hook))
+        {
+            let start = Instant::now();
+            match policy.check_if_configured(context)? {
+                Some(report) => {
+                    debug!(
+                        "Policy {} completed in {} ms: {}",
+                        report.policy,
+                        start.elapsed().as_millis(),
+                        report.result
+                    );
+                    if report.result.is_err() {
+                        return Ok(report.result);
+                    }
+                }
+                None => debug!("Policy {} is not configured, skipping", policy.name()),
+            }
+        }
+        Ok(PolicyResult::Ok)
+    }
+}
+
+/// Runs the registered commit checks on the new commits in an update.
+/// Downstream crates can register their own commit checks alongside
+/// the builtin ones with `VerifyGitCommitsPolicy::new`.
+pub struct VerifyGitCommitsPolicy<G: Git, P: Gpg> {
+    checks: CommitChecks<G, P>,
+}
+
+impl<G: Git, P: Gpg> VerifyGitCommitsPolicy<G, P> {
+    pub fn new(checks: CommitChecks<G, P>) -> Self {
+        VerifyGitCommitsPolicy { checks }
+    }
+}
+
+impl<G: Git, P: Gpg> Default for VerifyGitCommitsPolicy<G, P> {
+    fn default() -> Self {
+        VerifyGitCommitsPolicy::new(CommitChecks::with_builtin_checks())
+    }
+}
+
+impl<G: Git, P: Gpg> Policy<G, P> for VerifyGitCommitsPolicy<G, P> {
+    type Config = VerifyGitCommitsConfig;
+
+    fn name(&self) -> &str {
+        "verify_git_commits"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<VerifyGitCommitsConfig>, Box<dyn Error>> {
+        Ok(config.verify_git_commits.clone())
+    }
+
+    fn check(
+        &self,
+        config: &VerifyGitCommitsConfig,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let result = match context.audit_notes {
+            Some(audit_notes) => verify_git_commits_with_audit_notes(
+                &self.checks,
+                context.git,
+                context.gpg,
+                config,
+                context.ref_update,
+                audit_notes,
+            )?,
+            None => verify_git_commits_with_checks(
+                &self.checks,
+                context.git,
+                context.gpg,
+                config,
+                context.ref_update,
+            )?,
+        };
+        Ok(PolicyReport::new(Policy::<G, P>::name(self), result))
+    }
+}
+
+pub struct VerifyTagsPolicy;
+
+impl<G: Git, P: Gpg> Policy<G, P> for VerifyTagsPolicy {
+    type Config = VerifyTagsConfig;
+
+    fn name(&self) -> &str {
+        "verify_tags"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<VerifyTagsConfig>, Box<dyn Error>> {
+        Ok(config.verify_tags.clone())
+    }
+
+    fn check(
+        &self,
+        config: &VerifyTagsConfig,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let result = verify_tags(context.git, context.gpg, config, context.ref_update)?;
+        Ok(PolicyReport::new(Policy::<G, P>::name(self), result))
+    }
+}
+
+pub struct VerifyFileSizesPolicy;
+
+impl<G: Git, P: Gpg> Policy<G, P> for VerifyFileSizesPolicy {
+    type Config = VerifyFileSizesConfig;
+
+    fn name(&self) -> &str {
+        "verify_file_sizes"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<VerifyFileSizesConfig>, Box<dyn Error>> {
+        Ok(config.verify_file_sizes.clone())
+    }
+
+    fn check(
+        &self,
+        config: &VerifyFileSizesConfig,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let result = verify_file_sizes(context.git, config, context.ref_update)?;
+        Ok(PolicyReport::new(Policy::<G, P>::name(self), result))
+    }
+}
+
+pub struct VerifyFilenamesPolicy;
+
+impl<G: Git, P: Gpg> Policy<G, P> for VerifyFilenamesPolicy {
+    type Config = VerifyFilenamesConfig;
+
+    fn name(&self) -> &str {
+        "verify_filenames"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<VerifyFilenamesConfig>, Box<dyn Error>> {
+        Ok(config.verify_filenames.clone())
+    }
+
+    fn check(
+        &self,
+        config: &VerifyFilenamesConfig,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let result = verify_filenames(context.git, config, context.ref_update)?;
+        Ok(PolicyReport::new(Policy::<G, P>::name(self), result))
+    }
+}
+
+pub struct VerifySignOffPolicy;
+
+impl<G: Git, P: Gpg> Policy<G, P> for VerifySignOffPolicy {
+    type Config = VerifySignOffConfig;
+
+    fn name(&self) -> &str {
+        "verify_sign_off"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<VerifySignOffConfig>, Box<dyn Error>> {
+        Ok(config.verify_sign_off.clone())
+    }
+
+    fn check(
+        &self,
+        config: &VerifySignOffConfig,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let result = verify_sign_off(context.git, config, context.ref_update)?;
+        Ok(PolicyReport::new(Policy::<G, P>::name(self), result))
+    }
+}
+
+pub struct VerifyLinearHistoryPolicy;
+
+impl<G: Git, P: Gpg> Policy<G, P> for VerifyLinearHistoryPolicy {
+    type Config = Vec<LinearHistoryConfig>;
+
+    fn name(&self) -> &str {
+        "verify_linear_history"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<Vec<LinearHistoryConfig>>, Box<dyn Error>> {
+        if config.verify_linear_history.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(config.verify_linear_history.clone()))
+        }
+    }
+
+    fn check(
+        &self,
+        config: &Vec<LinearHistoryConfig>,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let result = verify_linear_history(context.git, config, context.ref_update)?;
+        Ok(PolicyReport::new(Policy::<G, P>::name(self), result))
+    }
+}
+
+pub struct VerifySubjectsPolicy;
+
+impl<G: Git, P: Gpg> Policy<G, P> for VerifySubjectsPolicy {
+    type Config = VerifySubjectsConfig;
+
+    fn name(&self) -> &str {
+        "verify_subjects"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<VerifySubjectsConfig>, Box<dyn Error>> {
+        Ok(config.verify_subjects.clone())
+    }
+
+    fn check(
+        &self,
+        config: &VerifySubjectsConfig,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let result = verify_subjects(context.git, config, context.ref_update)?;
+        Ok(PolicyReport::new(Policy::<G, P>::name(self), result))
+    }
+}
+
+pub struct VerifyParentCountPolicy;
+
+impl<G: Git, P: Gpg> Policy<G, P> for VerifyParentCountPolicy {
+    type Config = VerifyParentCountConfig;
+
+    fn name(&self) -> &str {
+        "verify_parent_count"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<VerifyParentCountConfig>, Box<dyn Error>> {
+        Ok(config.verify_parent_count.clone())
+    }
+
+    fn check(
+        &self,
+        config: &VerifyParentCountConfig,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let result = verify_parent_count(context.git, config, context.ref_update)?;
+        Ok(PolicyReport::new(Policy::<G, P>::name(self), result))
+    }
+}
+
+pub struct VerifySubmodulesAndSymlinksPolicy;
+
+impl<G: Git, P: Gpg> Policy<G, P> for VerifySubmodulesAndSymlinksPolicy {
+    type Config = VerifySubmodulesAndSymlinksConfig;
+
+    fn name(&self) -> &str {
+        "verify_submodules_and_symlinks"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(
+        &self,
+        config: &Config,
+    ) -> Result<Option<VerifySubmodulesAndSymlinksConfig>, Box<dyn Error>> {
+        Ok(config.verify_submodules_and_symlinks.clone())
+    }
+
+    fn check(
+        &self,
+        config: &VerifySubmodulesAndSymlinksConfig,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let result =
+            verify_submodules_and_symlinks(context.git, context.gpg, config, context.ref_update)?;
+        Ok(PolicyReport::new(Policy::<G, P>::name(self), result))
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+/// A submodule from a .gitmodules file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Submodule {
+    pub path: Option<String>,
+    pub url: Option<String>,
+}
+
+/// The submodules in a .gitmodules file, by name, like
+/// `[submodule "lib"]`. Like Git, the last value set for a key wins.
+pub fn parse_gitmodules(file_contents: &str) -> BTreeMap<String, Submodule> {
+    let mut submodules = BTreeMap::new();
+    let mut current = None;
+    for line in file_contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            current = header
+                .trim_start()
+                .strip_prefix("submodule")
+                .and_then(|rest| rest.trim_start().strip_prefix('"'))
+                .and_then(|rest| rest.split_once('"'))
+                .map(|(name, _)| name.to_string());
+            if let Some(name) = &current {
+                submodules
+                    .entry(name.clone())
+                    .or_insert_with(Submodule::default);
+            }
+            continue;
+        }
+        let submodule = match current.as_ref().and_then(|name| submodules.get_mut(name)) {
+            Some(submodule) => submodule,
+            None => continue,
+        };
+        if let Some((key, value)) = line.split_once('=') {
+            let value = Some(parse_value(value));
+            match key.trim().to_lowercase().as_str() {
+                "path" => submodule.path = value,
+                "url" => submodule.url = value,
+                _ => {}
+            }
+        }
+    }
+    submodules
+}
+
+// Values work the way Git reads them: `#` and `;` start a comment
+// unless they're inside double quotes, the quotes themselves are
+// dropped, and whitespace at the end is only kept if it's quoted.
+fn parse_value(raw: &str) -> String {
+    let mut value = String::new();
+    let mut kept_len = 0;
+    let mut in_quotes = false;
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' | ';' if !in_quotes => break,
+            '"' => {
+                in_quotes = !in_quotes;
+                continue;
+            }
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('b') => value.push('\u{8}'),
+                Some(escaped) => value.push(escaped),
+                None => break,
+            },
+            _ => value.push(c),
+        }
+        if in_quotes || c == '\\' || !c.is_whitespace() {
+            kept_len = value.len();
+        }
+    }
+    value.truncate(kept_len);
+    value
+}
+
+/// Whether a symlink at `path` would point outside the repo when it is
+/// checked out. Absolute targets always do, and relative targets do if
+/// they go up through more directories than the link is in.
+pub fn escapes_repo(path: &str, target: &str) -> bool {
+    if target.starts_with('/') || target.starts_with('\\') || target.get(1..2) == Some(":") {
+        return true;
+    }
+    let mut depth = path.split('/').count() - 1;
+    for name in target.split(['/', '\\']) {
+        match name {
+            "" | "." => {}
+            ".." if depth == 0 => return true,
+            ".." => depth -= 1,
+            _ => depth += 1,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_submodules_by_name() {
+        let submodules = parse_gitmodules(
+            "[submodule \"lib\"]\n\tpath = vendor/lib\n\turl = https://example.com/lib.git\n\n# Tools\n[submodule \"tools\"]\n\tpath = tools\n\turl = git@example.com:tools.git\n[core]\n\turl = ignored\n",
+        );
+        assert_eq!(submodules.len(), 2);
+        assert_eq!(
+            submodules["lib"],
+            Submodule {
+                path: Some("vendor/lib".to_string()),
+                url: Some("https://example.com/lib.git".to_string()),
+            }
+        );
+        assert_eq!(
+            submodules["tools"].url.as_deref(),
+            Some("git@example.com:tools.git")
+        );
+    }
+
+    #[test]
+    fn strips_comments_the_way_git_does() {
+        let submodules = parse_gitmodules(
+            "[submodule \"lib\"] # Vendored\n\tpath = vendor/lib ; moved in 2019\n\turl = https://example.com/lib.git # upstream\n[submodule \"docs\"]\n\tpath = \"docs # and notes\"\n\turl = \"https://example.com/docs.git\" \n",
+        );
+        assert_eq!(
+            submodules["lib"],
+            Submodule {
+                path: Some("vendor/lib".to_string()),
+                url: Some("https://example.com/lib.git".to_string()),
+            }
+        );
+        assert_eq!(
+            submodules["docs"],
+            Submodule {
+                path: Some("docs # and notes".to_string()),
+                url: Some("https://example.com/docs.git".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn finds_symlinks_that_escape_the_repo() {
+        assert!(!escapes_repo("docs/latest", "v2/readme.md"));
+        assert!(!escapes_repo("docs/latest", "../readme.md"));
+        assert!(!escapes_repo("a/b/link", "../../c/../b"));
+        assert!(escapes_repo("docs/latest", "../../readme.md"));
+        assert!(escapes_repo("link", ".."));
+        assert!(escapes_repo("link", "sub/../../etc"));
+        assert!(escapes_repo("link", "/etc/passwd"));
+        assert!(escapes_repo("link", "C:\\Windows"));
+    }
+}
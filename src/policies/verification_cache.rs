@@ -0,0 +1,224 @@
+use crate::config::VerifyGitCommitsConfig;
+use crate::git::{Commit, Git};
+
+use git2::{ObjectType, Oid};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+
+use log::*;
+
+const VERIFICATION_CACHE_FILE: &str = "capn-verification-cache.json";
+
+/// Once the cache holds this many commits, the ones that were recorded
+/// first are evicted, so that the file doesn't grow forever.
+const MAX_CACHED_COMMITS: usize = 50_000;
+
+/// Remembers which commits have already passed which checks, so that
+/// they don't need to be checked again when they're pushed to another
+/// branch. The cache lives in the Git directory, rather than in a
+/// ref, so that it can't be changed by pushing to the repo.
+///
+/// Results are only valid for the keyring, mailmap and config that
+/// they were checked with. If any of them changes, the whole cache is
+/// discarded.
+pub struct VerificationCache {
+    enabled: bool,
+    changed: bool,
+    contents: CacheContents,
+}
+
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+struct CacheContents {
+    keyring_hash: String,
+    #[serde(default)]
+    mailmap_hash: String,
+    config_hash: String,
+    passed: HashMap<String, HashSet<String>>,
+    /// The commits in `passed`, in the order they were recorded.
+    #[serde(default)]
+    recorded: VecDeque<String>,
+}
+
+impl CacheContents {
+    // Caches written before commits were kept in order, or edited by
+    // hand, can have commits that aren't in `recorded`. They're
+    // treated as the oldest.
+    fn with_complete_order(mut self) -> CacheContents {
+        let passed = &self.passed;
+        let mut in_order = HashSet::new();
+        self.recorded
+            .retain(|id| passed.contains_key(id) && in_order.insert(id.clone()));
+        let mut unordered = passed
+            .keys()
+            .filter(|id| !in_order.contains(*id))
+            .cloned()
+            .collect::<Vec<_>>();
+        unordered.sort();
+        for id in unordered.into_iter().rev() {
+            self.recorded.push_front(id);
+        }
+        self
+    }
+
+    fn evict_oldest(&mut self, max_commits: usize) -> bool {
+        let excess = self.recorded.len().saturating_sub(max_commits);
+        for id in self.recorded.drain(..excess) {
+            self.passed.remove(&id);
+        }
+        excess > 0
+    }
+}
+
+impl VerificationCache {
+    pub fn load<G: Git>(
+        git: &G,
+        config: &VerifyGitCommitsConfig,
+        team_fingerprints_file: &str,
+        mailmap_file: &str,
+    ) -> Result<VerificationCache, Box<dyn Error>> {
+        if !config.verification_cache {
+            return Ok(VerificationCache {
+                enabled: false,
+                changed: false,
+                contents: CacheContents::default(),
+            });
+        }
+
+        let keyring_hash = hash(team_fingerprints_file.as_bytes())?;
+        let mailmap_hash = hash(mailmap_file.as_bytes())?;
+        let config_hash = hash(serde_json::to_string(config)?.as_bytes())?;
+
+        let cached = git
+            .read_git_file(VERIFICATION_CACHE_FILE)?
+            .and_then(|json| match serde_json::from_str::<CacheContents>(&json) {
+                Ok(contents) => Some(contents),
+                Err(e) => {
+                    warn!("Ignoring unreadable verification cache. Error: {}", e);
+                    None
+                }
+            })
+            .map(CacheContents::with_complete_order)
+            .filter(|contents| {
+                let is_valid = contents.keyring_hash == keyring_hash
+                    && contents.mailmap_hash == mailmap_hash
+                    && contents.config_hash == config_hash;
+                if !is_valid {
+                    debug!("Keyring, mailmap or config has changed, discarding verification cache");
+                }
+                is_valid
+            });
+
+        Ok(VerificationCache {
+            enabled: true,
+            changed: cached.is_none(),
+            contents: cached.unwrap_or(CacheContents {
+                keyring_hash,
+                mailmap_hash,
+                config_hash,
+                passed: HashMap::new(),
+                recorded: VecDeque::new(),
+            }),
+        })
+    }
+
+    pub fn uncached(&self, check: &str, commits: &[Commit]) -> Vec<Commit> {
+        commits
+            .iter()
+            .filter(|commit| {
+                let cached = self.has_passed(check, commit.id);
+                if cached {
+                    info!(
+                        "Skipping {} check for {}: it has already passed with the current keyring and config",
+                        check, commit.id
+                    );
+                }
+                !cached
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn has_passed(&self, check: &str, commit_id: Oid) -> bool {
+        self.enabled
+            && self
+                .contents
+                .passed
+                .get(&commit_id.to_string())
+                .is_some_and(|checks| checks.contains(check))
+    }
+
+    pub fn record_passes(&mut self, check: &str, commits: &[Commit]) {
+        if !self.enabled {
+            return;
+        }
+        for commit in commits {
+            let id = commit.id.to_string();
+            if !self.contents.passed.contains_key(&id) {
+                self.contents.recorded.push_back(id.clone());
+            }
+            self.changed |= self
+                .contents
+                .passed
+                .entry(id)
+                .or_default()
+                .insert(check.to_string());
+        }
+        self.changed |= self.contents.evict_oldest(MAX_CACHED_COMMITS);
+    }
+
+    /// Failing to save the cache only means that the work will be
+    /// redone next time, so it doesn't fail the policy.
+    pub fn save<G: Git>(&self, git: &G) {
+        if !self.enabled || !self.changed {
+            return;
+        }
+        let result = serde_json::to_string(&self.contents)
+            .map_err(|e| e.into())
+            .and_then(|json| git.write_git_file(VERIFICATION_CACHE_FILE, 0o640, &json));
+        if let Err(e) = result {
+            warn!("Failed to save verification cache. Error: {}", e);
+        }
+    }
+}
+
+fn hash(bytes: &[u8]) -> Result<String, Box<dyn Error>> {
+    Ok(Oid::hash_object(ObjectType::Blob, bytes)?.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn contents(passed: &[&str], recorded: &[&str]) -> CacheContents {
+        CacheContents {
+            passed: passed
+                .iter()
+                .map(|id| (id.to_string(), HashSet::new()))
+                .collect(),
+            recorded: recorded.iter().map(|id| id.to_string()).collect(),
+            ..CacheContents::default()
+        }
+    }
+
+    #[test]
+    fn oldest_commits_are_evicted_first() {
+        let mut contents = contents(&["a", "b", "c"], &["a", "b", "c"]);
+
+        assert!(contents.evict_oldest(2));
+        assert!(!contents.evict_oldest(2));
+
+        assert_eq!(contents.recorded, ["b", "c"]);
+        assert!(!contents.passed.contains_key("a"));
+        assert_eq!(contents.passed.len(), 2);
+    }
+
+    #[test]
+    fn commits_missing_from_the_order_are_treated_as_the_oldest() {
+        let mut contents = contents(&["a", "b", "c"], &["c", "gone", "c"]).with_complete_order();
+
+        assert_eq!(contents.recorded, ["a", "b", "c"]);
+        contents.evict_oldest(1);
+        assert_eq!(contents.passed.keys().collect::<Vec<_>>(), ["c"]);
+    }
+}
@@ -0,0 +1,385 @@
+//! Policies written as WebAssembly modules, run in an embedded
+//! interpreter. Modules can't import anything except the host API
+//! below, so they have no access to the filesystem, the network or
+//! the environment of the Git server. Execution is limited by fuel
+//! and `timeout_ms`, and memory by `max_memory_bytes`.
+//!
+//! A module must export its `memory`, and a function `check` which
+//! takes no parameters and returns nothing. It may import these
+//! functions from the `capn` module:
+//!
+//! - `input() -> i32` prepares the same JSON description of the
+//!   reference update that external policies get, and returns its
+//!   length.
+//! - `commit_diff(id_ptr: i32, id_len: i32) -> i32` prepares the patch
+//!   of a commit against its first parent, and returns its length, or
+//!   -1 if there is no such commit.
+//! - `file_contents(id_ptr: i32, id_len: i32, path_ptr: i32, path_len:
+//!   i32) -> i32` prepares the contents of a file at a commit, and
+//!   returns its length, or -1 if there is no such file.
+//! - `read_response(ptr: i32)` copies the data prepared by the last
+//!   call above into the module's memory.
+//! - `report_violation(ptr: i32, len: i32)` reports a UTF-8 message
+//!   explaining why the update should be rejected.
+
+use crate::config::{Config, WasmModuleLocation, WasmPolicyConfig};
+use crate::error::CapnError;
+use crate::git::Git;
+use crate::gpg::Gpg;
+use crate::policies::external::external_policy_input;
+use crate::policies::policy_result::PolicyResult;
+use crate::policies::registry::{Hook, Policy, PolicyReport, RefUpdateContext};
+
+use git2::Oid;
+use std::error::Error;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use wasmi::core::{HostError, Trap};
+use wasmi::{
+    Caller, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedResumableCall, Value,
+};
+
+use log::*;
+
+const WASM_MODULE_DIR_KEY: &str = "capn.wasmModuleDir";
+
+pub struct WasmPolicies;
+
+impl<G: Git, P: Gpg> Policy<G, P> for WasmPolicies {
+    type Config = Vec<WasmPolicyConfig>;
+
+    fn name(&self) -> &str {
+        "wasm_policy"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PrePush, Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<Vec<WasmPolicyConfig>>, Box<dyn Error>> {
+        if config.wasm_policy.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(config.wasm_policy.clone()))
+        }
+    }
+
+    fn check(
+        &self,
+        config: &Vec<WasmPolicyConfig>,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let input = external_policy_input(context.git, context.hook, context.ref_update)?;
+        let input = serde_json::to_vec(&input)?;
+
+        for policy in config {
+            info!("Executing wasm policy: {}", policy.name);
+            let start = Instant::now();
+            let module = load_module(context.git, policy)?;
+            let violations =
+                run_wasm_policy(context.git, policy, module, input.clone()).map_err(|e| {
+                    CapnError::new(format!("Wasm policy {} failed to run: {}", policy.name, e))
+                })?;
+            debug!(
+                "Wasm policy {} completed in {} ms",
+                policy.name,
+                start.elapsed().as_millis()
+            );
+            if !violations.is_empty() {
+                for violation in &violations {
+                    error!("Wasm policy {} failed: {}", policy.name, violation);
+                }
+                return Ok(PolicyReport::new(
+                    Policy::<G, P>::name(self),
+                    PolicyResult::ExternalPolicyViolations(policy.name.clone(), violations),
+                ));
+            }
+            info!("Wasm policy {} passed", policy.name);
+        }
+        Ok(PolicyReport::new(
+            Policy::<G, P>::name(self),
+            PolicyResult::Ok,
+        ))
+    }
+}
+
+fn load_module<G: Git>(git: &G, policy: &WasmPolicyConfig) -> Result<Vec<u8>, Box<dyn Error>> {
+    let module = match policy.location {
+        WasmModuleLocation::Repo => match git.head_commit_id()? {
+            Some(head) => git.read_file_at_commit(head, &policy.module)?,
+            None => None,
+        },
+        WasmModuleLocation::Server => Some(std::fs::read(server_module_path(git, policy)?)?),
+    };
+    module.ok_or_else(|| {
+        CapnError::new(format!(
+            "Wasm policy {} module not found: {}",
+            policy.name, policy.module
+        ))
+        .into()
+    })
+}
+
+struct HostState {
+    input: Vec<u8>,
+    response: Vec<u8>,
+    violations: Vec<String>,
+    limits: StoreLimits,
+}
+
+/// Host calls that need the Git repo stop the module, so that they can
+/// be answered outside of the interpreter, and then resume it.
+#[derive(Debug, Clone)]
+enum HostRequest {
+    CommitDiff(String),
+    FileContents(String, String),
+}
+
+impl fmt::Display for HostRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostRequest::CommitDiff(commit) => write!(f, "diff of {}", commit),
+            HostRequest::FileContents(commit, path) => write!(f, "{} at {}", path, commit),
+        }
+    }
+}
+
+impl HostError for HostRequest {}
+
+enum WorkerMessage {
+    Request(HostRequest),
+    Done(Result<Vec<String>, String>),
+}
+
+/// Server modules are only loaded from the directory in the repo's
+/// `capn.wasmModuleDir` Git config, since the `.capn` file naming them
+/// can be changed by anyone who can push.
+fn server_module_path<G: Git>(
+    git: &G,
+    policy: &WasmPolicyConfig,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let module_dir = git.config_value(WASM_MODULE_DIR_KEY)?.ok_or_else(|| {
+        CapnError::new(format!(
+            "Wasm policy {} is loaded from the server, but {} is not set in the repo's Git config",
+            policy.name, WASM_MODULE_DIR_KEY
+        ))
+    })?;
+    let module = Path::new(&policy.module);
+    let is_plain_relative_path = module
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if policy.module.is_empty() || !is_plain_relative_path {
+        return Err(CapnError::new(format!(
+            "Wasm policy {} module must be a path inside {}: {}",
+            policy.name, WASM_MODULE_DIR_KEY, policy.module
+        ))
+        .into());
+    }
+
+    // Symlinks in the module directory could still point outside it
+    let module_dir = Path::new(&module_dir).canonicalize()?;
+    let path = module_dir.join(module).canonicalize().map_err(|e| {
+        CapnError::new(format!(
+            "Wasm policy {} module not found: {}: {}",
+            policy.name, policy.module, e
+        ))
+    })?;
+    if !path.starts_with(&module_dir) {
+        return Err(CapnError::new(format!(
+            "Wasm policy {} module must be a path inside {}: {}",
+            policy.name, WASM_MODULE_DIR_KEY, policy.module
+        ))
+        .into());
+    }
+    Ok(path)
+}
+
+// The interpreter runs on its own thread, so that a module that spins
+// without calling back into the host can't hold up the hook past
+// `timeout_ms`. Host requests are answered here, since the Git repo
+// can't be shared with that thread. The thread is never joined: if the
+// module is still running at the timeout, it's left to run out of
+// fuel.
+fn run_wasm_policy<G: Git>(
+    git: &G,
+    policy: &WasmPolicyConfig,
+    module: Vec<u8>,
+    input: Vec<u8>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let start = Instant::now();
+    let timeout = Duration::from_millis(policy.timeout_ms);
+
+    let (message_sender, message_receiver) = mpsc::channel();
+    let (response_sender, response_receiver) = mpsc::channel();
+    let worker_policy = policy.clone();
+    thread::spawn(move || {
+        let result = run_wasm_module(
+            &worker_policy,
+            &module,
+            &input,
+            &message_sender,
+            &response_receiver,
+        )
+        .map_err(|e| e.to_string());
+        let _ = message_sender.send(WorkerMessage::Done(result));
+    });
+
+    loop {
+        match message_receiver.recv_timeout(timeout.saturating_sub(start.elapsed())) {
+            Ok(WorkerMessage::Request(request)) => {
+                let response = match request {
+                    HostRequest::CommitDiff(commit) => match Oid::from_str(&commit) {
+                        Ok(commit_id) => git.commit_diff(commit_id)?.map(String::into_bytes),
+                        Err(_) => None,
+                    },
+                    HostRequest::FileContents(commit, path) => match Oid::from_str(&commit) {
+                        Ok(commit_id) => git.read_file_at_commit(commit_id, &path)?,
+                        Err(_) => None,
+                    },
+                };
+                // The module only stops waiting if it has already failed
+                let _ = response_sender.send(response);
+            }
+            Ok(WorkerMessage::Done(result)) => {
+                return result.map_err(|e| CapnError::new(e).into());
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(Box::new(CapnError::new(format!(
+                    "did not finish within {} ms",
+                    policy.timeout_ms
+                ))));
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(Box::new(CapnError::new("interpreter stopped unexpectedly")));
+            }
+        }
+    }
+}
+
+fn run_wasm_module(
+    policy: &WasmPolicyConfig,
+    module: &[u8],
+    input: &[u8],
+    messages: &Sender<WorkerMessage>,
+    responses: &Receiver<Option<Vec<u8>>>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut engine_config = wasmi::Config::default();
+    engine_config.consume_fuel(true);
+    let engine = Engine::new(&engine_config);
+    let module = Module::new(&engine, module)?;
+
+    let mut store = Store::new(
+        &engine,
+        HostState {
+            input: input.to_vec(),
+            response: Vec::new(),
+            violations: Vec::new(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(policy.max_memory_bytes)
+                .instances(1)
+                .build(),
+        },
+    );
+    store.limiter(|state| &mut state.limits);
+    store
+        .add_fuel(policy.fuel)
+        .map_err(|e| CapnError::new(e.to_string()))?;
+
+    let mut linker = Linker::<HostState>::new(&engine);
+    linker.func_wrap("capn", "input", |mut caller: Caller<'_, HostState>| {
+        let state = caller.data_mut();
+        state.response = state.input.clone();
+        state.response.len() as i32
+    })?;
+    linker.func_wrap(
+        "capn",
+        "commit_diff",
+        |caller: Caller<'_, HostState>, id_ptr: i32, id_len: i32| -> Result<i32, Trap> {
+            let commit = read_string(&caller, id_ptr, id_len)?;
+            Err(HostRequest::CommitDiff(commit).into())
+        },
+    )?;
+    linker.func_wrap(
+        "capn",
+        "file_contents",
+        |caller: Caller<'_, HostState>,
+         id_ptr: i32,
+         id_len: i32,
+         path_ptr: i32,
+         path_len: i32|
+         -> Result<i32, Trap> {
+            let commit = read_string(&caller, id_ptr, id_len)?;
+            let path = read_string(&caller, path_ptr, path_len)?;
+            Err(HostRequest::FileContents(commit, path).into())
+        },
+    )?;
+    linker.func_wrap(
+        "capn",
+        "read_response",
+        |mut caller: Caller<'_, HostState>, ptr: i32| -> Result<(), Trap> {
+            let memory = exported_memory(&caller)?;
+            let response = std::mem::take(&mut caller.data_mut().response);
+            memory
+                .write(&mut caller, ptr as u32 as usize, &response)
+                .map_err(|e| Trap::new(e.to_string()))
+        },
+    )?;
+    linker.func_wrap(
+        "capn",
+        "report_violation",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<(), Trap> {
+            let violation = read_string(&caller, ptr, len)?;
+            caller.data_mut().violations.push(violation);
+            Ok(())
+        },
+    )?;
+
+    let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+    let check = instance.get_typed_func::<(), ()>(&store, "check")?;
+
+    let mut call = check.call_resumable(&mut store, ())?;
+    while let TypedResumableCall::Resumable(invocation) = call {
+        let request = match invocation.host_error().downcast_ref::<HostRequest>() {
+            Some(request) => request.clone(),
+            None => {
+                return Err(Box::new(CapnError::new(
+                    invocation.host_error().to_string(),
+                )))
+            }
+        };
+        messages.send(WorkerMessage::Request(request))?;
+        let response_len = match responses.recv()? {
+            Some(response) => {
+                let len = response.len() as i32;
+                store.data_mut().response = response;
+                len
+            }
+            None => -1,
+        };
+        call = invocation.resume(&mut store, &[Value::I32(response_len)])?;
+    }
+
+    Ok(store.into_data().violations)
+}
+
+fn exported_memory(caller: &Caller<'_, HostState>) -> Result<wasmi::Memory, Trap> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("Module does not export its memory"))
+}
+
+fn read_string(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<String, Trap> {
+    let memory = exported_memory(caller)?;
+    let start = ptr as u32 as usize;
+    let end = start.saturating_add(len as u32 as usize);
+    let bytes = memory
+        .data(caller)
+        .get(start..end)
+        .ok_or_else(|| Trap::new("String is outside of the module's memory"))?;
+    String::from_utf8(bytes.to_vec()).map_err(|e| Trap::new(e.to_string()))
+}
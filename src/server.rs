@@ -0,0 +1,184 @@
+use crate::git::{Git, LiveGit};
+use git2::{Repository, RepositoryOpenFlags};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use log::*;
+
+const MANAGED_HOOK_MARKER: &str = "# Managed by Captain Git Hook";
+
+#[derive(Debug, StructOpt)]
+pub struct ServerInstall {
+    /// Directory to search for bare repos to install the pre-receive and post-receive hooks into
+    #[structopt(long = "root", parse(from_os_str))]
+    pub root: PathBuf,
+    /// Command used by the hook to call Captain Git Hook
+    #[structopt(long = "capn-path", default_value = "capn")]
+    pub capn_path: String,
+    /// URL for logging over TCP, passed on to the installed hook
+    #[structopt(long = "log-url")]
+    pub log_url: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ServerInstallReport {
+    pub installed: Vec<PathBuf>,
+    pub updated: Vec<PathBuf>,
+    pub skipped_unmanaged_hook: Vec<PathBuf>,
+    pub missing_config: Vec<PathBuf>,
+}
+
+pub fn install_server_hooks(opt: &ServerInstall) -> Result<ServerInstallReport, Box<dyn Error>> {
+    let mut report = ServerInstallReport::default();
+
+    for repo_path in find_bare_repos(&opt.root)? {
+        let repo_name = repo_path
+            .strip_prefix(&opt.root)
+            .unwrap_or(&repo_path)
+            .to_string_lossy()
+            .into_owned();
+        let git = LiveGit::default(&repo_path)?;
+
+        match install_hook(
+            &git,
+            "pre-receive",
+            &hook_script(opt, &repo_name, "pre-receive"),
+        )? {
+            HookInstall::Installed => {
+                info!("Installed pre-receive hook for {}", repo_name);
+                report.installed.push(repo_path.clone());
+            }
+            HookInstall::Updated => {
+                info!("Updated pre-receive hook for {}", repo_name);
+                report.updated.push(repo_path.clone());
+            }
+            HookInstall::SkippedUnmanagedHook => {
+                warn!(
+                    "Skipping {}: it already has a pre-receive hook that is not managed by Captain Git Hook",
+                    repo_name
+                );
+                report.skipped_unmanaged_hook.push(repo_path.clone());
+            }
+        }
+        // Audit notes saved by the pre-receive hook are written once the
+        // refs have been updated
+        if !report.skipped_unmanaged_hook.contains(&repo_path)
+            && install_hook(
+                &git,
+                "post-receive",
+                &hook_script(opt, &repo_name, "post-receive"),
+            )? == HookInstall::SkippedUnmanagedHook
+        {
+            warn!(
+                "Not installing the post-receive hook for {}: it already has one that is not managed by Captain Git Hook, so audit notes won't be written",
+                repo_name
+            );
+        }
+
+        if let Err(e) = git.read_config() {
+            warn!(
+                "No usable .capn config found in {}. Error: {}",
+                repo_name, e
+            );
+            report.missing_config.push(repo_path);
+        }
+    }
+
+    Ok(report)
+}
+
+#[derive(Debug, PartialEq)]
+enum HookInstall {
+    Installed,
+    Updated,
+    SkippedUnmanagedHook,
+}
+
+fn install_hook(git: &LiveGit, hook: &str, script: &str) -> Result<HookInstall, Box<dyn Error>> {
+    let hook_path = format!("hooks/{}", hook);
+    match fs::read_to_string(git.path().join(&hook_path)).ok() {
+        Some(ref existing) if !existing.contains(MANAGED_HOOK_MARKER) => {
+            Ok(HookInstall::SkippedUnmanagedHook)
+        }
+        existing => {
+            fs::create_dir_all(git.path().join("hooks"))?;
+            git.write_git_file(&hook_path, 0o755, script)?;
+            if existing.is_some() {
+                Ok(HookInstall::Updated)
+            } else {
+                Ok(HookInstall::Installed)
+            }
+        }
+    }
+}
+
+fn find_bare_repos(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let no_search: &[&OsStr] = &[];
+    match Repository::open_ext(dir, RepositoryOpenFlags::NO_SEARCH, no_search) {
+        Ok(repo) if repo.is_bare() => return Ok(vec![dir.to_path_buf()]),
+        Ok(_) => {
+            debug!("Skipping non-bare repo {}", dir.display());
+            return Ok(Vec::new());
+        }
+        Err(_) => {}
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!(
+                "Skipping unreadable directory {}. Error: {}",
+                dir.display(),
+                e
+            );
+            return Ok(Vec::new());
+        }
+    };
+    // Symlinks aren't followed, so that a link back up the tree can't
+    // send the search round in circles.
+    let mut dirs = entries
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!(
+                    "Skipping unreadable entry in {}. Error: {}",
+                    dir.display(),
+                    e
+                );
+                None
+            }
+        })
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    dirs.sort();
+
+    let mut repos = Vec::new();
+    for entry in &dirs {
+        repos.extend(find_bare_repos(entry)?);
+    }
+    Ok(repos)
+}
+
+fn hook_script(opt: &ServerInstall, repo_name: &str, hook: &str) -> String {
+    let log_url = opt
+        .log_url
+        .as_ref()
+        .map(|url| format!(" --log-url {}", shell_quote(url)))
+        .unwrap_or_default();
+    format!(
+        "#!/bin/sh\n{}\n{} --repo {}{} {}\n",
+        MANAGED_HOOK_MARKER,
+        shell_quote(&opt.capn_path),
+        shell_quote(repo_name),
+        log_url,
+        hook
+    )
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
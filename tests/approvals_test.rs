@@ -0,0 +1,311 @@
+use capn::config::{SignerRequirement, VerifyGitCommitsConfig};
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository};
+use uuid::Uuid;
+
+mod common;
+use common::{Person, TempTestRepo};
+
+const AUTHOR: Person = ("Author", "author@jemstep.com");
+const APPROVER_ONE: Person = ("Approver One", "approver1@jemstep.com");
+const APPROVER_TWO: Person = ("Approver Two", "approver2@jemstep.com");
+
+/// A copy of the test repo, with the approvers added to the team
+/// fingerprints on master. The second approver is in the `security`
+/// group.
+fn test_repo() -> TempTestRepo {
+    let mut repo = TempTestRepo::with_keys(&[APPROVER_ONE, APPROVER_TWO]);
+    let team_fingerprints = repo.keyring().team_member(APPROVER_ONE, "")
+        + &repo.keyring().team_member(APPROVER_TWO, "security");
+    repo.commit_team_fingerprints(AUTHOR, &team_fingerprints);
+    repo
+}
+
+impl TempTestRepo {
+    /// A feature branch, and a merge of it into master, both by the
+    /// given author. Returns the branch tip and the merge.
+    fn merged_branch(&self, author: (&str, &str)) -> (Oid, Oid) {
+        self.merged_branch_as(author, author, None)
+    }
+
+    /// Like `merged_branch`, but the merge is committed by someone
+    /// else, and optionally signed with another key.
+    fn merged_branch_as(
+        &self,
+        author: (&str, &str),
+        committer: (&str, &str),
+        signer: Option<(&str, &str)>,
+    ) -> (Oid, Oid) {
+        let tip = self.commit_file_as(author, author, self.master, "feature.txt", "feature", &[]);
+        let repo = Repository::open(&self.path).unwrap();
+        let tree = repo.find_commit(tip).unwrap().tree_id().to_string();
+        let master = self.master.to_string();
+        let tip_id = tip.to_string();
+        let sign = signer.map(|signer| format!("-S{}", signer.1));
+        let mut args = vec![
+            "commit-tree",
+            &tree,
+            "-p",
+            &master,
+            "-p",
+            &tip_id,
+            "-m",
+            "Merge feature",
+        ];
+        args.extend(sign.as_deref());
+        let merge = self.git_as(author, committer, &args);
+        (tip, Oid::from_str(merge.trim()).unwrap())
+    }
+
+    fn approve(&self, approver: (&str, &str), commit: Oid, signed: bool) {
+        self.tag(approver, "capn-approve", commit, signed);
+    }
+
+    fn tag(&self, approver: (&str, &str), prefix: &str, commit: Oid, signed: bool) {
+        let name = format!("{}-{}", prefix, Uuid::new_v4());
+        let sign = if signed { "-s" } else { "-a" };
+        let mut args = vec!["tag", sign];
+        if signed {
+            args.extend(&["-u", approver.1]);
+        }
+        let commit = commit.to_string();
+        args.extend(&["-m", "Approved", &name, &commit]);
+        self.git_as(approver, approver, &args);
+    }
+
+    fn verify(&self, ref_name: &str, new_commit_id: Oid) -> PolicyResult {
+        self.verify_with(&approvals_config(), ref_name, new_commit_id)
+    }
+
+    fn verify_with(
+        &self,
+        config: &VerifyGitCommitsConfig,
+        ref_name: &str,
+        new_commit_id: Oid,
+    ) -> PolicyResult {
+        policies::verify_git_commits::<LiveGit, MockGpg>(
+            &LiveGit::default(&self.path).unwrap(),
+            MockGpg,
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                &self.master.to_string(),
+                &new_commit_id.to_string(),
+                ref_name,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn approvals_config() -> VerifyGitCommitsConfig {
+    VerifyGitCommitsConfig {
+        author_domain: "jemstep.com".into(),
+        committer_domain: "jemstep.com".into(),
+        keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
+        team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
+        verify_email_addresses: false,
+        verify_commit_signatures: false,
+        override_tags_required: 1.into(),
+        required_approvals: 2.into(),
+        ..Default::default()
+    }
+}
+
+fn approvers(result: PolicyResult) -> Vec<String> {
+    match result {
+        PolicyResult::NotEnoughApprovals(_, required, approvers) => {
+            assert_eq!(required, "2 signers");
+            approvers
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn merge_approved_by_two_team_members_passes() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch(AUTHOR);
+    repo.approve(APPROVER_ONE, merge, true);
+    repo.approve(APPROVER_TWO, merge, true);
+
+    let result = repo.verify("refs/heads/master", merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn approvals_on_the_branch_tip_count() {
+    let repo = test_repo();
+    let (tip, merge) = repo.merged_branch(AUTHOR);
+    repo.approve(APPROVER_ONE, tip, true);
+    repo.approve(APPROVER_TWO, merge, true);
+
+    let result = repo.verify("refs/heads/master", merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn merge_with_too_few_approvals_fails() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch(AUTHOR);
+    repo.approve(APPROVER_ONE, merge, true);
+
+    let result = repo.verify("refs/heads/master", merge);
+
+    assert_eq!(approvers(result), vec![APPROVER_ONE.1]);
+}
+
+#[test]
+fn approval_from_the_merge_author_does_not_count() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch(APPROVER_ONE);
+    repo.approve(APPROVER_ONE, merge, true);
+    repo.approve(APPROVER_TWO, merge, true);
+
+    let result = repo.verify("refs/heads/master", merge);
+
+    assert_eq!(approvers(result), vec![APPROVER_TWO.1]);
+}
+
+#[test]
+fn approval_from_the_merge_committer_does_not_count() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch_as(AUTHOR, APPROVER_ONE, None);
+    repo.approve(APPROVER_ONE, merge, true);
+    repo.approve(APPROVER_TWO, merge, true);
+
+    let result = repo.verify("refs/heads/master", merge);
+
+    assert_eq!(approvers(result), vec![APPROVER_TWO.1]);
+}
+
+#[test]
+fn approval_from_the_merge_signer_does_not_count() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch_as(AUTHOR, AUTHOR, Some(APPROVER_ONE));
+    repo.approve(APPROVER_ONE, merge, true);
+    repo.approve(APPROVER_TWO, merge, true);
+
+    let result = repo.verify("refs/heads/master", merge);
+
+    assert_eq!(approvers(result), vec![APPROVER_TWO.1]);
+}
+
+#[test]
+fn tags_that_do_not_match_the_default_pattern_do_not_count() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch(AUTHOR);
+    repo.approve(APPROVER_ONE, merge, true);
+    repo.tag(APPROVER_TWO, "release", merge, true);
+
+    // The config leaves approval_tag_pattern as its default
+    let result = repo.verify("refs/heads/master", merge);
+
+    assert_eq!(approvers(result), vec![APPROVER_ONE.1]);
+}
+
+#[test]
+fn unsigned_approvals_do_not_count() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch(AUTHOR);
+    repo.approve(APPROVER_ONE, merge, true);
+    repo.approve(APPROVER_TWO, merge, false);
+
+    let result = repo.verify("refs/heads/master", merge);
+
+    assert_eq!(approvers(result), vec![APPROVER_ONE.1]);
+}
+
+#[test]
+fn approvals_are_not_required_outside_of_mainlines() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch(AUTHOR);
+
+    let result = repo.verify("refs/heads/feature", merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+fn security_config(signers: u8) -> VerifyGitCommitsConfig {
+    VerifyGitCommitsConfig {
+        required_approvals: SignerRequirement::Groups {
+            signers,
+            groups: vec![("security".to_string(), 1)].into_iter().collect(),
+        },
+        ..approvals_config()
+    }
+}
+
+#[test]
+fn approval_from_a_required_group_passes() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch(AUTHOR);
+    repo.approve(APPROVER_TWO, merge, true);
+
+    let result = repo.verify_with(&security_config(1), "refs/heads/master", merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn approvals_are_required_from_groups_without_a_signer_count() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch(AUTHOR);
+    repo.approve(APPROVER_ONE, merge, true);
+
+    let result = repo.verify_with(&security_config(0), "refs/heads/master", merge);
+
+    match result {
+        PolicyResult::NotEnoughApprovals(_, required, approvers) => {
+            assert_eq!(required, "0 signers, at least 1 from @security");
+            assert_eq!(approvers, vec![APPROVER_ONE.1]);
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn enough_approvals_without_a_required_group_fails() {
+    let repo = test_repo();
+    let (_, merge) = repo.merged_branch(APPROVER_TWO);
+    repo.approve(APPROVER_ONE, merge, true);
+    repo.approve(APPROVER_TWO, merge, true);
+
+    let result = repo.verify_with(&security_config(1), "refs/heads/master", merge);
+
+    match result {
+        PolicyResult::NotEnoughApprovals(_, required, approvers) => {
+            assert_eq!(required, "1 signers, at least 1 from @security");
+            assert_eq!(approvers, vec![APPROVER_ONE.1]);
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn signer_requirements_can_be_read_with_groups() {
+    let config: VerifyGitCommitsConfig = toml::from_str(
+        r#"
+author_domain = "jemstep.com"
+committer_domain = "jemstep.com"
+keyserver = "hkp://p80.pool.sks-keyservers.net"
+team_fingerprints_file = "TEAM_FINGERPRINTS"
+override_tags_required = 3
+required_approvals = { signers = 2, groups = { security = 1 } }
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(config.override_tags_required, SignerRequirement::Count(3));
+    assert_eq!(
+        config.required_approvals,
+        security_config(2).required_approvals
+    );
+}
@@ -0,0 +1,332 @@
+use capn::config::{Config, VerifyGitCommitsConfig};
+use capn::git::{Git, LiveGit};
+use capn::gpg::test::MockGpg;
+use capn::gpg::{Gpg, LiveGpg};
+use capn::policies;
+use capn::policies::audit::{read_audit_record, PendingAuditNotes, AUDIT_NOTES_REF};
+use capn::policies::commit_checks::CommitChecks;
+use capn::policies::policy_result::PolicyResult;
+use capn::policies::registry::{Hook, Policy, PolicyRegistry, PolicyReport, RefUpdateContext};
+use capn::reference_update::ReferenceUpdate;
+use capn::{audit_show, AuditShow};
+
+use git2::Oid;
+use std::error::Error;
+use std::process::*;
+
+mod common;
+use common::{Person, TempTestRepo};
+
+const AUDITOR: Person = ("Cap'n Audit", "capn-audit@example.com");
+const SIGNING_KEY: &str = AUDITOR.1;
+
+const SIGNED_COMMIT: &str = "26b9047d071ad631d4a7c25df8bfd5361a679938";
+const TRIVIAL_MERGE: &str = "3eb315d10e2ad89555d7bfc78a1db1ce07bce434";
+const UNSIGNED_COMMIT: &str = "d2e3bfdc923986d04e7a6368b5fdd78b1ddf84f1";
+const MASTER: &str = "eb5e0185546b0bb1a13feec6b9ee8b39985fea42";
+
+impl TempTestRepo {
+    fn git(&self) -> LiveGit {
+        LiveGit::default(&self.path).unwrap()
+    }
+}
+
+fn audited_config() -> VerifyGitCommitsConfig {
+    VerifyGitCommitsConfig {
+        author_domain: "jemstep.com".into(),
+        committer_domain: "jemstep.com".into(),
+        keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
+        team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
+        override_tag_pattern: Some("capn-override-*".to_string()),
+        override_tags_required: 1.into(),
+        audit_notes: true,
+        ..Default::default()
+    }
+}
+
+// The test repo's HEAD is master, so commits need to be verified
+// against it rather than being pushed to it, otherwise they are
+// already on the mainline and aren't checked.
+fn verify(repo: &TempTestRepo, config: &VerifyGitCommitsConfig, new: &str) -> bool {
+    let git = repo.git();
+    let audit_notes = PendingAuditNotes::default();
+    let accepted = policies::verify_git_commits_with_audit_notes::<LiveGit, MockGpg>(
+        &CommitChecks::with_builtin_checks(),
+        &git,
+        &MockGpg,
+        config,
+        &ReferenceUpdate::from_git_hook_format(MASTER, new, "refs/heads/feature").unwrap(),
+        &audit_notes,
+    )
+    .unwrap()
+    .is_ok();
+    audit_notes.save(&git).unwrap();
+    capn::post_receive(&git, signing_gpg(), MASTER, new, "refs/heads/feature").unwrap();
+    accepted
+}
+
+// Signs notes with the secret keys in the test keyring.
+fn signing_gpg() -> LiveGpg {
+    LiveGpg {
+        parallel_fetch: false,
+        keyserver: String::new(),
+    }
+}
+
+fn oid(id: &str) -> Oid {
+    Oid::from_str(id).unwrap()
+}
+
+#[test]
+fn notes_record_why_each_commit_passed() {
+    let repo = TempTestRepo::with_keys(&[AUDITOR]);
+
+    assert!(verify(&repo, &audited_config(), TRIVIAL_MERGE));
+
+    let git = repo.git();
+    let signed = read_audit_record(&git, oid(SIGNED_COMMIT))
+        .unwrap()
+        .unwrap();
+    assert_eq!(signed.ref_name, "refs/heads/feature");
+    assert_eq!(signed.config_revision, Some(MASTER.to_string()));
+    assert_eq!(
+        signed.signer_fingerprint,
+        Some("40D0184E8FE9752CD2577D34E1F315E39CCCECAA".to_string())
+    );
+    assert_eq!(signed.outcomes["commit signatures"], "valid signature");
+    assert_eq!(signed.outcomes["email addresses"], "passed");
+
+    let merge = read_audit_record(&git, oid(TRIVIAL_MERGE))
+        .unwrap()
+        .unwrap();
+    assert_eq!(merge.signer_fingerprint, None);
+    assert_eq!(
+        merge.outcomes["commit signatures"],
+        "identical tree to a parent"
+    );
+}
+
+#[test]
+fn notes_record_checks_that_passed_in_an_earlier_verification() {
+    let repo = TempTestRepo::with_keys(&[AUDITOR]);
+    let config = VerifyGitCommitsConfig {
+        verification_cache: true,
+        ..audited_config()
+    };
+
+    assert!(verify(&repo, &config, TRIVIAL_MERGE));
+    assert!(verify(&repo, &config, TRIVIAL_MERGE));
+
+    let signed = read_audit_record(&repo.git(), oid(SIGNED_COMMIT))
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        signed.outcomes["email addresses"],
+        "passed in an earlier verification"
+    );
+    assert_eq!(
+        signed.outcomes["commit signatures"],
+        "passed in an earlier verification"
+    );
+}
+
+#[test]
+fn notes_are_not_written_when_the_update_is_rejected() {
+    let repo = TempTestRepo::with_keys(&[AUDITOR]);
+
+    assert!(!verify(&repo, &audited_config(), UNSIGNED_COMMIT));
+
+    assert!(read_audit_record(&repo.git(), oid(UNSIGNED_COMMIT))
+        .unwrap()
+        .is_none());
+}
+
+// Runs after verify_git_commits has accepted the update, and rejects
+// it anyway.
+struct RejectingPolicy;
+
+impl<G: Git, P: Gpg> Policy<G, P> for RejectingPolicy {
+    type Config = ();
+
+    fn name(&self) -> &str {
+        "rejecting"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PreReceive]
+    }
+
+    fn config(&self, _config: &Config) -> Result<Option<()>, Box<dyn Error>> {
+        Ok(Some(()))
+    }
+
+    fn check(
+        &self,
+        _config: &(),
+        _context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        Ok(PolicyReport::new(
+            "rejecting",
+            PolicyResult::PolicyViolation("rejecting".to_string(), "rejected".to_string()),
+        ))
+    }
+}
+
+#[test]
+fn notes_are_not_written_when_a_later_policy_rejects_the_update() {
+    let repo = TempTestRepo::with_keys(&[AUDITOR]);
+    let git = repo.git();
+    let mut config = Config::from_toml_string("").unwrap();
+    config.verify_git_commits = Some(audited_config());
+    let mut registry = PolicyRegistry::with_builtin_policies();
+    registry.register(RejectingPolicy);
+    let audit_notes = PendingAuditNotes::default();
+
+    let result = capn::pre_receive_with_registry::<LiveGit, MockGpg>(
+        &registry,
+        &git,
+        MockGpg,
+        &config,
+        MASTER,
+        TRIVIAL_MERGE,
+        "refs/heads/feature",
+        &audit_notes,
+    )
+    .unwrap();
+
+    assert!(result.is_err());
+    assert!(git
+        .read_note(AUDIT_NOTES_REF, oid(SIGNED_COMMIT))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn notes_are_only_written_by_post_receive_for_the_applied_update() {
+    let repo = TempTestRepo::with_keys(&[AUDITOR]);
+    let git = repo.git();
+    let mut config = Config::from_toml_string("").unwrap();
+    config.verify_git_commits = Some(audited_config());
+
+    let result = capn::pre_receive::<LiveGit, MockGpg>(
+        &git,
+        MockGpg,
+        &config,
+        MASTER,
+        TRIVIAL_MERGE,
+        "refs/heads/feature",
+    )
+    .unwrap();
+    assert!(result.is_ok(), "Error: {:?}", result);
+    assert!(read_audit_record(&git, oid(SIGNED_COMMIT))
+        .unwrap()
+        .is_none());
+
+    // Git didn't apply an update to this ref
+    capn::post_receive(&git, MockGpg, MASTER, TRIVIAL_MERGE, "refs/heads/other").unwrap();
+    assert!(read_audit_record(&git, oid(SIGNED_COMMIT))
+        .unwrap()
+        .is_none());
+
+    capn::post_receive(&git, MockGpg, MASTER, TRIVIAL_MERGE, "refs/heads/feature").unwrap();
+    assert!(read_audit_record(&git, oid(SIGNED_COMMIT))
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn notes_are_not_written_when_disabled() {
+    let repo = TempTestRepo::with_keys(&[AUDITOR]);
+
+    assert!(verify(
+        &repo,
+        &VerifyGitCommitsConfig {
+            audit_notes: false,
+            ..audited_config()
+        },
+        TRIVIAL_MERGE
+    ));
+
+    assert!(repo
+        .git()
+        .read_note(AUDIT_NOTES_REF, oid(TRIVIAL_MERGE))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn notes_commit_is_signed_with_the_configured_key() {
+    let repo = TempTestRepo::with_keys(&[AUDITOR]);
+
+    assert!(verify(
+        &repo,
+        &VerifyGitCommitsConfig {
+            audit_signing_key: Some(SIGNING_KEY.to_string()),
+            ..audited_config()
+        },
+        TRIVIAL_MERGE
+    ));
+
+    let verify_notes = Command::new("git")
+        .current_dir(&repo.path)
+        .args(["verify-commit", AUDIT_NOTES_REF])
+        .output()
+        .unwrap();
+    assert!(
+        verify_notes.status.success(),
+        "Notes commit was not signed: {}",
+        String::from_utf8_lossy(&verify_notes.stderr)
+    );
+    assert!(read_audit_record(&repo.git(), oid(SIGNED_COMMIT))
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn notes_commit_is_signed_by_the_gpg_client() {
+    let repo = TempTestRepo::with_keys(&[AUDITOR]);
+    let git = repo.git();
+    let audit_notes = PendingAuditNotes::default();
+    policies::verify_git_commits_with_audit_notes::<LiveGit, MockGpg>(
+        &CommitChecks::with_builtin_checks(),
+        &git,
+        &MockGpg,
+        &VerifyGitCommitsConfig {
+            audit_signing_key: Some(SIGNING_KEY.to_string()),
+            ..audited_config()
+        },
+        &ReferenceUpdate::from_git_hook_format(MASTER, TRIVIAL_MERGE, "refs/heads/feature")
+            .unwrap(),
+        &audit_notes,
+    )
+    .unwrap();
+    audit_notes.save(&git).unwrap();
+
+    // MockGpg can't sign, even though the key is in the test keyring
+    assert!(
+        capn::post_receive(&git, MockGpg, MASTER, TRIVIAL_MERGE, "refs/heads/feature").is_err()
+    );
+}
+
+#[test]
+fn audit_show_reads_note_for_abbreviated_commit() {
+    let repo = TempTestRepo::with_keys(&[AUDITOR]);
+    verify(&repo, &audited_config(), TRIVIAL_MERGE);
+
+    let record = audit_show(
+        &repo.git(),
+        &AuditShow {
+            commit: "26b9047".to_string(),
+        },
+    )
+    .unwrap();
+
+    assert_eq!(record.outcomes["commit signatures"], "valid signature");
+    assert!(audit_show(
+        &repo.git(),
+        &AuditShow {
+            commit: UNSIGNED_COMMIT.to_string()
+        }
+    )
+    .is_err());
+}
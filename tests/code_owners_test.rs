@@ -0,0 +1,263 @@
+use capn::config::VerifyGitCommitsConfig;
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository, Tree};
+use uuid::Uuid;
+
+mod common;
+use common::{Person, TempTestRepo};
+
+const AUTHOR: Person = ("Author", "author@jemstep.com");
+const OWNER: Person = ("Owner", "owner@jemstep.com");
+const WRITER: Person = ("Writer", "writer@jemstep.com");
+
+const CODEOWNERS: &str = "\
+# Owners of everything, unless a later rule says otherwise
+*             owner@jemstep.com
+/docs/        @docs
+/generated/
+";
+
+/// A copy of the test repo, with the owners in the team fingerprints
+/// and a CODEOWNERS file on master. The writer is in the `docs` group.
+fn test_repo() -> TempTestRepo {
+    let mut repo = TempTestRepo::with_keys(&[OWNER, WRITER]);
+    let team_fingerprints =
+        repo.keyring().team_member(OWNER, "") + &repo.keyring().team_member(WRITER, "docs");
+    let master = repo.commit(
+        repo.master,
+        AUTHOR,
+        &[
+            ("TEAM_FINGERPRINTS", &team_fingerprints),
+            ("CODEOWNERS", CODEOWNERS),
+        ],
+        false,
+    );
+    repo.set_master(master);
+    repo
+}
+
+impl TempTestRepo {
+    /// Commits the files on top of `parent`, authored by the author
+    /// and committed, and optionally signed, by the committer.
+    fn commit(
+        &self,
+        parent: Oid,
+        committer: (&str, &str),
+        files: &[(&str, &str)],
+        signed: bool,
+    ) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let mut tree = repo.find_commit(parent).unwrap().tree_id();
+        for (path, contents) in files {
+            let blob = repo.blob(contents.as_bytes()).unwrap();
+            let components = path.split('/').collect::<Vec<_>>();
+            tree = insert(
+                &repo,
+                Some(repo.find_tree(tree).unwrap()),
+                &components,
+                blob,
+            );
+        }
+        let mut args = vec![
+            "commit-tree".to_string(),
+            tree.to_string(),
+            "-p".to_string(),
+            parent.to_string(),
+            "-m".to_string(),
+            "Change".to_string(),
+        ];
+        if signed {
+            args.push(format!("-S{}", committer.1));
+        }
+        let commit = self.git_as(
+            AUTHOR,
+            committer,
+            &args.iter().map(String::as_str).collect::<Vec<_>>(),
+        );
+        Oid::from_str(commit.trim()).unwrap()
+    }
+
+    /// Merges a branch with the changed files into master.
+    fn merged_branch(&self, files: &[(&str, &str)], committer: (&str, &str), signed: bool) -> Oid {
+        let tip = self.commit(self.master, committer, files, signed);
+        let repo = Repository::open(&self.path).unwrap();
+        let tree = repo.find_commit(tip).unwrap().tree_id().to_string();
+        let merge = self.git_as(
+            AUTHOR,
+            AUTHOR,
+            &[
+                "commit-tree",
+                &tree,
+                "-p",
+                &self.master.to_string(),
+                "-p",
+                &tip.to_string(),
+                "-m",
+                "Merge branch",
+            ],
+        );
+        Oid::from_str(merge.trim()).unwrap()
+    }
+
+    fn approve(&self, approver: (&str, &str), commit: Oid) {
+        let name = format!("capn-approve-{}", Uuid::new_v4());
+        self.git_as(
+            AUTHOR,
+            approver,
+            &[
+                "tag",
+                "-s",
+                "-u",
+                approver.1,
+                "-m",
+                "Approved",
+                &name,
+                &commit.to_string(),
+            ],
+        );
+    }
+
+    fn verify(&self, new_commit_id: Oid) -> PolicyResult {
+        policies::verify_git_commits::<LiveGit, MockGpg>(
+            &LiveGit::default(&self.path).unwrap(),
+            MockGpg,
+            &code_owners_config(),
+            &ReferenceUpdate::from_git_hook_format(
+                &self.master.to_string(),
+                &new_commit_id.to_string(),
+                "refs/heads/master",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn insert(repo: &Repository, tree: Option<Tree<'_>>, path: &[&str], blob: Oid) -> Oid {
+    let mut builder = repo.treebuilder(tree.as_ref()).unwrap();
+    match path {
+        [file_name] => {
+            builder.insert(file_name, blob, 0o100644).unwrap();
+        }
+        [directory, rest @ ..] => {
+            let subtree = tree
+                .as_ref()
+                .and_then(|t| t.get_name(directory))
+                .map(|entry| repo.find_tree(entry.id()).unwrap());
+            let subtree = insert(repo, subtree, rest, blob);
+            builder.insert(directory, subtree, 0o040000).unwrap();
+        }
+        [] => panic!("Empty path"),
+    }
+    builder.write().unwrap()
+}
+
+fn code_owners_config() -> VerifyGitCommitsConfig {
+    VerifyGitCommitsConfig {
+        author_domain: "jemstep.com".into(),
+        committer_domain: "jemstep.com".into(),
+        keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
+        team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
+        verify_email_addresses: false,
+        verify_commit_signatures: false,
+        override_tags_required: 1.into(),
+        code_owners_file: Some("CODEOWNERS".to_string()),
+        ..Default::default()
+    }
+}
+
+fn missing_owners(result: PolicyResult) -> Vec<String> {
+    match result {
+        PolicyResult::MissingCodeOwnerApprovals(_, owners) => owners,
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn change_without_owner_approval_fails() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(&[("src/lib.rs", "code")], AUTHOR, false);
+
+    let result = repo.verify(merge);
+
+    assert_eq!(missing_owners(result), vec![OWNER.1]);
+}
+
+#[test]
+fn change_approved_by_owner_tag_passes() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(&[("src/lib.rs", "code")], AUTHOR, false);
+    repo.approve(OWNER, merge);
+
+    let result = repo.verify(merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn change_signed_by_owner_passes() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(&[("src/lib.rs", "code")], OWNER, true);
+
+    let result = repo.verify(merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn every_owned_path_needs_approval() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(
+        &[("src/lib.rs", "code"), ("docs/guide.md", "docs")],
+        AUTHOR,
+        false,
+    );
+    repo.approve(OWNER, merge);
+
+    let result = repo.verify(merge);
+
+    assert_eq!(missing_owners(result), vec!["@docs"]);
+}
+
+#[test]
+fn group_member_approves_for_group() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(&[("docs/guide.md", "docs")], AUTHOR, false);
+    repo.approve(WRITER, merge);
+
+    let result = repo.verify(merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn unowned_paths_do_not_need_approval() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(&[("generated/schema.rs", "generated")], AUTHOR, false);
+
+    let result = repo.verify(merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn owners_come_from_the_mainline_rather_than_the_branch() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(
+        &[
+            ("CODEOWNERS", "* author@jemstep.com\n"),
+            ("src/lib.rs", "code"),
+        ],
+        AUTHOR,
+        false,
+    );
+
+    let result = repo.verify(merge);
+
+    assert_eq!(missing_owners(result), vec![OWNER.1]);
+}
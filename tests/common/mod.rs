@@ -0,0 +1,276 @@
+//! Fixtures shared by the integration tests. Each test file includes
+//! this with `mod common;`, and not every file uses every fixture.
+#![allow(dead_code)]
+
+use git2::{Oid, Repository};
+use std::fs;
+use std::path::PathBuf;
+use std::process::*;
+use std::sync::{Mutex, OnceLock};
+use uuid::Uuid;
+
+/// A person who commits, tags or signs things in the tests, as a name
+/// and email address.
+pub type Person = (&'static str, &'static str);
+
+static KEYRING: OnceLock<TestKeyring> = OnceLock::new();
+
+/// A GPG home with the test repo's public key, and keys generated for
+/// the people who sign things in the tests, so that the tests don't
+/// touch the user's keyring. GNUPGHOME is set for the whole test
+/// process, so every test in a test binary shares one keyring. It's
+/// removed when the process exits.
+pub struct TestKeyring {
+    path: PathBuf,
+    generating: Mutex<()>,
+}
+
+impl TestKeyring {
+    /// The shared keyring, with a key for each of the people.
+    pub fn with_keys(people: &[Person]) -> &'static TestKeyring {
+        let keyring = KEYRING.get_or_init(|| {
+            let keyring = TestKeyring::new();
+            unsafe {
+                libc::atexit(remove_keyring);
+            }
+            keyring
+        });
+        let _generating = keyring.generating.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, email) in people {
+            if !keyring.has_key(email) {
+                keyring.generate_key(&format!("{} <{}>", name, email));
+            }
+        }
+        keyring
+    }
+
+    fn new() -> TestKeyring {
+        let path = std::env::temp_dir().join(format!("capn_gnupg_{}", Uuid::new_v4()));
+        fs::create_dir(&path).unwrap();
+        std::env::set_var("GNUPGHOME", &path);
+
+        let project_root = env!("CARGO_MANIFEST_DIR");
+        let status = Command::new("gpg")
+            .args([
+                "--import",
+                &format!("{}/tests/test-public-key.asc", project_root),
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "Failed to import test GPG key");
+        TestKeyring {
+            path,
+            generating: Mutex::new(()),
+        }
+    }
+
+    /// Generates a signing key for a user ID, like `Name <email>`.
+    pub fn generate_key(&self, user_id: &str) {
+        let status = Command::new("gpg")
+            .args([
+                "--batch",
+                "--passphrase",
+                "",
+                "--quick-generate-key",
+                user_id,
+                "default",
+                "sign",
+                "never",
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "Failed to generate test GPG key");
+    }
+
+    fn has_key(&self, email: &str) -> bool {
+        Command::new("gpg")
+            .args(["--list-secret-keys", email])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap()
+            .success()
+    }
+
+    /// A line for a Team Fingerprints file, with the groups that the
+    /// person is in, if any.
+    pub fn team_member(&self, person: Person, groups: &str) -> String {
+        let (name, email) = person;
+        if groups.is_empty() {
+            format!("{},{},{}\n", fingerprint(email), name, email)
+        } else {
+            format!("{},{},{},{}\n", fingerprint(email), name, email, groups)
+        }
+    }
+}
+
+extern "C" fn remove_keyring() {
+    if let Some(keyring) = KEYRING.get() {
+        let _ = Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(&keyring.path)
+            .args(["--kill", "all"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        let _ = fs::remove_dir_all(&keyring.path);
+    }
+}
+
+/// The fingerprint of the key for an email address in the current
+/// GPG home.
+pub fn fingerprint(email: &str) -> String {
+    let output = Command::new("gpg")
+        .args(["--with-colons", "--list-keys", email])
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .find(|line| line.starts_with("fpr:"))
+        .and_then(|line| line.split(':').nth(9))
+        .unwrap()
+        .to_string()
+}
+
+/// A copy of the test repo, so that the commits made by the tests
+/// don't end up in the checked in test repo. `master` starts as the
+/// test repo's HEAD.
+pub struct TempTestRepo {
+    pub path: PathBuf,
+    pub master: Oid,
+    keyring: Option<&'static TestKeyring>,
+}
+
+impl TempTestRepo {
+    pub fn new() -> TempTestRepo {
+        let project_root = env!("CARGO_MANIFEST_DIR");
+        let path = std::env::temp_dir().join(format!("capn_test_{}.git", Uuid::new_v4()));
+        let status = Command::new("git")
+            .arg("clone")
+            .arg("--mirror")
+            .arg("--quiet")
+            .arg(format!("{}/tests/test-repo.git", project_root))
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success(), "Failed to copy test repo");
+
+        let master = Repository::open(&path)
+            .unwrap()
+            .head()
+            .unwrap()
+            .target()
+            .unwrap();
+        TempTestRepo {
+            path,
+            master,
+            keyring: None,
+        }
+    }
+
+    /// A copy of the test repo that uses the shared test keyring, with
+    /// keys for each of the people.
+    pub fn with_keys(people: &[Person]) -> TempTestRepo {
+        let mut repo = TempTestRepo::new();
+        repo.keyring = Some(TestKeyring::with_keys(people));
+        repo
+    }
+
+    pub fn keyring(&self) -> &TestKeyring {
+        self.keyring.expect("Repo was created without a keyring")
+    }
+
+    /// Runs git in the repo as the given author and committer, and
+    /// returns its stdout.
+    pub fn git_as(&self, author: (&str, &str), committer: (&str, &str), args: &[&str]) -> String {
+        let output = Command::new("git")
+            .current_dir(&self.path)
+            .env("GIT_AUTHOR_NAME", author.0)
+            .env("GIT_AUTHOR_EMAIL", author.1)
+            .env("GIT_COMMITTER_NAME", committer.0)
+            .env("GIT_COMMITTER_EMAIL", committer.1)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {:?}",
+            args,
+            output
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// A tree with the file added to, or replaced in, the commit's tree.
+    pub fn tree_with_file(&self, commit: Oid, file_name: &str, contents: &str) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let parent = repo.find_commit(commit).unwrap();
+        let blob = repo.blob(contents.as_bytes()).unwrap();
+        let mut tree_builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        tree_builder.insert(file_name, blob, 0o100644).unwrap();
+        tree_builder.write().unwrap()
+    }
+
+    /// Commits a file on top of `parent` with `git commit-tree`, so
+    /// that extra arguments like `-S` can be passed.
+    pub fn commit_file_as(
+        &self,
+        author: (&str, &str),
+        committer: (&str, &str),
+        parent: Oid,
+        file_name: &str,
+        contents: &str,
+        extra_args: &[&str],
+    ) -> Oid {
+        let tree = self.tree_with_file(parent, file_name, contents).to_string();
+        let parent = parent.to_string();
+        let mut args = vec!["commit-tree", &tree, "-p", &parent, "-m", "Change"];
+        args.extend(extra_args);
+        let commit = self.git_as(author, committer, &args);
+        Oid::from_str(commit.trim()).unwrap()
+    }
+
+    /// Commits a Team Fingerprints file on top of master, and moves
+    /// master to it.
+    pub fn commit_team_fingerprints(&mut self, committer: (&str, &str), team_fingerprints: &str) {
+        self.commit_files_to_master(committer, &[("TEAM_FINGERPRINTS", team_fingerprints)]);
+    }
+
+    /// Commits files on top of master, and moves master to it.
+    pub fn commit_files_to_master(&mut self, committer: (&str, &str), files: &[(&str, &str)]) {
+        let mut master = self.master;
+        for (file_name, contents) in files {
+            master = self.commit_file_as(committer, committer, master, file_name, contents, &[]);
+        }
+        self.set_master(master);
+    }
+
+    /// The number of loose objects in the repo, to check that verifying
+    /// doesn't write anything to it.
+    pub fn loose_object_count(&self) -> usize {
+        fs::read_dir(self.path.join("objects"))
+            .unwrap()
+            .map(|dir| dir.unwrap())
+            .filter(|dir| dir.file_name().len() == 2)
+            .map(|dir| fs::read_dir(dir.path()).unwrap().count())
+            .sum()
+    }
+
+    pub fn set_master(&mut self, commit: Oid) {
+        let repo = Repository::open(&self.path).unwrap();
+        repo.reference("refs/heads/master", commit, true, "Test setup")
+            .unwrap();
+        self.master = commit;
+    }
+}
+
+impl Drop for TempTestRepo {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).unwrap();
+    }
+}
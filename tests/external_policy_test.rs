@@ -0,0 +1,195 @@
+use capn::config::Config;
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies::external::ExternalPolicyInput;
+use capn::policies::policy_result::PolicyResult;
+
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+struct TempPlugins {
+    path: PathBuf,
+}
+
+impl TempPlugins {
+    fn new() -> TempPlugins {
+        let path = std::env::temp_dir().join(format!("capn_external_test_{}", Uuid::new_v4()));
+        fs::create_dir(&path).unwrap();
+        TempPlugins { path }
+    }
+
+    fn script(&self, name: &str, body: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = self.path.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+}
+
+impl Drop for TempPlugins {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).unwrap();
+    }
+}
+
+fn pre_receive(external_policies: &str) -> Result<PolicyResult, Box<dyn Error>> {
+    capn::pre_receive::<LiveGit, MockGpg>(
+        &LiveGit::default(format!(
+            "{}/tests/test-repo.git",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap(),
+        MockGpg,
+        &Config::from_toml_string(external_policies).unwrap(),
+        "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+        "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+        "refs/heads/valid-merge",
+    )
+}
+
+fn external_policy(name: &str, command: &str) -> String {
+    format!(
+        "[[external_policy]]\nname = \"{}\"\ncommand = \"{}\"\ntimeout_ms = 2000\n",
+        name, command
+    )
+}
+
+#[test]
+fn passing_external_policy_accepts_update() {
+    let plugins = TempPlugins::new();
+    let command = plugins.script("pass", "cat > /dev/null\necho '{\"violations\": []}'");
+
+    let result = pre_receive(&external_policy("pass", &command)).unwrap();
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn external_policy_receives_ref_update_and_new_commits() {
+    let plugins = TempPlugins::new();
+    let input_file = plugins.path.join("input.json");
+    let command = plugins.script(
+        "record",
+        &format!(
+            "cat > '{}'\necho '{{\"violations\": []}}'",
+            input_file.display()
+        ),
+    );
+
+    pre_receive(&external_policy("record", &command)).unwrap();
+
+    let input: ExternalPolicyInput =
+        serde_json::from_str(&fs::read_to_string(input_file).unwrap()).unwrap();
+    assert_eq!(input.hook, "pre-receive");
+    assert_eq!(input.ref_update.ref_name, "refs/heads/valid-merge");
+    assert_eq!(
+        input.ref_update.old_commit_id,
+        Some("eb5e0185546b0bb1a13feec6b9ee8b39985fea42".to_string())
+    );
+    let commit_ids = input
+        .commits
+        .iter()
+        .map(|c| c.id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commit_ids,
+        vec![
+            "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+            "26b9047d071ad631d4a7c25df8bfd5361a679938"
+        ]
+    );
+}
+
+#[test]
+fn violations_from_external_policy_reject_update() {
+    let plugins = TempPlugins::new();
+    let command = plugins.script(
+        "license",
+        "cat > /dev/null\necho '{\"violations\": [{\"commit\": \"26b9047\", \"message\": \"Unknown license\"}]}'",
+    );
+
+    let result = pre_receive(&external_policy("license", &command)).unwrap();
+
+    match result {
+        PolicyResult::ExternalPolicyViolations(policy, violations) => {
+            assert_eq!(policy, "license");
+            assert_eq!(violations, vec!["26b9047: Unknown license"]);
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn crashing_external_policy_is_an_error() {
+    let plugins = TempPlugins::new();
+    let command = plugins.script("crash", "echo 'Segmentation fault' >&2\nexit 139");
+
+    let error = pre_receive(&external_policy("crash", &command)).unwrap_err();
+
+    assert!(
+        error.to_string().contains("External policy crash crashed"),
+        "Error: {}",
+        error
+    );
+    assert!(error.to_string().contains("Segmentation fault"));
+}
+
+#[test]
+fn slow_external_policy_times_out() {
+    let plugins = TempPlugins::new();
+    let command = plugins.script("slow", "exec sleep 10");
+
+    let error = pre_receive(&format!(
+        "[[external_policy]]\nname = \"slow\"\ncommand = \"{}\"\ntimeout_ms = 200\n",
+        command
+    ))
+    .unwrap_err();
+
+    assert!(
+        error.to_string().contains("did not finish within 200 ms"),
+        "Error: {}",
+        error
+    );
+}
+
+#[test]
+fn external_policy_that_leaves_a_process_holding_its_output_times_out() {
+    let plugins = TempPlugins::new();
+    let command = plugins.script(
+        "background",
+        "cat > /dev/null\necho '{\"violations\": []}'\nsleep 10 &",
+    );
+    let start = Instant::now();
+
+    let error = pre_receive(&format!(
+        "[[external_policy]]\nname = \"background\"\ncommand = \"{}\"\ntimeout_ms = 200\n",
+        command
+    ))
+    .unwrap_err();
+
+    assert!(
+        error.to_string().contains("did not finish within 200 ms"),
+        "Error: {}",
+        error
+    );
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn invalid_verdict_is_an_error() {
+    let plugins = TempPlugins::new();
+    let command = plugins.script("garbage", "cat > /dev/null\necho 'LGTM'");
+
+    let error = pre_receive(&external_policy("garbage", &command)).unwrap_err();
+
+    assert!(
+        error.to_string().contains("invalid verdict"),
+        "Error: {}",
+        error
+    );
+}
@@ -0,0 +1,193 @@
+use capn::config::VerifyFileSizesConfig;
+use capn::git::LiveGit;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository, Signature};
+
+mod common;
+use common::TempTestRepo;
+
+const MASTER: &str = "eb5e0185546b0bb1a13feec6b9ee8b39985fea42";
+
+const POINTER: &str = "version https://git-lfs.github.com/spec/v1\noid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\nsize 12345\n";
+
+const ZIP: &[u8] = b"PK\x03\x04\x14\x00\x00\x00\x08\x00\x00\x00\x00\x00";
+
+impl TempTestRepo {
+    /// Commits the files on top of master.
+    fn commit(&self, files: &[(&str, &[u8])]) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let parent = repo.find_commit(Oid::from_str(MASTER).unwrap()).unwrap();
+        let mut tree_builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        for (file_name, contents) in files {
+            let blob = repo.blob(contents).unwrap();
+            tree_builder.insert(file_name, blob, 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(tree_builder.write().unwrap()).unwrap();
+        let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+        repo.commit(None, &signature, &signature, "Add files", &tree, &[&parent])
+            .unwrap()
+    }
+
+    fn verify(&self, config: &VerifyFileSizesConfig, new_commit_id: Oid) -> PolicyResult {
+        policies::verify_file_sizes::<LiveGit>(
+            &LiveGit::default(&self.path).unwrap(),
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                MASTER,
+                &new_commit_id.to_string(),
+                "refs/heads/master",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn file_sizes_config() -> VerifyFileSizesConfig {
+    VerifyFileSizesConfig {
+        max_file_size_bytes: Some(1024),
+        lfs_required: vec!["*.zip".to_string()],
+        verify_lfs_pointers: true,
+    }
+}
+
+#[test]
+fn small_text_files_pass() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(&[("readme.txt", b"Hello")]);
+
+    let result = repo.verify(&file_sizes_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn files_over_the_maximum_size_fail() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(&[("output.txt", &[b'a'; 2048])]);
+
+    let result = repo.verify(&file_sizes_config(), commit);
+
+    match result {
+        PolicyResult::FileTooLarge(id, path, size, max) => {
+            assert_eq!(
+                (id, path.as_str(), size, max),
+                (commit, "output.txt", 2048, 1024)
+            );
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn binary_files_that_need_lfs_fail() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(&[("release.zip", ZIP)]);
+
+    let result = repo.verify(&file_sizes_config(), commit);
+
+    assert!(
+        matches!(&result, PolicyResult::BinaryFileNotInLfs(id, path) if *id == commit && path == "release.zip"),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn lfs_pointers_pass() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(&[
+        (
+            ".gitattributes",
+            b"*.zip filter=lfs diff=lfs merge=lfs -text\n",
+        ),
+        ("release.zip", POINTER.as_bytes()),
+    ]);
+
+    let result = repo.verify(&file_sizes_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn lfs_tracked_files_that_are_not_pointers_fail() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(&[
+        (
+            ".gitattributes",
+            b"*.csv filter=lfs diff=lfs merge=lfs -text\n",
+        ),
+        ("data.csv", b"a,b,c\n1,2,3\n"),
+    ]);
+
+    let result = repo.verify(&file_sizes_config(), commit);
+
+    assert!(
+        matches!(&result, PolicyResult::InvalidLfsPointer(id, path) if *id == commit && path == "data.csv"),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn lfs_pointers_are_not_verified_if_turned_off() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(&[
+        (
+            ".gitattributes",
+            b"*.csv filter=lfs diff=lfs merge=lfs -text\n",
+        ),
+        ("data.csv", b"a,b,c\n1,2,3\n"),
+    ]);
+
+    let result = repo.verify(
+        &VerifyFileSizesConfig {
+            verify_lfs_pointers: false,
+            ..file_sizes_config()
+        },
+        commit,
+    );
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn lfs_tracked_files_in_a_nested_gitattributes_that_are_not_pointers_fail() {
+    let repo = TempTestRepo::new();
+    let git2_repo = Repository::open(&repo.path).unwrap();
+    let mut data_builder = git2_repo.treebuilder(None).unwrap();
+    for (file_name, contents) in [
+        (
+            ".gitattributes",
+            &b"*.csv filter=lfs diff=lfs merge=lfs -text\n"[..],
+        ),
+        ("data.csv", &b"a,b,c\n1,2,3\n"[..]),
+    ] {
+        let blob = git2_repo.blob(contents).unwrap();
+        data_builder.insert(file_name, blob, 0o100644).unwrap();
+    }
+    let data_tree = data_builder.write().unwrap();
+    let parent = git2_repo
+        .find_commit(Oid::from_str(MASTER).unwrap())
+        .unwrap();
+    let mut tree_builder = git2_repo
+        .treebuilder(Some(&parent.tree().unwrap()))
+        .unwrap();
+    tree_builder.insert("data", data_tree, 0o040000).unwrap();
+    let tree = git2_repo.find_tree(tree_builder.write().unwrap()).unwrap();
+    let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+    let commit = git2_repo
+        .commit(None, &signature, &signature, "Add data", &tree, &[&parent])
+        .unwrap();
+
+    let result = repo.verify(&file_sizes_config(), commit);
+
+    assert!(
+        matches!(&result, PolicyResult::InvalidLfsPointer(id, path) if *id == commit && path == "data/data.csv"),
+        "Unexpected result: {:?}",
+        result
+    );
+}
@@ -0,0 +1,244 @@
+use capn::config::VerifyFilenamesConfig;
+use capn::git::LiveGit;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository, Signature};
+use std::error::Error;
+
+mod common;
+use common::TempTestRepo;
+
+const MASTER: &str = "eb5e0185546b0bb1a13feec6b9ee8b39985fea42";
+
+impl TempTestRepo {
+    /// Commits a file at each of the paths on top of `parent`.
+    fn commit(&self, parent: Oid, paths: &[&[u8]]) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let mut tree = parent.tree_id();
+        for path in paths {
+            let names = path.split(|b| *b == b'/').collect::<Vec<_>>();
+            tree = Self::insert(&repo, Some(tree), &names);
+        }
+        let tree = repo.find_tree(tree).unwrap();
+        let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+        repo.commit(None, &signature, &signature, "Add files", &tree, &[&parent])
+            .unwrap()
+    }
+
+    /// Renames a file in the root of the tree of `parent`.
+    fn rename(&self, parent: Oid, from: &[u8], to: &[u8]) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let mut tree_builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        let blob = tree_builder.get(from).unwrap().unwrap().id();
+        tree_builder.remove(from).unwrap();
+        tree_builder.insert(to, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree_builder.write().unwrap()).unwrap();
+        let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+        repo.commit(
+            None,
+            &signature,
+            &signature,
+            "Rename file",
+            &tree,
+            &[&parent],
+        )
+        .unwrap()
+    }
+
+    fn insert(repo: &Repository, tree: Option<Oid>, names: &[&[u8]]) -> Oid {
+        let tree = tree.map(|id| repo.find_tree(id).unwrap());
+        let mut tree_builder = repo.treebuilder(tree.as_ref()).unwrap();
+        match names {
+            [name] => {
+                let blob = repo.blob(b"contents").unwrap();
+                tree_builder.insert(*name, blob, 0o100644).unwrap();
+            }
+            [name, rest @ ..] => {
+                let subtree = tree_builder.get(*name).unwrap().map(|entry| entry.id());
+                let subtree = Self::insert(repo, subtree, rest);
+                tree_builder.insert(*name, subtree, 0o040000).unwrap();
+            }
+            [] => unreachable!(),
+        }
+        tree_builder.write().unwrap()
+    }
+
+    fn verify(&self, config: &VerifyFilenamesConfig, new_commit_id: Oid) -> PolicyResult {
+        self.try_verify(config, new_commit_id).unwrap()
+    }
+
+    fn try_verify(
+        &self,
+        config: &VerifyFilenamesConfig,
+        new_commit_id: Oid,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        policies::verify_filenames::<LiveGit>(
+            &LiveGit::default(&self.path).unwrap(),
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                MASTER,
+                &new_commit_id.to_string(),
+                "refs/heads/master",
+            )
+            .unwrap(),
+        )
+    }
+}
+
+fn filenames_config() -> VerifyFilenamesConfig {
+    VerifyFilenamesConfig {
+        verify_case_collisions: true,
+        verify_windows_names: true,
+        verify_unicode: true,
+        max_path_length: Some(260),
+        time_budget_ms: None,
+    }
+}
+
+fn master() -> Oid {
+    Oid::from_str(MASTER).unwrap()
+}
+
+#[test]
+fn portable_filenames_pass() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(master(), &[b"docs/readme.md", b"src/lib.rs"]);
+
+    let result = repo.verify(&filenames_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn case_collisions_are_reported_against_the_commit_that_made_them() {
+    let repo = TempTestRepo::new();
+    let first = repo.commit(master(), &[b"docs/Readme.md"]);
+    let second = repo.commit(first, &[b"docs/README.md"]);
+
+    let result = repo.verify(&filenames_config(), second);
+
+    match result {
+        PolicyResult::FilenameCaseCollision(id, path, other) => {
+            assert_eq!(
+                (id, path.as_str(), other.as_str()),
+                (second, "docs/README.md", "docs/Readme.md")
+            );
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn renaming_a_file_to_a_different_case_passes() {
+    let repo = TempTestRepo::new();
+    let first = repo.commit(master(), &[b"Readme.md"]);
+    let second = repo.rename(first, b"Readme.md", b"README.md");
+
+    let result = repo.verify(&filenames_config(), second);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn directories_that_differ_by_case_collide() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(master(), &[b"Docs/a.md", b"docs/b.md"]);
+
+    let result = repo.verify(&filenames_config(), commit);
+
+    assert!(
+        matches!(&result, PolicyResult::FilenameCaseCollision(id, _, _) if *id == commit),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn windows_reserved_names_fail() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(master(), &[b"notes/aux.txt"]);
+
+    let result = repo.verify(&filenames_config(), commit);
+
+    assert!(
+        matches!(&result, PolicyResult::InvalidFilename(id, path, _) if *id == commit && path == "notes/aux.txt"),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn non_nfc_filenames_fail() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(master(), &["cafe\u{301}.txt".as_bytes()]);
+
+    let result = repo.verify(&filenames_config(), commit);
+
+    assert!(
+        matches!(&result, PolicyResult::InvalidFilename(id, _, reason) if *id == commit && reason == "is not NFC normalised"),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn invalid_utf8_filenames_fail() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(master(), &[b"caf\xe9.txt"]);
+
+    let result = repo.verify(&filenames_config(), commit);
+
+    assert!(
+        matches!(&result, PolicyResult::InvalidFilename(id, _, reason) if *id == commit && reason == "is not valid UTF-8"),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn long_paths_fail() {
+    let repo = TempTestRepo::new();
+    let path = format!("{}/file.txt", "a".repeat(20));
+    let commit = repo.commit(master(), &[path.as_bytes()]);
+
+    let result = repo.verify(
+        &VerifyFilenamesConfig {
+            max_path_length: Some(20),
+            ..filenames_config()
+        },
+        commit,
+    );
+
+    match result {
+        PolicyResult::PathTooLong(id, long_path, length, max) => {
+            assert_eq!((id, long_path, length, max), (commit, path, 29, 20));
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn verification_stops_when_the_time_budget_runs_out() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(master(), &[b"docs/readme.md"]);
+
+    let error = repo
+        .try_verify(
+            &VerifyFilenamesConfig {
+                time_budget_ms: Some(0),
+                ..filenames_config()
+            },
+            commit,
+        )
+        .unwrap_err();
+
+    assert!(
+        error.to_string().contains("Time budget exceeded"),
+        "Error: {}",
+        error
+    );
+}
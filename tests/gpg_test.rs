@@ -1,6 +1,8 @@
+use capn::error::OutOfTime;
 use capn::gpg::*;
 use capn::keyring::{Fingerprint, Keyring};
 use std::collections::{HashMap, HashSet};
+use std::time::Instant;
 
 #[test]
 fn receive_keys_attempts_to_fetch_unfetched_keys() {
@@ -13,6 +15,7 @@ fn receive_keys_attempts_to_fetch_unfetched_keys() {
             id: "1212121212121212112".to_string(),
             name: "Test User".to_string(),
             email: "test@jemstep.com".to_string(),
+            groups: Vec::new(),
             public_key_is_available_locally: false,
         },
     );
@@ -41,6 +44,7 @@ fn receive_keys_does_not_fetch_already_fetched_keys() {
             id: "1212121212121212112".to_string(),
             name: "Test User".to_string(),
             email: "test@jemstep.com".to_string(),
+            groups: Vec::new(),
             public_key_is_available_locally: true,
         },
     );
@@ -57,3 +61,32 @@ fn receive_keys_does_not_fetch_already_fetched_keys() {
     // This key is made up, so this is successful only if there was no request made
     assert!(result.is_ok());
 }
+
+#[test]
+fn receive_keys_before_does_not_fetch_after_the_deadline() {
+    let mut keyring = Keyring {
+        fingerprints: HashMap::new(),
+    };
+    keyring.fingerprints.insert(
+        "test@jemstep.com".to_string(),
+        Fingerprint {
+            id: "1212121212121212112".to_string(),
+            name: "Test User".to_string(),
+            email: "test@jemstep.com".to_string(),
+            groups: Vec::new(),
+            public_key_is_available_locally: false,
+        },
+    );
+
+    let mut emails = HashSet::new();
+    emails.insert("test@jemstep.com");
+
+    let result = LiveGpg {
+        parallel_fetch: false,
+        keyserver: "keyserver".to_string(),
+    }
+    .receive_keys_before(&mut keyring, &emails, Some(Instant::now()));
+
+    assert!(result.unwrap_err().downcast_ref::<OutOfTime>().is_some());
+    assert!(keyring.requires_public_key_download("test@jemstep.com"));
+}
@@ -0,0 +1,227 @@
+use capn::config::VerifyGitCommitsConfig;
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository};
+
+mod common;
+use common::{Person, TempTestRepo};
+
+const ALICE: Person = ("Alice Smith", "alice@jemstep.com");
+const BOB: Person = ("Bob Jones", "bob@jemstep.com");
+
+/// A copy of the test repo, with Alice and Bob added to the team
+/// fingerprints on master.
+fn test_repo() -> TempTestRepo {
+    let mut repo = TempTestRepo::with_keys(&[ALICE, BOB]);
+    let team_fingerprints =
+        repo.keyring().team_member(ALICE, "") + &repo.keyring().team_member(BOB, "");
+    let master = repo.commit(
+        repo.master,
+        ALICE,
+        ALICE,
+        None,
+        "Add team",
+        ("TEAM_FINGERPRINTS", &team_fingerprints),
+    );
+    repo.set_master(master);
+    repo
+}
+
+impl TempTestRepo {
+    /// Commits the file on top of `parent`, optionally signed with
+    /// the key for `signing_key`.
+    fn commit(
+        &self,
+        parent: Oid,
+        author: (&str, &str),
+        committer: (&str, &str),
+        signing_key: Option<&str>,
+        message: &str,
+        file: (&str, &str),
+    ) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let blob = repo.blob(file.1.as_bytes()).unwrap();
+        let mut tree_builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        tree_builder.insert(file.0, blob, 0o100644).unwrap();
+        let tree = tree_builder.write().unwrap().to_string();
+        let parent = parent.id().to_string();
+        let mut args = vec!["commit-tree", &tree, "-p", &parent, "-m", message];
+        let sign = signing_key.map(|key| format!("-S{}", key));
+        if let Some(sign) = &sign {
+            args.push(sign);
+        }
+        let commit = self.git_as(author, committer, &args);
+        Oid::from_str(commit.trim()).unwrap()
+    }
+
+    fn change(
+        &self,
+        author: (&str, &str),
+        committer: (&str, &str),
+        signing_key: &str,
+        message: &str,
+    ) -> Oid {
+        self.commit(
+            self.master,
+            author,
+            committer,
+            Some(signing_key),
+            message,
+            ("change.txt", "change"),
+        )
+    }
+
+    fn override_tag(&self, tagger: (&str, &str), commit: Oid) {
+        let commit = commit.to_string();
+        self.git_as(
+            tagger,
+            tagger,
+            &[
+                "tag",
+                "-s",
+                "-u",
+                tagger.1,
+                "-m",
+                "Override",
+                "capn-override-identity",
+                &commit,
+            ],
+        );
+    }
+
+    fn verify(&self, config: &VerifyGitCommitsConfig, new_commit_id: Oid) -> PolicyResult {
+        policies::verify_git_commits::<LiveGit, MockGpg>(
+            &LiveGit::default(&self.path).unwrap(),
+            MockGpg,
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                &self.master.to_string(),
+                &new_commit_id.to_string(),
+                "refs/heads/master",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn identity_config() -> VerifyGitCommitsConfig {
+    VerifyGitCommitsConfig {
+        author_domain: "jemstep.com".into(),
+        committer_domain: "jemstep.com".into(),
+        keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
+        team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
+        verify_email_addresses: false,
+        verify_commit_signatures: false,
+        verify_signer_is_committer: true,
+        verify_identity_names: true,
+        verify_author_is_committer: true,
+        override_tags_required: 1.into(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn commit_signed_by_its_committer_passes() {
+    let repo = test_repo();
+    let commit = repo.change(ALICE, ALICE, ALICE.1, "Change");
+
+    let result = repo.verify(&identity_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn commit_signed_by_someone_else_fails() {
+    let repo = test_repo();
+    let commit = repo.change(ALICE, ALICE, BOB.1, "Change");
+
+    let result = repo.verify(&identity_config(), commit);
+
+    match result {
+        PolicyResult::SignerIsNotCommitter(id, committer, signer) => {
+            assert_eq!(id, commit);
+            assert_eq!(committer, ALICE.1);
+            assert_eq!(signer, BOB.1);
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn names_need_to_match_the_keyring() {
+    let repo = test_repo();
+    let alias = ("alice  smith", ALICE.1);
+    let commit = repo.change(alias, alias, ALICE.1, "Change");
+
+    let result = repo.verify(&identity_config(), commit);
+
+    match result {
+        PolicyResult::NameDoesNotMatchKeyring(_, name, expected) => {
+            assert_eq!((name.as_str(), expected.as_str()), (alias.0, ALICE.0));
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn normalised_names_ignore_case_and_whitespace() {
+    let repo = test_repo();
+    let alias = ("alice  smith", ALICE.1);
+    let commit = repo.change(alias, alias, ALICE.1, "Change");
+
+    let result = repo.verify(
+        &VerifyGitCommitsConfig {
+            normalise_identity_names: true,
+            ..identity_config()
+        },
+        commit,
+    );
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn author_needs_to_be_the_committer() {
+    let repo = test_repo();
+    let commit = repo.change(BOB, ALICE, ALICE.1, "Change");
+
+    let result = repo.verify(&identity_config(), commit);
+
+    assert!(
+        matches!(result, PolicyResult::AuthorIsNotCommitter(id) if id == commit),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn cherry_picks_can_be_committed_by_someone_else() {
+    let repo = test_repo();
+    let commit = repo.change(
+        BOB,
+        ALICE,
+        ALICE.1,
+        "Change\n\n(cherry picked from commit eb5e0185546b0bb1a13feec6b9ee8b39985fea42)",
+    );
+
+    let result = repo.verify(&identity_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn override_tags_apply_without_email_or_signature_checks() {
+    let repo = test_repo();
+    let commit = repo.change(ALICE, ALICE, BOB.1, "Change");
+    repo.override_tag(BOB, commit);
+
+    let result = repo.verify(&identity_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
@@ -0,0 +1,206 @@
+use capn::config::{VerifyGitCommitsConfig, VerifySignOffConfig};
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository, Signature};
+
+mod common;
+use common::{Person, TempTestRepo};
+
+const ALICE: Person = ("Alice Smith", "alice@jemstep.com");
+const OLD_ALICE: Person = ("alice", "alice@old-domain.com");
+
+/// A copy of the test repo, with a mailmap committed to master.
+fn test_repo(mailmap: &str) -> TempTestRepo {
+    let mut repo = TempTestRepo::new();
+    let master = repo.commit(repo.master, ALICE, ALICE, (".mailmap", mailmap));
+    repo.set_master(master);
+    repo
+}
+
+impl TempTestRepo {
+    fn commit(
+        &self,
+        parent: Oid,
+        author: (&str, &str),
+        committer: (&str, &str),
+        file: (&str, &str),
+    ) -> Oid {
+        self.commit_with_message(parent, author, committer, file, "Change")
+    }
+
+    fn commit_with_message(
+        &self,
+        parent: Oid,
+        author: (&str, &str),
+        committer: (&str, &str),
+        file: (&str, &str),
+        message: &str,
+    ) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let blob = repo.blob(file.1.as_bytes()).unwrap();
+        let mut tree_builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        tree_builder.insert(file.0, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree_builder.write().unwrap()).unwrap();
+        let author = Signature::now(author.0, author.1).unwrap();
+        let committer = Signature::now(committer.0, committer.1).unwrap();
+        repo.commit(None, &author, &committer, message, &tree, &[&parent])
+            .unwrap()
+    }
+
+    fn change(&self, author: (&str, &str), committer: (&str, &str)) -> Oid {
+        self.commit(self.master, author, committer, ("change.txt", "change"))
+    }
+
+    fn verify_sign_off(&self, new_commit_id: Oid) -> PolicyResult {
+        policies::verify_sign_off::<LiveGit>(
+            &LiveGit::default(&self.path).unwrap(),
+            &VerifySignOffConfig {
+                mailmap_file: Some(".mailmap".to_string()),
+            },
+            &ReferenceUpdate::from_git_hook_format(
+                &self.master.to_string(),
+                &new_commit_id.to_string(),
+                "refs/heads/feature",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn verify(&self, config: &VerifyGitCommitsConfig, new_commit_id: Oid) -> PolicyResult {
+        policies::verify_git_commits::<LiveGit, MockGpg>(
+            &LiveGit::default(&self.path).unwrap(),
+            MockGpg,
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                &self.master.to_string(),
+                &new_commit_id.to_string(),
+                "refs/heads/feature",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn mailmap_config() -> VerifyGitCommitsConfig {
+    VerifyGitCommitsConfig {
+        author_domain: "jemstep.com".into(),
+        committer_domain: "jemstep.com".into(),
+        mailmap_file: Some(".mailmap".to_string()),
+        keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
+        team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
+        verify_commit_signatures: false,
+        override_tags_required: 1.into(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn mailmapped_emails_are_checked_against_the_email_domains() {
+    let repo = test_repo("Alice Smith <alice@jemstep.com> <alice@old-domain.com>\n");
+    let commit = repo.change(OLD_ALICE, ALICE);
+
+    let result = repo.verify(&mailmap_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn emails_are_not_mailmapped_without_a_mailmap_file() {
+    let repo = test_repo("Alice Smith <alice@jemstep.com> <alice@old-domain.com>\n");
+    let commit = repo.change(OLD_ALICE, ALICE);
+
+    let result = repo.verify(
+        &VerifyGitCommitsConfig {
+            mailmap_file: None,
+            ..mailmap_config()
+        },
+        commit,
+    );
+
+    match result {
+        PolicyResult::InvalidAuthorEmail(id, email, _) => {
+            assert_eq!((id, email.as_str()), (commit, OLD_ALICE.1))
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn rejected_emails_report_the_email_in_the_commit() {
+    let repo = test_repo("<alice@gmail.com> <alice@old-domain.com>\n");
+    let commit = repo.change(OLD_ALICE, ALICE);
+
+    let result = repo.verify(&mailmap_config(), commit);
+
+    match result {
+        PolicyResult::InvalidAuthorEmail(_, email, _) => assert_eq!(
+            email,
+            "alice@gmail.com (mailmapped from alice@old-domain.com)"
+        ),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn mailmapped_authors_can_be_their_committers() {
+    let repo = test_repo("Alice Smith <alice@jemstep.com> <alice@old-domain.com>\n");
+    let commit = repo.change(OLD_ALICE, ALICE);
+    let config = VerifyGitCommitsConfig {
+        verify_email_addresses: false,
+        verify_author_is_committer: true,
+        ..mailmap_config()
+    };
+
+    let with_mailmap = repo.verify(&config, commit);
+    let without_mailmap = repo.verify(
+        &VerifyGitCommitsConfig {
+            mailmap_file: None,
+            ..config.clone()
+        },
+        commit,
+    );
+
+    assert!(with_mailmap.is_ok(), "Error: {:?}", with_mailmap);
+    assert!(
+        matches!(without_mailmap, PolicyResult::AuthorIsNotCommitter(id) if id == commit),
+        "Unexpected result: {:?}",
+        without_mailmap
+    );
+}
+
+#[test]
+fn mailmapped_authors_can_sign_off_as_the_identity_in_the_commit() {
+    let repo = test_repo("Alice Smith <alice@jemstep.com> <alice@old-domain.com>\n");
+    let commit = repo.commit_with_message(
+        repo.master,
+        OLD_ALICE,
+        ALICE,
+        ("change.txt", "change"),
+        "Change\n\nSigned-off-by: alice <alice@old-domain.com>\n",
+    );
+    let result = repo.verify_sign_off(commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn mailmapped_authors_can_sign_off_as_their_canonical_identity() {
+    let repo = test_repo("Alice Smith <alice@jemstep.com> <alice@old-domain.com>\n");
+    let commit = repo.commit_with_message(
+        repo.master,
+        OLD_ALICE,
+        ALICE,
+        ("change.txt", "change"),
+        "Change\n\nSigned-off-by: Alice Smith <alice@jemstep.com>\n",
+    );
+    let result = repo.verify_sign_off(commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
@@ -0,0 +1,211 @@
+use capn::config::{Config, VerifyParentCountConfig, VerifySignOffConfig};
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository, Signature};
+
+mod common;
+use common::TempTestRepo;
+
+const MASTER: &str = "eb5e0185546b0bb1a13feec6b9ee8b39985fea42";
+
+const SIGN_OFF: &str = "Signed-off-by: Test User <blackhole@jemstep.com>";
+
+impl TempTestRepo {
+    /// Branches off master, adding each file in its own signed off
+    /// commit, and returns the branch tips.
+    fn branches(&self, file_names: &[&str]) -> Vec<Oid> {
+        let master = Oid::from_str(MASTER).unwrap();
+        file_names
+            .iter()
+            .map(|file_name| {
+                let tree = self.tree_with(master, &[file_name]);
+                self.commit_tree(
+                    tree,
+                    &[master],
+                    &format!("Add {}\n\n{}", file_name, SIGN_OFF),
+                )
+            })
+            .collect()
+    }
+
+    /// The tree of `base`, with the files added to it.
+    fn tree_with(&self, base: Oid, file_names: &[&str]) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let base = repo.find_commit(base).unwrap();
+        let mut tree_builder = repo.treebuilder(Some(&base.tree().unwrap())).unwrap();
+        for file_name in file_names {
+            let blob = repo.blob(file_name.as_bytes()).unwrap();
+            tree_builder.insert(file_name, blob, 0o100644).unwrap();
+        }
+        tree_builder.write().unwrap()
+    }
+
+    fn commit_tree(&self, tree: Oid, parents: &[Oid], message: &str) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        let parents = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect::<Vec<_>>();
+        let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+        repo.commit(
+            None,
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents.iter().collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    fn verify_parent_count(
+        &self,
+        config: &VerifyParentCountConfig,
+        new_commit_id: Oid,
+    ) -> PolicyResult {
+        policies::verify_parent_count::<LiveGit>(
+            &LiveGit::default(&self.path).unwrap(),
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                MASTER,
+                &new_commit_id.to_string(),
+                "refs/heads/feature",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn verify(&self, config: &VerifySignOffConfig, new_commit_id: Oid) -> PolicyResult {
+        policies::verify_sign_off::<LiveGit>(
+            &LiveGit::default(&self.path).unwrap(),
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                MASTER,
+                &new_commit_id.to_string(),
+                "refs/heads/feature",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn merge_parents_config() -> VerifySignOffConfig {
+    VerifySignOffConfig::default()
+}
+
+#[test]
+fn trivial_octopus_merge_does_not_need_sign_off() {
+    let repo = TempTestRepo::new();
+    let parents = repo.branches(&["a.txt", "b.txt", "c.txt"]);
+    let tree = repo.tree_with(parents[0], &["b.txt", "c.txt"]);
+    let merge = repo.commit_tree(tree, &parents, "Merge branches");
+
+    let result = repo.verify(&merge_parents_config(), merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn octopus_merge_with_extra_changes_needs_sign_off() {
+    let repo = TempTestRepo::new();
+    let parents = repo.branches(&["a.txt", "b.txt", "c.txt"]);
+    let tree = repo.tree_with(parents[0], &["b.txt", "c.txt", "injected.txt"]);
+    let merge = repo.commit_tree(tree, &parents, "Merge branches");
+
+    let result = repo.verify(&merge_parents_config(), merge);
+
+    assert!(
+        matches!(result, PolicyResult::MissingSignOff(id, _) if id == merge),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn octopus_merge_missing_a_parents_changes_needs_sign_off() {
+    let repo = TempTestRepo::new();
+    let parents = repo.branches(&["a.txt", "b.txt", "c.txt"]);
+    let tree = repo.tree_with(parents[0], &["b.txt"]);
+    let merge = repo.commit_tree(tree, &parents, "Merge branches");
+
+    let result = repo.verify(&merge_parents_config(), merge);
+
+    assert!(
+        matches!(result, PolicyResult::MissingSignOff(id, _) if id == merge),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn reproducing_an_octopus_merge_does_not_write_to_the_repo() {
+    let repo = TempTestRepo::new();
+    let parents = repo.branches(&["a.txt", "b.txt", "c.txt"]);
+    let tree = repo.tree_with(parents[0], &["b.txt", "c.txt", "injected.txt"]);
+    let merge = repo.commit_tree(tree, &parents, "Merge branches");
+    let loose_objects = repo.loose_object_count();
+
+    repo.verify(&merge_parents_config(), merge);
+
+    assert_eq!(repo.loose_object_count(), loose_objects);
+}
+
+#[test]
+fn merges_with_too_many_parents_are_rejected() {
+    let repo = TempTestRepo::new();
+    let parents = repo.branches(&["a.txt", "b.txt", "c.txt"]);
+    let tree = repo.tree_with(parents[0], &["b.txt", "c.txt"]);
+    let merge = repo.commit_tree(tree, &parents, "Merge branches");
+
+    let result = repo.verify_parent_count(&VerifyParentCountConfig { max_parents: 2 }, merge);
+
+    match result {
+        PolicyResult::TooManyParents(id, parents, max) => {
+            assert_eq!((id, parents, max), (merge, 3, 2));
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn merges_within_the_parent_limit_pass() {
+    let repo = TempTestRepo::new();
+    let parents = repo.branches(&["a.txt", "b.txt"]);
+    let tree = repo.tree_with(parents[0], &["b.txt"]);
+    let merge = repo.commit_tree(tree, &parents, "Merge branches");
+
+    let result = repo.verify_parent_count(&VerifyParentCountConfig { max_parents: 2 }, merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn parent_count_is_checked_without_the_verify_git_commits_config() {
+    let repo = TempTestRepo::new();
+    let parents = repo.branches(&["a.txt", "b.txt", "c.txt"]);
+    let tree = repo.tree_with(parents[0], &["b.txt", "c.txt"]);
+    let merge = repo.commit_tree(tree, &parents, "Merge branches");
+
+    let result = capn::pre_receive::<LiveGit, MockGpg>(
+        &LiveGit::default(&repo.path).unwrap(),
+        MockGpg,
+        &Config::from_toml_string("[verify_parent_count]\nmax_parents = 2\n").unwrap(),
+        MASTER,
+        &merge.to_string(),
+        "refs/heads/feature",
+    )
+    .unwrap();
+
+    assert!(
+        matches!(result, PolicyResult::TooManyParents(id, 3, 2) if id == merge),
+        "Unexpected result: {:?}",
+        result
+    );
+}
@@ -1,14 +1,25 @@
-use capn;
-use capn::config::{Config, GitConfig, VerifyGitCommitsConfig};
+use capn::config::{
+    Config, EmailDomains, GitConfig, LinearHistoryConfig, TimeBudgetOutcome, VerifyGitCommitsConfig,
+};
+use capn::error::OutOfTime;
 use capn::policies;
+use capn::policies::commit_checks::{CommitCheck, CommitCheckContext, CommitChecks};
+use capn::policies::policy_result::PolicyResult;
 use capn::reference_update::ReferenceUpdate;
 
-use capn::git::LiveGit;
+use capn::git::{Git, LiveGit};
 use capn::gpg::test::MockGpg;
+use capn::gpg::{Gpg, KeyValidity};
+use capn::keyring::Keyring;
 
 use capn::logger::Logger;
 
+use git2::Oid;
+use std::collections::HashSet;
+use std::error::Error;
 use std::process::*;
+use std::thread;
+use std::time::Duration;
 
 use std::sync::Once;
 
@@ -34,7 +45,7 @@ fn set_current_dir_to_test_repo() {
 fn import_test_key() {
     let project_root = env!("CARGO_MANIFEST_DIR");
     let status = Command::new("gpg")
-        .args(&[
+        .args([
             "--import",
             &format!("{}/tests/test-public-key.asc", project_root),
         ])
@@ -47,17 +58,14 @@ fn import_test_key() {
 
 fn verify_commits_config() -> VerifyGitCommitsConfig {
     VerifyGitCommitsConfig {
-        author_domain: "jemstep.com".to_string(),
-        committer_domain: "jemstep.com".to_string(),
+        author_domain: "jemstep.com".into(),
+        committer_domain: "jemstep.com".into(),
         keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
         team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
-        recv_keys_par: true,
-        verify_email_addresses: true,
-        verify_commit_signatures: true,
         verify_different_authors: true,
-        verify_rebased: false,
         override_tag_pattern: Some("capn-override-*".to_string()),
-        override_tags_required: 1,
+        override_tags_required: 1.into(),
+        ..Default::default()
     }
 }
 
@@ -68,6 +76,17 @@ fn verify_git_commits_happy_path_from_empty_through_pre_receive() {
         git: GitConfig::default(),
         prepend_branch_name: None,
         verify_git_commits: Some(verify_commits_config()),
+        verify_tags: None,
+        verify_file_sizes: None,
+        verify_filenames: None,
+        verify_sign_off: None,
+        verify_linear_history: vec![],
+        verify_subjects: None,
+        verify_parent_count: None,
+        verify_submodules_and_symlinks: None,
+        external_policy: vec![],
+        wasm_policy: vec![],
+        other: Default::default(),
     };
     let result = capn::pre_receive::<LiveGit, MockGpg>(
         &LiveGit::default("./").unwrap(),
@@ -407,7 +426,7 @@ fn verify_tagged_git_commits_not_overridden_if_not_enough_tags() {
         &LiveGit::default("./").unwrap(),
         MockGpg,
         &VerifyGitCommitsConfig {
-            override_tags_required: 2,
+            override_tags_required: 2.into(),
             ..verify_commits_config()
         },
         &ReferenceUpdate::from_git_hook_format(
@@ -525,3 +544,399 @@ fn verify_rebased_branch_is_allowed_when_a_force_push_has_occurred() {
     .unwrap();
     assert!(result.is_ok(), "Error: {:?}", result);
 }
+
+#[test]
+fn verify_git_commits_fails_closed_when_time_budget_is_exceeded() {
+    before_all();
+    let result = policies::verify_git_commits::<LiveGit, MockGpg>(
+        &LiveGit::default("./").unwrap(),
+        MockGpg,
+        &VerifyGitCommitsConfig {
+            time_budget_ms: Some(0),
+            on_time_budget_exceeded: TimeBudgetOutcome::FailClosed,
+            ..verify_commits_config()
+        },
+        &ReferenceUpdate::from_git_hook_format(
+            "7f9763e189ade34345e683ab7e0c22d164280452",
+            "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+            "refs/heads/master",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    match result {
+        PolicyResult::TimeBudgetExceeded(completed, not_run, commits_verified) => {
+            assert!(completed.is_empty());
+            assert!(!not_run.is_empty());
+            assert_eq!(commits_verified, 0);
+        }
+        _ => panic!("Expected time budget to be exceeded, was {:?}", result),
+    }
+}
+
+// Takes longer than the time budget on each commit, like a check
+// that is waiting on GPG would.
+struct SlowCheck;
+
+impl<G: Git, P: Gpg> CommitCheck<G, P> for SlowCheck {
+    fn name(&self) -> &str {
+        "slow"
+    }
+
+    fn is_enabled(&self, _context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>> {
+        Ok(true)
+    }
+
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        for verified in 0..context.commits.len() {
+            OutOfTime::check(context.deadline, verified)?;
+            thread::sleep(Duration::from_millis(100));
+        }
+        Ok(PolicyResult::Ok)
+    }
+}
+
+#[test]
+fn verify_git_commits_stops_partway_through_a_check_when_time_budget_is_exceeded() {
+    before_all();
+    let mut checks = CommitChecks::default();
+    checks.register(SlowCheck);
+    let result = policies::verify_git_commits_with_checks::<LiveGit, MockGpg>(
+        &checks,
+        &LiveGit::default("./").unwrap(),
+        &MockGpg,
+        &VerifyGitCommitsConfig {
+            time_budget_ms: Some(50),
+            on_time_budget_exceeded: TimeBudgetOutcome::FailClosed,
+            ..verify_commits_config()
+        },
+        &ReferenceUpdate::from_git_hook_format(
+            "7f9763e189ade34345e683ab7e0c22d164280452",
+            "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+            "refs/heads/valid-merge",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    match result {
+        PolicyResult::TimeBudgetExceeded(completed, not_run, commits_verified) => {
+            assert!(completed.is_empty());
+            assert_eq!(not_run, vec!["slow".to_string()]);
+            assert_eq!(commits_verified, 1);
+        }
+        _ => panic!("Expected time budget to be exceeded, was {:?}", result),
+    }
+}
+
+// Receives keys after the deadline has already passed, like a
+// keyserver that answers just too late.
+struct SlowKeyserverGpg;
+
+impl Gpg for SlowKeyserverGpg {
+    fn receive_keys(
+        &self,
+        keyring: &mut Keyring,
+        emails: &HashSet<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        thread::sleep(Duration::from_millis(100));
+        MockGpg.receive_keys(keyring, emails)
+    }
+
+    fn key_validity(&self, fingerprint: &str) -> Result<Option<KeyValidity>, Box<dyn Error>> {
+        MockGpg.key_validity(fingerprint)
+    }
+
+    fn sign(&self, contents: &str, signing_key: &str) -> Result<String, Box<dyn Error>> {
+        MockGpg.sign(contents, signing_key)
+    }
+}
+
+#[test]
+fn verify_git_commits_does_not_verify_signatures_after_the_time_budget_is_exceeded() {
+    before_all();
+    let result = policies::verify_git_commits::<LiveGit, SlowKeyserverGpg>(
+        &LiveGit::default("./").unwrap(),
+        SlowKeyserverGpg,
+        &VerifyGitCommitsConfig {
+            verify_email_addresses: false,
+            verify_different_authors: false,
+            override_tag_pattern: None,
+            time_budget_ms: Some(50),
+            on_time_budget_exceeded: TimeBudgetOutcome::FailClosed,
+            ..verify_commits_config()
+        },
+        &ReferenceUpdate::from_git_hook_format(
+            "7f9763e189ade34345e683ab7e0c22d164280452",
+            "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+            "refs/heads/valid-merge",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    match result {
+        PolicyResult::TimeBudgetExceeded(completed, not_run, commits_verified) => {
+            assert!(completed.is_empty());
+            assert_eq!(not_run, vec!["commit signatures".to_string()]);
+            assert_eq!(commits_verified, 0);
+        }
+        _ => panic!("Expected time budget to be exceeded, was {:?}", result),
+    }
+}
+
+#[test]
+fn verify_git_commits_fails_open_when_time_budget_is_exceeded() {
+    before_all();
+    let result = policies::verify_git_commits::<LiveGit, MockGpg>(
+        &LiveGit::default("./").unwrap(),
+        MockGpg,
+        &VerifyGitCommitsConfig {
+            time_budget_ms: Some(0),
+            on_time_budget_exceeded: TimeBudgetOutcome::FailOpen,
+            ..verify_commits_config()
+        },
+        &ReferenceUpdate::from_git_hook_format(
+            "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+            "d2e3bfdc923986d04e7a6368b5fdd78b1ddf84f1",
+            "refs/heads/master",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn verify_git_commits_completes_within_generous_time_budget() {
+    before_all();
+    let result = policies::verify_git_commits::<LiveGit, MockGpg>(
+        &LiveGit::default("./").unwrap(),
+        MockGpg,
+        &VerifyGitCommitsConfig {
+            time_budget_ms: Some(60_000),
+            ..verify_commits_config()
+        },
+        &ReferenceUpdate::from_git_hook_format(
+            "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+            "d2e3bfdc923986d04e7a6368b5fdd78b1ddf84f1",
+            "refs/heads/master",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert!(
+        matches!(result, PolicyResult::UnsignedCommit(_)),
+        "Error: {:?}",
+        result
+    );
+}
+
+#[test]
+fn verify_merge_is_blocked_on_linear_history_ref() {
+    before_all();
+    let result = policies::verify_linear_history::<LiveGit>(
+        &LiveGit::default("./").unwrap(),
+        &[LinearHistoryConfig {
+            refs: "refs/heads/master".to_string(),
+            fast_forward_only: false,
+        }],
+        &ReferenceUpdate::from_git_hook_format(
+            "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+            "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+            "refs/heads/master",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    match result {
+        PolicyResult::MergeCommitsNotAllowed(_, merges) => assert_eq!(
+            merges,
+            vec![Oid::from_str("3eb315d10e2ad89555d7bfc78a1db1ce07bce434").unwrap()]
+        ),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn linear_history_is_checked_without_the_verify_git_commits_config() {
+    before_all();
+    let result = capn::pre_receive::<LiveGit, MockGpg>(
+        &LiveGit::default("./").unwrap(),
+        MockGpg,
+        &Config::from_toml_string("[[verify_linear_history]]\nrefs = \"refs/heads/master\"\n")
+            .unwrap(),
+        "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+        "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+        "refs/heads/master",
+    )
+    .unwrap();
+    assert!(
+        matches!(result, PolicyResult::MergeCommitsNotAllowed(_, _)),
+        "Error: {:?}",
+        result
+    );
+}
+
+#[test]
+fn verify_merge_is_allowed_on_refs_without_linear_history() {
+    before_all();
+    let result = policies::verify_linear_history::<LiveGit>(
+        &LiveGit::default("./").unwrap(),
+        &[LinearHistoryConfig {
+            refs: "refs/heads/release/*".to_string(),
+            fast_forward_only: false,
+        }],
+        &ReferenceUpdate::from_git_hook_format(
+            "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+            "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+            "refs/heads/master",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn verify_force_push_is_blocked_when_fast_forward_only() {
+    before_all();
+    let result = policies::verify_linear_history::<LiveGit>(
+        &LiveGit::default("./").unwrap(),
+        &[LinearHistoryConfig {
+            refs: "refs/heads/*".to_string(),
+            fast_forward_only: true,
+        }],
+        &ReferenceUpdate::from_git_hook_format(
+            "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+            "02bb68e637bda7667428f8fb3b709be5720fe76a",
+            "refs/heads/master",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert!(
+        matches!(result, PolicyResult::NotFastForward(_, _)),
+        "Error: {:?}",
+        result
+    );
+}
+
+#[test]
+fn verify_invalid_author_email_says_which_domains_are_allowed() {
+    before_all();
+    let result = policies::verify_git_commits::<LiveGit, MockGpg>(
+        &LiveGit::default("./").unwrap(),
+        MockGpg,
+        &VerifyGitCommitsConfig {
+            author_domain: EmailDomains::Many(vec![
+                "jemstep.com".to_string(),
+                "*.jemstep.com".to_string(),
+            ]),
+            ..verify_commits_config()
+        },
+        &ReferenceUpdate::from_git_hook_format(
+            "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+            "afe2141ef20abd098927adc66d6728821cb34f59",
+            "refs/heads/master",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    match result {
+        PolicyResult::InvalidAuthorEmail(_, email, rule) => {
+            assert_eq!(email, "jemstep.com@gmail.com");
+            assert_eq!(rule, "its domain does not match jemstep.com, *.jemstep.com");
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn verify_email_domains_are_globs_that_ignore_case() {
+    before_all();
+    let result = policies::verify_git_commits::<LiveGit, MockGpg>(
+        &LiveGit::default("./").unwrap(),
+        MockGpg,
+        &VerifyGitCommitsConfig {
+            author_domain: EmailDomains::Many(vec![
+                "jemstep.com".to_string(),
+                "*MAIL.COM".to_string(),
+            ]),
+            verify_commit_signatures: false,
+            ..verify_commits_config()
+        },
+        &ReferenceUpdate::from_git_hook_format(
+            "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+            "afe2141ef20abd098927adc66d6728821cb34f59",
+            "refs/heads/master",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn verify_allowed_emails_can_be_outside_the_email_domains() {
+    before_all();
+    let result = policies::verify_git_commits::<LiveGit, MockGpg>(
+        &LiveGit::default("./").unwrap(),
+        MockGpg,
+        &VerifyGitCommitsConfig {
+            allowed_emails: vec!["JemStep.com@gmail.com".to_string()],
+            verify_commit_signatures: false,
+            ..verify_commits_config()
+        },
+        &ReferenceUpdate::from_git_hook_format(
+            "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+            "afe2141ef20abd098927adc66d6728821cb34f59",
+            "refs/heads/master",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn verify_keyring_emails_can_be_outside_the_email_domains() {
+    before_all();
+    let config = VerifyGitCommitsConfig {
+        committer_domain: "example.com".into(),
+        allowed_emails: vec!["jemstep.com@gmail.com".to_string()],
+        verify_commit_signatures: false,
+        ..verify_commits_config()
+    };
+    let verify = |config: &VerifyGitCommitsConfig| {
+        policies::verify_git_commits::<LiveGit, MockGpg>(
+            &LiveGit::default("./").unwrap(),
+            MockGpg,
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+                "afe2141ef20abd098927adc66d6728821cb34f59",
+                "refs/heads/master",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    };
+
+    let without_keyring = verify(&config);
+    let with_keyring = verify(&VerifyGitCommitsConfig {
+        allow_keyring_emails: true,
+        ..config.clone()
+    });
+
+    assert!(
+        matches!(
+            without_keyring,
+            PolicyResult::InvalidCommitterEmail(_, _, _)
+        ),
+        "Error: {:?}",
+        without_keyring
+    );
+    assert!(with_keyring.is_ok(), "Error: {:?}", with_keyring);
+}
@@ -0,0 +1,189 @@
+use capn::server::{install_server_hooks, ServerInstall};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::*;
+use uuid::Uuid;
+
+// These tests push to a real bare repo with Capn installed as its
+// pre-receive and post-receive hooks, so the hooks run the way Git
+// runs them, with the object quarantine during pre-receive.
+
+struct TempRoot {
+    path: PathBuf,
+}
+
+impl TempRoot {
+    fn new() -> TempRoot {
+        let path = std::env::temp_dir().join(format!("capn_hook_test_{}", Uuid::new_v4()));
+        fs::create_dir(&path).unwrap();
+        TempRoot { path }
+    }
+}
+
+impl Drop for TempRoot {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).unwrap();
+    }
+}
+
+const CAPN_CONFIG: &str = r#"
+[git]
+mainlines = [ "master", "feature" ]
+
+[verify_git_commits]
+author_domain = "jemstep.com"
+committer_domain = "jemstep.com"
+keyserver = "hkp://127.0.0.1:1"
+team_fingerprints_file = "TEAM_FINGERPRINTS"
+"#;
+
+fn git(dir: &Path, args: &[&str]) -> Output {
+    Command::new("git")
+        .current_dir(dir)
+        .args([
+            "-c",
+            "user.name=Test User",
+            "-c",
+            "user.email=blackhole@jemstep.com",
+            "-c",
+            "commit.gpgsign=false",
+        ])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn git_ok(dir: &Path, args: &[&str]) {
+    let output = git(dir, args);
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn commit_file(dir: &Path, name: &str, contents: &str) {
+    fs::write(dir.join(name), contents).unwrap();
+    git_ok(dir, &["add", name]);
+    git_ok(dir, &["commit", "-m", &format!("Add {}", name)]);
+}
+
+/// Sets up a server repo with diverged master and feature branches,
+/// pushed before the hook was installed, and a local clone ready to
+/// merge them.
+fn diverged_branches_with_hook_installed(root: &Path) -> PathBuf {
+    diverged_branches_with_config(root, CAPN_CONFIG)
+}
+
+fn diverged_branches_with_config(root: &Path, capn_config: &str) -> PathBuf {
+    let server = root.join("server.git");
+    let local = root.join("local");
+    git_ok(root, &["init", "--bare", "server.git"]);
+    git_ok(&server, &["symbolic-ref", "HEAD", "refs/heads/master"]);
+    git_ok(root, &["init", "local"]);
+    git_ok(&local, &["checkout", "-b", "master"]);
+    git_ok(
+        &local,
+        &["remote", "add", "origin", server.to_str().unwrap()],
+    );
+
+    fs::write(local.join(".capn"), capn_config).unwrap();
+    fs::write(local.join("TEAM_FINGERPRINTS"), "").unwrap();
+    git_ok(&local, &["add", ".capn", "TEAM_FINGERPRINTS"]);
+    git_ok(&local, &["commit", "-m", "Add capn config"]);
+    git_ok(&local, &["checkout", "-b", "feature"]);
+    commit_file(&local, "feature.txt", "feature");
+    git_ok(&local, &["checkout", "master"]);
+    commit_file(&local, "master.txt", "master");
+    git_ok(&local, &["push", "origin", "master", "feature"]);
+
+    install_server_hooks(&ServerInstall {
+        root: root.to_path_buf(),
+        capn_path: env!("CARGO_BIN_EXE_capn").to_string(),
+        log_url: None,
+    })
+    .unwrap();
+
+    local
+}
+
+#[test]
+fn pushing_unsigned_trivial_merge_is_accepted() {
+    let root = TempRoot::new();
+    let local = diverged_branches_with_hook_installed(&root.path);
+
+    git_ok(
+        &local,
+        &["merge", "--no-ff", "feature", "-m", "Merge feature"],
+    );
+    let push = git(&local, &["push", "origin", "master"]);
+
+    assert!(
+        push.status.success(),
+        "Push was rejected: {}",
+        String::from_utf8_lossy(&push.stderr)
+    );
+}
+
+#[test]
+fn pushing_unsigned_merge_with_additional_changes_is_rejected() {
+    let root = TempRoot::new();
+    let local = diverged_branches_with_hook_installed(&root.path);
+
+    git_ok(&local, &["merge", "--no-ff", "--no-commit", "feature"]);
+    fs::write(local.join("injected.txt"), "injected").unwrap();
+    git_ok(&local, &["add", "injected.txt"]);
+    git_ok(&local, &["commit", "-m", "Merge feature"]);
+    let push = git(&local, &["push", "origin", "master"]);
+
+    assert!(!push.status.success(), "Push was accepted");
+}
+
+#[test]
+fn audit_notes_are_written_after_the_push_is_applied() {
+    let root = TempRoot::new();
+    let local =
+        diverged_branches_with_config(&root.path, &format!("{}audit_notes = true\n", CAPN_CONFIG));
+    let server = root.path.join("server.git");
+
+    git_ok(
+        &local,
+        &["merge", "--no-ff", "feature", "-m", "Merge feature"],
+    );
+    git_ok(&local, &["push", "origin", "master"]);
+
+    let notes = git(&server, &["notes", "--ref", "capn-audit", "list", "master"]);
+    assert!(
+        notes.status.success(),
+        "No audit note for the pushed merge: {}",
+        String::from_utf8_lossy(&notes.stderr)
+    );
+    assert!(
+        fs::read_dir(&server).unwrap().all(|entry| !entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with("capn-audit-notes-")),
+        "Saved audit notes were left behind"
+    );
+}
+
+#[test]
+fn audit_notes_are_not_written_for_rejected_pushes() {
+    let root = TempRoot::new();
+    let local =
+        diverged_branches_with_config(&root.path, &format!("{}audit_notes = true\n", CAPN_CONFIG));
+    let server = root.path.join("server.git");
+
+    git_ok(&local, &["merge", "--no-ff", "--no-commit", "feature"]);
+    fs::write(local.join("injected.txt"), "injected").unwrap();
+    git_ok(&local, &["add", "injected.txt"]);
+    git_ok(&local, &["commit", "-m", "Merge feature"]);
+    let push = git(&local, &["push", "origin", "master"]);
+
+    assert!(!push.status.success(), "Push was accepted");
+    let notes = git(&server, &["show-ref", "refs/notes/capn-audit"]);
+    assert!(!notes.status.success(), "Audit notes were written");
+}
@@ -0,0 +1,254 @@
+use capn::config::Config;
+use capn::git::{Git, LiveGit};
+use capn::gpg::test::MockGpg;
+use capn::gpg::Gpg;
+use capn::policies::audit::PendingAuditNotes;
+use capn::policies::commit_checks::{CommitCheck, CommitCheckContext, CommitChecks};
+use capn::policies::policy_result::PolicyResult;
+use capn::policies::registry::{
+    Hook, Policy, PolicyRegistry, PolicyReport, RefUpdateContext, VerifyGitCommitsPolicy,
+};
+use capn::reference_update::ReferenceUpdate;
+
+use serde::Deserialize;
+use std::error::Error;
+
+// A policy like one that a downstream crate would register, with its
+// own section in the .capn file.
+struct MaxNewCommitsPolicy;
+
+#[derive(Deserialize)]
+struct MaxNewCommitsConfig {
+    limit: usize,
+}
+
+impl<G: Git, P: Gpg> Policy<G, P> for MaxNewCommitsPolicy {
+    type Config = MaxNewCommitsConfig;
+
+    fn name(&self) -> &str {
+        "max_new_commits"
+    }
+
+    fn hooks(&self) -> &[Hook] {
+        &[Hook::PreReceive]
+    }
+
+    fn config(&self, config: &Config) -> Result<Option<MaxNewCommitsConfig>, Box<dyn Error>> {
+        Ok(config.section("max_new_commits")?)
+    }
+
+    fn check(
+        &self,
+        config: &MaxNewCommitsConfig,
+        context: &RefUpdateContext<'_, G, P>,
+    ) -> Result<PolicyReport, Box<dyn Error>> {
+        let new_commits = context.git.find_new_commits(
+            &context
+                .ref_update
+                .old_commit_id()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            &context
+                .ref_update
+                .new_commit_id()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            &None,
+        )?;
+        let result = if new_commits.len() > config.limit {
+            PolicyResult::PolicyViolation(
+                "max_new_commits".to_string(),
+                format!(
+                    "{} new commits is more than {}",
+                    new_commits.len(),
+                    config.limit
+                ),
+            )
+        } else {
+            PolicyResult::Ok
+        };
+        Ok(PolicyReport::new("max_new_commits", result))
+    }
+}
+
+// A commit check like one that a downstream crate would register
+// with verify_git_commits.
+struct NoMergesCheck;
+
+impl<G: Git, P: Gpg> CommitCheck<G, P> for NoMergesCheck {
+    fn name(&self) -> &str {
+        "no merges"
+    }
+
+    fn is_enabled(&self, _context: &CommitCheckContext<'_, G, P>) -> Result<bool, Box<dyn Error>> {
+        Ok(true)
+    }
+
+    fn check(
+        &self,
+        context: &mut CommitCheckContext<'_, G, P>,
+    ) -> Result<PolicyResult, Box<dyn Error>> {
+        Ok(context
+            .commits
+            .iter()
+            .find(|c| c.is_merge_commit)
+            .map(|c| {
+                PolicyResult::PolicyViolation(
+                    "no merges".to_string(),
+                    format!("{} is a merge", c.id),
+                )
+            })
+            .unwrap_or(PolicyResult::Ok))
+    }
+}
+
+const VERIFY_GIT_COMMITS_CONFIG: &str = r#"
+[verify_git_commits]
+author_domain = "jemstep.com"
+committer_domain = "jemstep.com"
+keyserver = "hkp://127.0.0.1:1"
+team_fingerprints_file = "TEAM_FINGERPRINTS"
+"#;
+
+fn test_git() -> LiveGit {
+    LiveGit::default(format!(
+        "{}/tests/test-repo.git",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap()
+}
+
+fn no_merges_registry() -> PolicyRegistry<LiveGit, MockGpg> {
+    let mut checks = CommitChecks::default();
+    checks.register(NoMergesCheck);
+    let mut registry = PolicyRegistry::default();
+    registry.register(VerifyGitCommitsPolicy::new(checks));
+    registry
+}
+
+fn check(config: &str, hook: Hook) -> PolicyResult {
+    let git = test_git();
+    let mut registry = PolicyRegistry::<LiveGit, MockGpg>::with_builtin_policies();
+    registry.register(MaxNewCommitsPolicy);
+
+    registry
+        .check(&RefUpdateContext {
+            hook,
+            git: &git,
+            gpg: &MockGpg,
+            config: &Config::from_toml_string(config).unwrap(),
+            ref_update: &ReferenceUpdate::from_git_hook_format(
+                "7f9763e189ade34345e683ab7e0c22d164280452",
+                "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+                "refs/heads/valid-merge",
+            )
+            .unwrap(),
+            audit_notes: None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn builtin_policies_are_registered() {
+    let registry = PolicyRegistry::<LiveGit, MockGpg>::with_builtin_policies();
+    assert_eq!(
+        registry.policy_names(),
+        vec![
+            "verify_git_commits",
+            "verify_tags",
+            "verify_file_sizes",
+            "verify_filenames",
+            "verify_sign_off",
+            "verify_linear_history",
+            "verify_subjects",
+            "verify_parent_count",
+            "verify_submodules_and_symlinks",
+            "external_policy",
+            "wasm_policy"
+        ]
+    );
+}
+
+#[test]
+fn registered_policy_reads_its_own_config_section() {
+    let result = check("[max_new_commits]\nlimit = 0\n", Hook::PreReceive);
+    match result {
+        PolicyResult::PolicyViolation(policy, _) => assert_eq!(policy, "max_new_commits"),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn registered_policy_passes_within_its_config() {
+    let result = check("[max_new_commits]\nlimit = 100\n", Hook::PreReceive);
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn registered_policy_does_not_run_without_config() {
+    let result = check("[git]\nmainlines = [ \"master\" ]\n", Hook::PreReceive);
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn registered_policy_does_not_run_on_other_hooks() {
+    let result = check("[max_new_commits]\nlimit = 0\n", Hook::PrePush);
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn builtin_commit_checks_are_registered() {
+    let checks = CommitChecks::<LiveGit, MockGpg>::with_builtin_checks();
+    assert_eq!(
+        checks.check_names(),
+        vec![
+            "email addresses",
+            "rebased",
+            "timestamps",
+            "different authors",
+            "identities",
+            "approvals",
+            "code owners",
+            "commit signatures"
+        ]
+    );
+}
+
+#[test]
+fn pre_receive_with_registry_runs_registered_commit_checks() {
+    let result = capn::pre_receive_with_registry(
+        &no_merges_registry(),
+        &test_git(),
+        MockGpg,
+        &Config::from_toml_string(VERIFY_GIT_COMMITS_CONFIG).unwrap(),
+        "7f9763e189ade34345e683ab7e0c22d164280452",
+        "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+        "refs/heads/valid-merge",
+        &PendingAuditNotes::default(),
+    )
+    .unwrap();
+    match result {
+        PolicyResult::PolicyViolation(check, _) => assert_eq!(check, "no merges"),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn pre_push_with_registry_runs_registered_commit_checks() {
+    let result = capn::pre_push_with_registry(
+        &no_merges_registry(),
+        &test_git(),
+        MockGpg,
+        &capn::PrePush {
+            remote_name: "origin".to_string(),
+            remote_location: "".to_string(),
+        },
+        &Config::from_toml_string(VERIFY_GIT_COMMITS_CONFIG).unwrap(),
+        "refs/heads/valid-merge",
+        "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+        "refs/heads/valid-merge",
+        "7f9763e189ade34345e683ab7e0c22d164280452",
+    )
+    .unwrap();
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
@@ -0,0 +1,134 @@
+use capn::server::{install_server_hooks, ServerInstall};
+
+use git2::{Repository, Signature};
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+struct TempRoot {
+    path: PathBuf,
+}
+
+impl TempRoot {
+    fn new() -> TempRoot {
+        let path = std::env::temp_dir().join(format!("capn_server_test_{}", Uuid::new_v4()));
+        fs::create_dir(&path).unwrap();
+        TempRoot { path }
+    }
+}
+
+impl Drop for TempRoot {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).unwrap();
+    }
+}
+
+fn init_bare_repo(path: &Path) -> Repository {
+    fs::create_dir_all(path).unwrap();
+    Repository::init_bare(path).unwrap()
+}
+
+fn commit_capn_config(repo: &Repository) {
+    let blob = repo.blob(b"[git]\nmainlines = [ \"master\" ]\n").unwrap();
+    let mut tree_builder = repo.treebuilder(None).unwrap();
+    tree_builder.insert(".capn", blob, 0o100644).unwrap();
+    let tree = repo.find_tree(tree_builder.write().unwrap()).unwrap();
+    let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        "Add capn config",
+        &tree,
+        &[],
+    )
+    .unwrap();
+}
+
+fn server_install(root: &Path) -> ServerInstall {
+    ServerInstall {
+        root: root.to_path_buf(),
+        capn_path: "capn".to_string(),
+        log_url: None,
+    }
+}
+
+#[test]
+fn installs_hook_in_all_bare_repos_and_reports_missing_config() {
+    let root = TempRoot::new();
+    let configured = root.path.join("configured.git");
+    let unconfigured = root.path.join("team/unconfigured.git");
+    commit_capn_config(&init_bare_repo(&configured));
+    init_bare_repo(&unconfigured);
+    Repository::init(root.path.join("working-copy")).unwrap();
+    fs::create_dir(root.path.join("not-a-repo")).unwrap();
+
+    let report = install_server_hooks(&server_install(&root.path)).unwrap();
+
+    assert_eq!(
+        report.installed,
+        vec![configured.clone(), unconfigured.clone()]
+    );
+    assert!(report.updated.is_empty());
+    assert!(report.skipped_unmanaged_hook.is_empty());
+    assert_eq!(report.missing_config, vec![unconfigured.clone()]);
+
+    let hook = fs::read_to_string(unconfigured.join("hooks/pre-receive")).unwrap();
+    assert!(hook.contains("'capn' --repo 'team/unconfigured.git' pre-receive"));
+    let hook = fs::read_to_string(unconfigured.join("hooks/post-receive")).unwrap();
+    assert!(hook.contains("'capn' --repo 'team/unconfigured.git' post-receive"));
+    assert!(!root
+        .path
+        .join("working-copy/.git/hooks/pre-receive")
+        .exists());
+}
+
+#[test]
+fn updates_previously_installed_hook() {
+    let root = TempRoot::new();
+    let repo_path = root.path.join("repo.git");
+    commit_capn_config(&init_bare_repo(&repo_path));
+    install_server_hooks(&server_install(&root.path)).unwrap();
+
+    let report = install_server_hooks(&ServerInstall {
+        log_url: Some("10.0.0.123:123".to_string()),
+        ..server_install(&root.path)
+    })
+    .unwrap();
+
+    assert!(report.installed.is_empty());
+    assert_eq!(report.updated, vec![repo_path.clone()]);
+    let hook = fs::read_to_string(repo_path.join("hooks/pre-receive")).unwrap();
+    assert!(hook.contains("--log-url '10.0.0.123:123' pre-receive"));
+}
+
+#[test]
+fn does_not_overwrite_unmanaged_hook() {
+    let root = TempRoot::new();
+    let repo_path = root.path.join("repo.git");
+    commit_capn_config(&init_bare_repo(&repo_path));
+    let custom_hook = "#!/bin/sh\nexit 0\n";
+    fs::write(repo_path.join("hooks/pre-receive"), custom_hook).unwrap();
+
+    let report = install_server_hooks(&server_install(&root.path)).unwrap();
+
+    assert_eq!(report.skipped_unmanaged_hook, vec![repo_path.clone()]);
+    assert_eq!(
+        fs::read_to_string(repo_path.join("hooks/pre-receive")).unwrap(),
+        custom_hook
+    );
+    assert!(!repo_path.join("hooks/post-receive").exists());
+}
+
+#[test]
+fn does_not_follow_symlinks_when_searching_for_repos() {
+    let root = TempRoot::new();
+    let repo_path = root.path.join("team/repo.git");
+    commit_capn_config(&init_bare_repo(&repo_path));
+    std::os::unix::fs::symlink(&root.path, root.path.join("team/loop")).unwrap();
+    std::os::unix::fs::symlink(&repo_path, root.path.join("link.git")).unwrap();
+
+    let report = install_server_hooks(&server_install(&root.path)).unwrap();
+
+    assert_eq!(report.installed, vec![repo_path]);
+}
@@ -0,0 +1,241 @@
+use capn::config::{Config, VerifySignOffConfig};
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository, Signature};
+
+mod common;
+use common::TempTestRepo;
+
+const MASTER: &str = "eb5e0185546b0bb1a13feec6b9ee8b39985fea42";
+
+impl TempTestRepo {
+    /// Commits a new file on top of `parent`, by Test User.
+    fn commit(&self, parent: Oid, file_name: &str, message: &str) -> Oid {
+        self.commit_contents(parent, file_name, file_name, message)
+    }
+
+    /// Commits a file with the contents on top of `parent`, by Test User.
+    fn commit_contents(&self, parent: Oid, file_name: &str, contents: &str, message: &str) -> Oid {
+        let tree = self.tree_with_file(parent, file_name, contents);
+        self.commit_tree(tree, &[parent], message)
+    }
+
+    /// Merges the parents, using the tree of `tree_from`.
+    fn merge(&self, parents: &[Oid], tree_from: Oid, message: &str) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let tree = repo.find_commit(tree_from).unwrap().tree_id();
+        self.commit_tree(tree, parents, message)
+    }
+
+    /// Merges the parents the way Git would, without conflicts.
+    fn trivial_merge(&self, a: Oid, b: Oid, message: &str) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let mut index = repo
+            .merge_commits(
+                &repo.find_commit(a).unwrap(),
+                &repo.find_commit(b).unwrap(),
+                None,
+            )
+            .unwrap();
+        let tree = index.write_tree_to(&repo).unwrap();
+        self.commit_tree(tree, &[a, b], message)
+    }
+
+    fn commit_tree(&self, tree: Oid, parents: &[Oid], message: &str) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        let parents = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect::<Vec<_>>();
+        let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+        repo.commit(
+            None,
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents.iter().collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    fn verify(&self, new_commit_id: Oid) -> PolicyResult {
+        policies::verify_sign_off::<LiveGit>(
+            &LiveGit::default(&self.path).unwrap(),
+            &VerifySignOffConfig::default(),
+            &ReferenceUpdate::from_git_hook_format(
+                MASTER,
+                &new_commit_id.to_string(),
+                "refs/heads/feature",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn master() -> Oid {
+    Oid::from_str(MASTER).unwrap()
+}
+
+const SIGNED_OFF: &str = "Add a file\n\nSigned-off-by: Test User <blackhole@jemstep.com>\n";
+
+#[test]
+fn commit_signed_off_by_its_author_passes() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(master(), "a.txt", SIGNED_OFF);
+
+    let result = repo.verify(commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn commit_without_sign_off_fails() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(master(), "a.txt", "Add a file\n");
+
+    let result = repo.verify(commit);
+
+    match result {
+        PolicyResult::MissingSignOff(id, author) => {
+            assert_eq!(id, commit);
+            assert_eq!(author, "Test User <blackhole@jemstep.com>");
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn commit_signed_off_by_someone_else_fails() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(
+        master(),
+        "a.txt",
+        "Add a file\n\nSigned-off-by: Someone Else <someone@jemstep.com>\n",
+    );
+
+    let result = repo.verify(commit);
+
+    assert!(
+        matches!(result, PolicyResult::MissingSignOff(..)),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn sign_off_in_the_body_rather_than_the_trailers_fails() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(
+        master(),
+        "a.txt",
+        "Add a file\n\nSigned-off-by: Test User <blackhole@jemstep.com>\n\nForgot to mention why.\n",
+    );
+
+    let result = repo.verify(commit);
+
+    assert!(
+        matches!(result, PolicyResult::MissingSignOff(..)),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn merge_with_identical_tree_to_a_parent_does_not_need_sign_off() {
+    let repo = TempTestRepo::new();
+    let feature = repo.commit(master(), "a.txt", SIGNED_OFF);
+    let merge = repo.merge(&[master(), feature], feature, "Merge feature\n");
+
+    let result = repo.verify(merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn trivial_merge_does_not_need_sign_off() {
+    let repo = TempTestRepo::new();
+    let a = repo.commit(master(), "a.txt", SIGNED_OFF);
+    let b = repo.commit(master(), "b.txt", SIGNED_OFF);
+    let merge = repo.trivial_merge(a, b, "Merge b\n");
+
+    let result = repo.verify(merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn trivial_merge_of_changes_to_the_same_file_does_not_need_sign_off() {
+    let repo = TempTestRepo::new();
+    let base = repo.commit_contents(master(), "lines.txt", "1\n2\n3\n4\n5\n", SIGNED_OFF);
+    let a = repo.commit_contents(base, "lines.txt", "one\n2\n3\n4\n5\n", SIGNED_OFF);
+    let b = repo.commit_contents(base, "lines.txt", "1\n2\n3\n4\nfive\n", SIGNED_OFF);
+    let merge = repo.trivial_merge(a, b, "Merge b\n");
+
+    let result = repo.verify(merge);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn reproducing_a_merge_does_not_write_to_the_repo() {
+    let repo = TempTestRepo::new();
+    let base = repo.commit_contents(master(), "lines.txt", "1\n2\n3\n4\n5\n", SIGNED_OFF);
+    let a = repo.commit_contents(base, "lines.txt", "one\n2\n3\n4\n5\n", SIGNED_OFF);
+    let b = repo.commit_contents(base, "lines.txt", "1\n2\n3\n4\nfive\n", SIGNED_OFF);
+    let extra = repo.commit_contents(a, "lines.txt", "one\n2\n3\n4\nfive\n6\n", SIGNED_OFF);
+    let merge = repo.merge(&[a, b], extra, "Merge b\n");
+    let loose_objects = repo.loose_object_count();
+
+    let result = repo.verify(merge);
+
+    match result {
+        PolicyResult::MissingSignOff(id, _) => assert_eq!(id, merge),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    assert_eq!(repo.loose_object_count(), loose_objects);
+}
+
+#[test]
+fn merge_with_changes_of_its_own_needs_sign_off() {
+    let repo = TempTestRepo::new();
+    let a = repo.commit(master(), "a.txt", SIGNED_OFF);
+    let b = repo.commit(master(), "b.txt", SIGNED_OFF);
+    let extra = repo.commit(repo.trivial_merge(a, b, "Merge b\n"), "c.txt", SIGNED_OFF);
+    let merge = repo.merge(&[a, b], extra, "Merge b\n");
+
+    let result = repo.verify(merge);
+
+    match result {
+        PolicyResult::MissingSignOff(id, _) => assert_eq!(id, merge),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn sign_off_is_checked_without_the_verify_git_commits_config() {
+    let repo = TempTestRepo::new();
+    let commit = repo.commit(master(), "a.txt", "Add a file\n");
+
+    let result = capn::pre_receive::<LiveGit, MockGpg>(
+        &LiveGit::default(&repo.path).unwrap(),
+        MockGpg,
+        &Config::from_toml_string("[verify_sign_off]\n").unwrap(),
+        MASTER,
+        &commit.to_string(),
+        "refs/heads/feature",
+    )
+    .unwrap();
+
+    assert!(
+        matches!(result, PolicyResult::MissingSignOff(id, _) if id == commit),
+        "Unexpected result: {:?}",
+        result
+    );
+}
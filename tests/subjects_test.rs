@@ -0,0 +1,121 @@
+use capn::config::{Config, VerifySubjectsConfig};
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository, Signature};
+
+mod common;
+use common::TempTestRepo;
+
+const MASTER: &str = "eb5e0185546b0bb1a13feec6b9ee8b39985fea42";
+
+impl TempTestRepo {
+    /// Commits a new file on top of `parent`, by Test User.
+    fn commit(&self, parent: Oid, file_name: &str, message: &str) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let blob = repo.blob(file_name.as_bytes()).unwrap();
+        let mut tree_builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        tree_builder.insert(file_name, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree_builder.write().unwrap()).unwrap();
+        let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+        repo.commit(None, &signature, &signature, message, &tree, &[&parent])
+            .unwrap()
+    }
+
+    fn verify(&self, new_commit_id: Oid, ref_name: &str) -> PolicyResult {
+        policies::verify_subjects::<LiveGit>(
+            &LiveGit::default(&self.path).unwrap(),
+            &subjects_config(),
+            &ReferenceUpdate::from_git_hook_format(MASTER, &new_commit_id.to_string(), ref_name)
+                .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn subjects_config() -> VerifySubjectsConfig {
+    VerifySubjectsConfig {
+        rejected_subjects: vec![
+            "fixup! *".to_string(),
+            "squash! *".to_string(),
+            "WIP*".to_string(),
+            "*DO NOT MERGE*".to_string(),
+            "tmp".to_string(),
+        ],
+    }
+}
+
+fn rejected_subject(result: PolicyResult) -> String {
+    match result {
+        PolicyResult::RejectedSubject(_, subject) => subject,
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn ordinary_subjects_pass() {
+    let repo = TempTestRepo::new();
+    let base = Oid::from_str(MASTER).unwrap();
+    let commit = repo.commit(base, "a.txt", "Add the temporary file cleanup");
+
+    let result = repo.verify(commit, "refs/heads/master");
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn autosquash_commits_are_rejected_on_mainlines() {
+    let repo = TempTestRepo::new();
+    let base = Oid::from_str(MASTER).unwrap();
+    let first = repo.commit(base, "a.txt", "Add a");
+    let fixup = repo.commit(first, "b.txt", "fixup! Add a");
+
+    let result = repo.verify(fixup, "refs/heads/master");
+
+    assert_eq!(rejected_subject(result), "fixup! Add a");
+}
+
+#[test]
+fn subjects_are_matched_case_insensitively() {
+    let repo = TempTestRepo::new();
+    let base = Oid::from_str(MASTER).unwrap();
+    let commit = repo.commit(base, "a.txt", "Half done, do not merge\n\nMore later");
+
+    let result = repo.verify(commit, "refs/heads/master");
+
+    assert_eq!(rejected_subject(result), "Half done, do not merge");
+}
+
+#[test]
+fn feature_branches_are_left_alone() {
+    let repo = TempTestRepo::new();
+    let base = Oid::from_str(MASTER).unwrap();
+    let commit = repo.commit(base, "a.txt", "wip");
+
+    let result = repo.verify(commit, "refs/heads/feature");
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn subjects_are_checked_without_the_verify_git_commits_config() {
+    let repo = TempTestRepo::new();
+    let base = Oid::from_str(MASTER).unwrap();
+    let commit = repo.commit(base, "a.txt", "WIP");
+
+    let result = capn::pre_receive::<LiveGit, MockGpg>(
+        &LiveGit::default(&repo.path).unwrap(),
+        MockGpg,
+        &Config::from_toml_string("[verify_subjects]\nrejected_subjects = [\"WIP*\"]\n").unwrap(),
+        MASTER,
+        &commit.to_string(),
+        "refs/heads/master",
+    )
+    .unwrap();
+
+    assert_eq!(rejected_subject(result), "WIP");
+}
@@ -0,0 +1,234 @@
+use capn::config::VerifySubmodulesAndSymlinksConfig;
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository, Signature};
+
+mod common;
+use common::{Person, TempTestRepo};
+
+const RELEASE_MANAGER: Person = ("Release Manager", "releases@jemstep.com");
+
+const SUBMODULE: i32 = 0o160000;
+const SYMLINK: i32 = 0o120000;
+const FILE: i32 = 0o100644;
+
+const GITMODULES: &str =
+    "[submodule \"lib\"]\n\tpath = vendor/lib\n\turl = https://example.com/lib.git\n";
+
+/// A copy of the test repo, with the release manager added to the
+/// team fingerprints on master.
+fn test_repo() -> TempTestRepo {
+    let mut repo = TempTestRepo::with_keys(&[RELEASE_MANAGER]);
+    let team_fingerprints = repo.keyring().team_member(RELEASE_MANAGER, "");
+    let master = repo.commit_file(repo.master, "TEAM_FINGERPRINTS", &team_fingerprints);
+    repo.set_master(master);
+    repo
+}
+
+impl TempTestRepo {
+    fn commit_file(&self, parent: Oid, path: &str, contents: &str) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let blob = repo.blob(contents.as_bytes()).unwrap();
+        self.commit(parent, path, blob, FILE)
+    }
+
+    fn commit_symlink(&self, parent: Oid, path: &str, target: &str) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let blob = repo.blob(target.as_bytes()).unwrap();
+        self.commit(parent, path, blob, SYMLINK)
+    }
+
+    fn commit_submodule(&self, parent: Oid, path: &str, submodule_commit: Oid) -> Oid {
+        self.commit(parent, path, submodule_commit, SUBMODULE)
+    }
+
+    /// Commits a tree entry at the path on top of `parent`.
+    fn commit(&self, parent: Oid, path: &str, id: Oid, mode: i32) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let names = path.split('/').collect::<Vec<_>>();
+        let tree = Self::insert(&repo, Some(parent.tree_id()), &names, id, mode);
+        let tree = repo.find_tree(tree).unwrap();
+        let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+        repo.commit(None, &signature, &signature, "Change", &tree, &[&parent])
+            .unwrap()
+    }
+
+    fn insert(repo: &Repository, tree: Option<Oid>, names: &[&str], id: Oid, mode: i32) -> Oid {
+        let tree = tree.map(|id| repo.find_tree(id).unwrap());
+        let mut tree_builder = repo.treebuilder(tree.as_ref()).unwrap();
+        match names {
+            [name] => {
+                tree_builder.insert(*name, id, mode).unwrap();
+            }
+            [name, rest @ ..] => {
+                let subtree = tree_builder.get(*name).unwrap().map(|entry| entry.id());
+                let subtree = Self::insert(repo, subtree, rest, id, mode);
+                tree_builder.insert(*name, subtree, 0o040000).unwrap();
+            }
+            [] => unreachable!(),
+        }
+        tree_builder.write().unwrap()
+    }
+
+    fn tag(&self, name: &str, commit: Oid) {
+        self.git_as(
+            RELEASE_MANAGER,
+            RELEASE_MANAGER,
+            &[
+                "tag",
+                "-s",
+                "-u",
+                RELEASE_MANAGER.1,
+                "-m",
+                "Override",
+                name,
+                &commit.to_string(),
+            ],
+        );
+    }
+
+    fn verify(
+        &self,
+        config: &VerifySubmodulesAndSymlinksConfig,
+        new_commit_id: Oid,
+    ) -> PolicyResult {
+        policies::verify_submodules_and_symlinks::<LiveGit, MockGpg>(
+            &LiveGit::default(&self.path).unwrap(),
+            &MockGpg,
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                &self.master.to_string(),
+                &new_commit_id.to_string(),
+                "refs/heads/master",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn submodules_config() -> VerifySubmodulesAndSymlinksConfig {
+    VerifySubmodulesAndSymlinksConfig {
+        keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
+        team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
+        allowed_submodule_paths: vec!["third_party/".to_string()],
+        allowed_symlink_paths: vec!["deploy/".to_string()],
+        override_tag_pattern: Some("capn-override-*".to_string()),
+        override_tags_required: 1.into(),
+    }
+}
+
+#[test]
+fn symlinks_inside_the_repo_pass() {
+    let repo = test_repo();
+    let commit = repo.commit_symlink(repo.master, "docs/latest", "../README.md");
+
+    let result = repo.verify(&submodules_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn symlinks_that_escape_the_repo_fail() {
+    let repo = test_repo();
+    let first = repo.commit_symlink(repo.master, "docs/latest", "../README.md");
+    let second = repo.commit_symlink(first, "docs/keys", "../../.ssh/id_rsa");
+
+    let result = repo.verify(&submodules_config(), second);
+
+    match result {
+        PolicyResult::SymlinkEscapesRepo(id, path, target) => {
+            assert_eq!(
+                (id, path.as_str(), target.as_str()),
+                (second, "docs/keys", "../../.ssh/id_rsa")
+            );
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn symlinks_that_escape_the_repo_pass_under_allowed_paths() {
+    let repo = test_repo();
+    let commit = repo.commit_symlink(repo.master, "deploy/config", "/etc/app/config");
+
+    let result = repo.verify(&submodules_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn submodules_fail_outside_of_allowed_paths() {
+    let repo = test_repo();
+    let commit = repo.commit_submodule(repo.master, "vendor/lib", repo.master);
+
+    let result = repo.verify(&submodules_config(), commit);
+
+    match result {
+        PolicyResult::SubmoduleNotAllowed(id, path) => {
+            assert_eq!((id, path.as_str()), (commit, "vendor/lib"));
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn submodules_pass_under_allowed_paths() {
+    let repo = test_repo();
+    let first = repo.commit_submodule(repo.master, "third_party/lib", repo.master);
+    let second = repo.commit_file(
+        first,
+        ".gitmodules",
+        &GITMODULES.replace("vendor/lib", "third_party/lib"),
+    );
+
+    let result = repo.verify(&submodules_config(), second);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn gitmodules_url_changes_fail() {
+    let repo = test_repo();
+    let config = VerifySubmodulesAndSymlinksConfig {
+        allowed_submodule_paths: Vec::new(),
+        ..submodules_config()
+    };
+    let first = repo.commit_file(repo.master, ".gitmodules", GITMODULES);
+    let second = repo.commit_file(
+        first,
+        ".gitmodules",
+        &GITMODULES.replace("example.com", "attacker.example"),
+    );
+    repo.tag("capn-override-lib", first);
+
+    let result = repo.verify(&config, second);
+
+    match result {
+        PolicyResult::SubmoduleUrlChanged(id, path, url) => {
+            assert_eq!(
+                (id, path.as_str(), url.as_str()),
+                (second, "vendor/lib", "https://attacker.example/lib.git")
+            );
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn override_tags_allow_submodule_and_symlink_changes() {
+    let repo = test_repo();
+    let first = repo.commit_submodule(repo.master, "vendor/lib", repo.master);
+    let second = repo.commit_file(first, ".gitmodules", GITMODULES);
+    let third = repo.commit_symlink(second, "keys", "/root/.ssh/id_rsa");
+    repo.tag("capn-override-lib", third);
+
+    let result = repo.verify(&submodules_config(), third);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
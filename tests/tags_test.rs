@@ -0,0 +1,216 @@
+use capn::config::VerifyTagsConfig;
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::Oid;
+
+mod common;
+use common::{Person, TempTestRepo};
+
+const RELEASE_MANAGER: Person = ("Release Manager", "releases@jemstep.com");
+const DEVELOPER: Person = ("Developer", "developer@jemstep.com");
+
+const ZERO: &str = "0000000000000000000000000000000000000000";
+
+/// A copy of the test repo, with the taggers added to the team
+/// fingerprints on master. The release manager is in the
+/// `release-managers` group.
+fn test_repo() -> TempTestRepo {
+    let mut repo = TempTestRepo::with_keys(&[RELEASE_MANAGER, DEVELOPER]);
+    let team_fingerprints = repo
+        .keyring()
+        .team_member(RELEASE_MANAGER, "release-managers")
+        + &repo.keyring().team_member(DEVELOPER, "");
+    repo.commit_team_fingerprints(DEVELOPER, &team_fingerprints);
+    repo
+}
+
+impl TempTestRepo {
+    fn commit(&self, parent: Oid, file_name: &str, contents: &str) -> Oid {
+        self.commit_file_as(DEVELOPER, DEVELOPER, parent, file_name, contents, &[])
+    }
+
+    /// Tags the commit, returning the id that the tag ref points to.
+    /// `sign` is "-s" for a signed tag, "-a" for an unsigned annotated
+    /// tag, or "" for a lightweight tag.
+    fn tag(&self, tagger: (&str, &str), sign: &str, name: &str, commit: Oid) -> Oid {
+        let commit = commit.to_string();
+        let mut args = vec!["tag"];
+        match sign {
+            "-s" => args.extend(&["-s", "-u", tagger.1, "-m", "Release"]),
+            "-a" => args.extend(&["-a", "-m", "Release"]),
+            _ => {}
+        }
+        args.extend(&[name, &commit]);
+        self.git_as(tagger, tagger, &args);
+        let tag_ref = format!("refs/tags/{}", name);
+        Oid::from_str(self.git_as(tagger, tagger, &["rev-parse", &tag_ref]).trim()).unwrap()
+    }
+
+    fn verify(&self, old: &str, new: &str, ref_name: &str) -> PolicyResult {
+        self.verify_with(&tags_config(), old, new, ref_name)
+    }
+
+    fn verify_with(
+        &self,
+        config: &VerifyTagsConfig,
+        old: &str,
+        new: &str,
+        ref_name: &str,
+    ) -> PolicyResult {
+        policies::verify_tags::<LiveGit, MockGpg>(
+            &LiveGit::default(&self.path).unwrap(),
+            MockGpg,
+            config,
+            &ReferenceUpdate::from_git_hook_format(old, new, ref_name).unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn tags_config() -> VerifyTagsConfig {
+    VerifyTagsConfig {
+        keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
+        team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
+        tag_name_pattern: Some("v*".to_string()),
+        verify_tag_signatures: true,
+        signer_group: Some("release-managers".to_string()),
+        verify_reachable_from_mainline: true,
+        allow_moving_tags: false,
+        allow_deleting_tags: false,
+    }
+}
+
+#[test]
+fn signed_release_tag_on_the_mainline_passes() {
+    let repo = test_repo();
+    let tag = repo.tag(RELEASE_MANAGER, "-s", "v1.0.0", repo.master);
+
+    let result = repo.verify(ZERO, &tag.to_string(), "refs/tags/v1.0.0");
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn lightweight_tag_fails() {
+    let repo = test_repo();
+    let tag = repo.tag(RELEASE_MANAGER, "", "v1.0.0", repo.master);
+
+    let result = repo.verify(ZERO, &tag.to_string(), "refs/tags/v1.0.0");
+
+    assert!(
+        matches!(result, PolicyResult::LightweightTag(_)),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn unsigned_annotated_tag_fails() {
+    let repo = test_repo();
+    let tag = repo.tag(RELEASE_MANAGER, "-a", "v1.0.0", repo.master);
+
+    let result = repo.verify(ZERO, &tag.to_string(), "refs/tags/v1.0.0");
+
+    assert!(
+        matches!(result, PolicyResult::UnsignedTag(_)),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn tag_signed_outside_of_the_signer_group_fails() {
+    let repo = test_repo();
+    let tag = repo.tag(DEVELOPER, "-s", "v1.0.0", repo.master);
+
+    let result = repo.verify(ZERO, &tag.to_string(), "refs/tags/v1.0.0");
+
+    assert!(
+        matches!(result, PolicyResult::TagSignerNotInGroup(_, _)),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn tag_name_must_match_the_pattern() {
+    let repo = test_repo();
+    let tag = repo.tag(RELEASE_MANAGER, "-s", "release-1", repo.master);
+
+    let result = repo.verify(ZERO, &tag.to_string(), "refs/tags/release-1");
+
+    assert!(
+        matches!(result, PolicyResult::InvalidTagName(_, _)),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn tag_on_a_commit_that_is_not_on_the_mainline_fails() {
+    let repo = test_repo();
+    let branch_commit = repo.commit(repo.master, "feature.txt", "feature");
+    let tag = repo.tag(RELEASE_MANAGER, "-s", "v1.0.0", branch_commit);
+
+    let result = repo.verify(ZERO, &tag.to_string(), "refs/tags/v1.0.0");
+
+    assert!(
+        matches!(result, PolicyResult::TagNotOnMainline(_, id) if id == branch_commit),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn tags_can_not_be_moved_or_deleted() {
+    let repo = test_repo();
+    let tag = repo.tag(RELEASE_MANAGER, "-s", "v1.0.0", repo.master);
+    let tag = tag.to_string();
+    let other = repo.master.to_string();
+
+    let moved = repo.verify(&tag, &other, "refs/tags/v1.0.0");
+    let deleted = repo.verify(&tag, ZERO, "refs/tags/v1.0.0");
+
+    assert!(
+        matches!(moved, PolicyResult::TagMoved(_)),
+        "Unexpected result: {:?}",
+        moved
+    );
+    assert!(
+        matches!(deleted, PolicyResult::TagDeleted(_)),
+        "Unexpected result: {:?}",
+        deleted
+    );
+}
+
+#[test]
+fn tags_can_be_deleted_when_allowed() {
+    let repo = test_repo();
+    let tag = repo.tag(RELEASE_MANAGER, "-s", "v1.0.0", repo.master);
+
+    let result = repo.verify_with(
+        &VerifyTagsConfig {
+            allow_deleting_tags: true,
+            ..tags_config()
+        },
+        &tag.to_string(),
+        ZERO,
+        "refs/tags/v1.0.0",
+    );
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn branches_are_not_checked() {
+    let repo = test_repo();
+    let branch_commit = repo.commit(repo.master, "feature.txt", "feature");
+
+    let result = repo.verify(ZERO, &branch_commit.to_string(), "refs/heads/feature");
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
@@ -0,0 +1,362 @@
+use capn::config::VerifyGitCommitsConfig;
+use capn::git::LiveGit;
+use capn::gpg::test::{MockGpg, MockGpgWithKeyValidity};
+use capn::gpg::{Gpg, KeyValidity, LiveGpg};
+use capn::keyring::Keyring;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::{Oid, Repository, Signature, Time};
+use std::collections::HashSet;
+use std::error::Error;
+use std::process::*;
+use std::sync::Once;
+
+mod common;
+use common::{fingerprint, Person, TempTestRepo};
+
+const COMMITTER: Person = ("Committer", "committer@jemstep.com");
+const SUBKEY_COMMITTER: Person = ("Subkey Signer", "subkey-signer@jemstep.com");
+
+static SIGNING_SUBKEY: Once = Once::new();
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// Treats every key as received, since the test keyring already has
+/// them, and reads key validity from the test keyring the way
+/// `LiveGpg` does.
+struct TestKeyringGpg;
+
+impl Gpg for TestKeyringGpg {
+    fn receive_keys(
+        &self,
+        keyring: &mut Keyring,
+        emails: &HashSet<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        MockGpg.receive_keys(keyring, emails)
+    }
+
+    fn key_validity(&self, fingerprint: &str) -> Result<Option<KeyValidity>, Box<dyn Error>> {
+        LiveGpg {
+            parallel_fetch: false,
+            keyserver: String::new(),
+        }
+        .key_validity(fingerprint)
+    }
+
+    fn sign(&self, contents: &str, signing_key: &str) -> Result<String, Box<dyn Error>> {
+        MockGpg.sign(contents, signing_key)
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// A copy of the test repo, with the committer added to the team
+/// fingerprints on master.
+fn test_repo() -> TempTestRepo {
+    let mut repo = TempTestRepo::with_keys(&[COMMITTER]);
+    let team_fingerprints = repo.keyring().team_member(COMMITTER, "");
+    let master = repo.commit(repo.master, "TEAM_FINGERPRINTS", &team_fingerprints, now());
+    repo.set_master(master);
+    repo
+}
+
+impl TempTestRepo {
+    /// Commits a file on top of `parent`, authored and committed at
+    /// `time`.
+    fn commit(&self, parent: Oid, file_name: &str, contents: &str, time: i64) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let blob = repo.blob(contents.as_bytes()).unwrap();
+        let mut tree_builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        tree_builder.insert(file_name, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree_builder.write().unwrap()).unwrap();
+        let signature = Signature::new(COMMITTER.0, COMMITTER.1, &Time::new(time, 0)).unwrap();
+        repo.commit(None, &signature, &signature, "Change", &tree, &[&parent])
+            .unwrap()
+    }
+
+    /// Commits a file on top of master, signed by the committer, with
+    /// the commit dated at `time`.
+    fn signed_commit(&self, time: i64) -> Oid {
+        self.signed_commit_by(COMMITTER, time)
+    }
+
+    fn signed_commit_by(&self, committer: Person, time: i64) -> Oid {
+        let repo = Repository::open(&self.path).unwrap();
+        let parent = repo.find_commit(self.master).unwrap();
+        let blob = repo.blob(b"signed").unwrap();
+        let mut tree_builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        tree_builder.insert("signed.txt", blob, 0o100644).unwrap();
+        let tree = tree_builder.write().unwrap().to_string();
+        let date = format!("{} +0000", time);
+        let output = Command::new("git")
+            .current_dir(&self.path)
+            .env("GIT_AUTHOR_NAME", committer.0)
+            .env("GIT_AUTHOR_EMAIL", committer.1)
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_NAME", committer.0)
+            .env("GIT_COMMITTER_EMAIL", committer.1)
+            .env("GIT_COMMITTER_DATE", &date)
+            .args([
+                "commit-tree",
+                &tree,
+                "-p",
+                &self.master.to_string(),
+                "-m",
+                "Signed change",
+                &format!("-S{}", committer.1),
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git failed: {:?}", output);
+        Oid::from_str(String::from_utf8(output.stdout).unwrap().trim()).unwrap()
+    }
+
+    fn commit_time(&self, commit: Oid) -> i64 {
+        Repository::open(&self.path)
+            .unwrap()
+            .find_commit(commit)
+            .unwrap()
+            .committer()
+            .when()
+            .seconds()
+    }
+
+    fn verify(&self, config: &VerifyGitCommitsConfig, new_commit_id: Oid) -> PolicyResult {
+        self.verify_with_gpg(TestKeyringGpg, config, new_commit_id)
+    }
+
+    fn verify_with_gpg<P: Gpg>(
+        &self,
+        gpg: P,
+        config: &VerifyGitCommitsConfig,
+        new_commit_id: Oid,
+    ) -> PolicyResult {
+        policies::verify_git_commits::<LiveGit, P>(
+            &LiveGit::default(&self.path).unwrap(),
+            gpg,
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                &self.master.to_string(),
+                &new_commit_id.to_string(),
+                "refs/heads/master",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn timestamps_config() -> VerifyGitCommitsConfig {
+    VerifyGitCommitsConfig {
+        author_domain: "jemstep.com".into(),
+        committer_domain: "jemstep.com".into(),
+        keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
+        team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
+        verify_email_addresses: false,
+        verify_commit_signatures: false,
+        max_future_skew_seconds: Some(300),
+        max_parent_skew_seconds: Some(3600),
+        override_tags_required: 1.into(),
+        ..Default::default()
+    }
+}
+
+fn signatures_config() -> VerifyGitCommitsConfig {
+    VerifyGitCommitsConfig {
+        verify_commit_signatures: true,
+        verify_signing_key_validity: true,
+        max_future_skew_seconds: None,
+        max_parent_skew_seconds: None,
+        ..timestamps_config()
+    }
+}
+
+#[test]
+fn commits_with_sensible_dates_pass() {
+    let repo = test_repo();
+    let commit = repo.commit(repo.master, "a.txt", "a", now() + 60);
+
+    let result = repo.verify(&timestamps_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn commits_dated_too_far_in_the_future_fail() {
+    let repo = test_repo();
+    let commit = repo.commit(repo.master, "a.txt", "a", now() + DAY);
+
+    let result = repo.verify(&timestamps_config(), commit);
+
+    assert!(
+        matches!(result, PolicyResult::CommitDatedInFuture(id, _) if id == commit),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn commits_slightly_before_their_parent_pass() {
+    let repo = test_repo();
+    let parent_time = repo.commit_time(repo.master);
+    let commit = repo.commit(repo.master, "a.txt", "a", parent_time - 600);
+
+    let result = repo.verify(&timestamps_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn commits_long_before_their_parent_fail() {
+    let repo = test_repo();
+    let parent_time = repo.commit_time(repo.master);
+    let commit = repo.commit(repo.master, "a.txt", "a", parent_time - DAY);
+
+    let result = repo.verify(&timestamps_config(), commit);
+
+    match result {
+        PolicyResult::CommittedBeforeParent(id, parent) => {
+            assert_eq!((id, parent), (commit, repo.master))
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn commits_signed_within_the_key_validity_pass() {
+    let repo = test_repo();
+    let commit = repo.signed_commit(now());
+
+    let result = repo.verify(&signatures_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn commits_dated_before_their_signing_key_was_made_fail() {
+    let repo = test_repo();
+    let commit = repo.signed_commit(now() - 365 * DAY);
+
+    let result = repo.verify(&signatures_config(), commit);
+
+    assert!(
+        matches!(result, PolicyResult::SignedOutsideKeyValidity(id, _) if id == commit),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn commits_dated_outside_of_the_stubbed_key_validity_fail() {
+    let repo = test_repo();
+    let commit = repo.signed_commit(now());
+
+    let result = repo.verify_with_gpg(
+        MockGpgWithKeyValidity(KeyValidity {
+            created: now() - 2 * DAY,
+            expires: Some(now() - DAY),
+        }),
+        &signatures_config(),
+        commit,
+    );
+
+    assert!(
+        matches!(result, PolicyResult::SignedOutsideKeyValidity(id, _) if id == commit),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+/// A copy of the test repo, where the subkey committer's primary key
+/// never expires, but has a signing subkey that expires in a day. The
+/// team fingerprints on master list them with `listed_key`. Returns
+/// the primary key and the subkey.
+fn subkey_repo(listed_key: impl FnOnce(&str, &str) -> String) -> (TempTestRepo, String, String) {
+    let mut repo = TempTestRepo::with_keys(&[SUBKEY_COMMITTER]);
+    let primary_key = fingerprint(SUBKEY_COMMITTER.1);
+    SIGNING_SUBKEY.call_once(|| {
+        let status = Command::new("gpg")
+            .args([
+                "--batch",
+                "--passphrase",
+                "",
+                "--quick-add-key",
+                &primary_key,
+                "default",
+                "sign",
+                "1d",
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "Failed to add a signing subkey");
+    });
+    let subkey = signing_subkey(SUBKEY_COMMITTER.1);
+    let team_fingerprints = format!(
+        "{},{},{}\n",
+        listed_key(&primary_key, &subkey),
+        SUBKEY_COMMITTER.0,
+        SUBKEY_COMMITTER.1
+    );
+    let master = repo.commit(repo.master, "TEAM_FINGERPRINTS", &team_fingerprints, now());
+    repo.set_master(master);
+    (repo, primary_key, subkey)
+}
+
+fn signing_subkey(email: &str) -> String {
+    let output = Command::new("gpg")
+        .args(["--with-colons", "--list-keys", email])
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .skip_while(|line| !line.starts_with("sub:"))
+        .find(|line| line.starts_with("fpr:"))
+        .and_then(|line| line.split(':').nth(9))
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn commits_signed_by_a_subkey_pass_with_the_primary_key_in_the_team_fingerprints() {
+    let (repo, _, _) = subkey_repo(|primary_key, _| primary_key.to_string());
+    let commit = repo.signed_commit_by(SUBKEY_COMMITTER, now());
+
+    let result = repo.verify(&signatures_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn commits_signed_by_a_subkey_pass_with_the_subkey_in_the_team_fingerprints() {
+    let (repo, _, _) = subkey_repo(|_, subkey| subkey.to_string());
+    let commit = repo.signed_commit_by(SUBKEY_COMMITTER, now());
+
+    let result = repo.verify(&signatures_config(), commit);
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn commits_dated_after_their_signing_subkey_expires_fail() {
+    let (repo, primary_key, subkey) = subkey_repo(|primary_key, _| primary_key.to_string());
+    let commit = repo.signed_commit_by(SUBKEY_COMMITTER, now() + 2 * DAY);
+
+    let result = repo.verify(&signatures_config(), commit);
+
+    match result {
+        PolicyResult::SignedOutsideKeyValidity(id, signing_key) => {
+            assert_eq!(id, commit);
+            assert_eq!(signing_key, subkey);
+            assert_ne!(signing_key, primary_key);
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
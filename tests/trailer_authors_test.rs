@@ -0,0 +1,196 @@
+use capn::config::VerifyGitCommitsConfig;
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::policies::policy_result::PolicyResult;
+use capn::reference_update::ReferenceUpdate;
+
+use git2::Oid;
+
+mod common;
+use common::{Person, TempTestRepo};
+
+const PAIR_ONE: Person = ("Pair One", "pair1@jemstep.com");
+const PAIR_TWO: Person = ("Pair Two", "pair2@jemstep.com");
+
+/// A copy of the test repo, with Pair Two added to the team
+/// fingerprints on master.
+fn test_repo() -> TempTestRepo {
+    let mut repo = TempTestRepo::with_keys(&[PAIR_TWO]);
+    let team_fingerprints = format!(
+        "40D0 184E 8FE9 752C D257  7D34 E1F3 15E3 9CCC ECAA,Test User,blackhole@jemstep.com\n{}",
+        repo.keyring().team_member(PAIR_TWO, "")
+    );
+    let tree = repo.tree_with_file(repo.master, "TEAM_FINGERPRINTS", &team_fingerprints);
+    let master = repo.commit_tree(tree, &[repo.master], PAIR_ONE, "Add Pair Two", false);
+    repo.set_master(master);
+    repo
+}
+
+impl TempTestRepo {
+    /// Commits, authored by Pair One, with the given committer, who
+    /// optionally signs the commit.
+    fn commit_tree(
+        &self,
+        tree: Oid,
+        parents: &[Oid],
+        committer: (&str, &str),
+        message: &str,
+        signed: bool,
+    ) -> Oid {
+        let tree = tree.to_string();
+        let parents = parents.iter().map(Oid::to_string).collect::<Vec<_>>();
+        let signing_key = format!("-S{}", committer.1);
+        let mut args = vec!["commit-tree", &tree, "-m", message];
+        for parent in &parents {
+            args.extend(["-p", parent]);
+        }
+        if signed {
+            args.push(&signing_key);
+        }
+        let commit = self.git_as(PAIR_ONE, committer, &args);
+        Oid::from_str(commit.trim()).unwrap()
+    }
+
+    /// A branch with a single commit by Pair One, merged into master.
+    fn merged_branch(&self, message: &str, committer: (&str, &str), signed: bool) -> Oid {
+        let branch_tree = self.tree_with_file(self.master, "pairing.txt", "pairing");
+        let branch = self.commit_tree(branch_tree, &[self.master], committer, message, signed);
+        let merge_tree = self.tree_with_file(branch, "merge.txt", "merge");
+        self.commit_tree(
+            merge_tree,
+            &[self.master, branch],
+            PAIR_ONE,
+            "Merge pairing",
+            false,
+        )
+    }
+
+    fn verify(&self, config: &VerifyGitCommitsConfig, merge: Oid) -> PolicyResult {
+        policies::verify_git_commits::<LiveGit, MockGpg>(
+            &LiveGit::default(&self.path).unwrap(),
+            MockGpg,
+            config,
+            &ReferenceUpdate::from_git_hook_format(
+                &self.master.to_string(),
+                &merge.to_string(),
+                "refs/heads/master",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+}
+
+fn different_authors_config() -> VerifyGitCommitsConfig {
+    VerifyGitCommitsConfig {
+        author_domain: "jemstep.com".into(),
+        committer_domain: "jemstep.com".into(),
+        keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
+        team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
+        verify_email_addresses: false,
+        verify_commit_signatures: false,
+        verify_different_authors: true,
+        override_tags_required: 1.into(),
+        ..Default::default()
+    }
+}
+
+const CO_AUTHORED: &str = "Pair on a feature\n\nCo-authored-by: Pair Two <pair2@jemstep.com>\n";
+const REVIEWED: &str = "Pair on a feature\n\nReviewed-by: Pair Two <pair2@jemstep.com>\n";
+
+#[test]
+fn co_authors_are_not_counted_by_default() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(CO_AUTHORED, PAIR_ONE, false);
+
+    let result = repo.verify(&different_authors_config(), merge);
+
+    assert!(
+        matches!(result, PolicyResult::NotEnoughAuthors(_)),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn co_authors_are_counted_when_configured() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(CO_AUTHORED, PAIR_ONE, false);
+
+    let result = repo.verify(
+        &VerifyGitCommitsConfig {
+            count_co_authored_by: true,
+            ..different_authors_config()
+        },
+        merge,
+    );
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn reviewers_are_only_counted_when_configured() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(REVIEWED, PAIR_ONE, false);
+
+    let co_authors_only = repo.verify(
+        &VerifyGitCommitsConfig {
+            count_co_authored_by: true,
+            ..different_authors_config()
+        },
+        merge,
+    );
+    let with_reviewers = repo.verify(
+        &VerifyGitCommitsConfig {
+            count_reviewed_by: true,
+            ..different_authors_config()
+        },
+        merge,
+    );
+
+    assert!(
+        matches!(co_authors_only, PolicyResult::NotEnoughAuthors(_)),
+        "Unexpected result: {:?}",
+        co_authors_only
+    );
+    assert!(with_reviewers.is_ok(), "Error: {:?}", with_reviewers);
+}
+
+#[test]
+fn unsigned_co_authors_are_not_counted_in_strict_mode() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(CO_AUTHORED, PAIR_ONE, false);
+
+    let result = repo.verify(
+        &VerifyGitCommitsConfig {
+            count_co_authored_by: true,
+            verify_trailer_authors: true,
+            ..different_authors_config()
+        },
+        merge,
+    );
+
+    assert!(
+        matches!(result, PolicyResult::NotEnoughAuthors(_)),
+        "Unexpected result: {:?}",
+        result
+    );
+}
+
+#[test]
+fn co_authors_who_signed_a_commit_are_counted_in_strict_mode() {
+    let repo = test_repo();
+    let merge = repo.merged_branch(CO_AUTHORED, PAIR_TWO, true);
+
+    let result = repo.verify(
+        &VerifyGitCommitsConfig {
+            count_co_authored_by: true,
+            verify_trailer_authors: true,
+            ..different_authors_config()
+        },
+        merge,
+    );
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
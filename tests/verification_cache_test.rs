@@ -0,0 +1,125 @@
+use capn::config::VerifyGitCommitsConfig;
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies;
+use capn::reference_update::ReferenceUpdate;
+
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::TempTestRepo;
+
+impl TempTestRepo {
+    fn cache_path(&self) -> PathBuf {
+        self.path.join("capn-verification-cache.json")
+    }
+}
+
+fn cached_config() -> VerifyGitCommitsConfig {
+    VerifyGitCommitsConfig {
+        author_domain: "jemstep.com".into(),
+        committer_domain: "jemstep.com".into(),
+        keyserver: "hkp://p80.pool.sks-keyservers.net".to_string(),
+        team_fingerprints_file: "TEAM_FINGERPRINTS".to_string(),
+        override_tag_pattern: Some("capn-override-*".to_string()),
+        override_tags_required: 1.into(),
+        verification_cache: true,
+        ..Default::default()
+    }
+}
+
+// The test repo's HEAD is master, so commits need to be verified
+// against it rather than being pushed to it, otherwise they are
+// already on the mainline and aren't checked.
+fn verify(repo: &TempTestRepo, config: &VerifyGitCommitsConfig, old: &str, new: &str) -> bool {
+    policies::verify_git_commits::<LiveGit, MockGpg>(
+        &LiveGit::default(&repo.path).unwrap(),
+        MockGpg,
+        config,
+        &ReferenceUpdate::from_git_hook_format(old, new, "refs/heads/master").unwrap(),
+    )
+    .unwrap()
+    .is_ok()
+}
+
+fn add_cached_pass(repo: &TempTestRepo, commit_id: &str) {
+    let mut cache: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(repo.cache_path()).unwrap()).unwrap();
+    cache["passed"][commit_id] = serde_json::json!(["email addresses", "commit signatures"]);
+    fs::write(repo.cache_path(), cache.to_string()).unwrap();
+}
+
+#[test]
+fn passing_commits_are_recorded_in_the_cache() {
+    let repo = TempTestRepo::with_keys(&[]);
+
+    assert!(verify(
+        &repo,
+        &cached_config(),
+        "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+        "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+    ));
+
+    let cache = fs::read_to_string(repo.cache_path()).unwrap();
+    assert!(cache.contains("3eb315d10e2ad89555d7bfc78a1db1ce07bce434"));
+}
+
+#[test]
+fn cache_is_not_written_when_disabled() {
+    let repo = TempTestRepo::with_keys(&[]);
+
+    assert!(verify(
+        &repo,
+        &VerifyGitCommitsConfig {
+            verification_cache: false,
+            ..cached_config()
+        },
+        "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+        "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+    ));
+
+    assert!(!repo.cache_path().exists());
+}
+
+#[test]
+fn cached_passes_are_not_checked_again() {
+    let repo = TempTestRepo::with_keys(&[]);
+    let config = cached_config();
+    verify(
+        &repo,
+        &config,
+        "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+        "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+    );
+    add_cached_pass(&repo, "d2e3bfdc923986d04e7a6368b5fdd78b1ddf84f1");
+
+    assert!(verify(
+        &repo,
+        &config,
+        "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+        "d2e3bfdc923986d04e7a6368b5fdd78b1ddf84f1",
+    ));
+}
+
+#[test]
+fn changing_config_invalidates_the_cache() {
+    let repo = TempTestRepo::with_keys(&[]);
+    verify(
+        &repo,
+        &cached_config(),
+        "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+        "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+    );
+    add_cached_pass(&repo, "d2e3bfdc923986d04e7a6368b5fdd78b1ddf84f1");
+
+    assert!(!verify(
+        &repo,
+        &VerifyGitCommitsConfig {
+            override_tags_required: 2.into(),
+            ..cached_config()
+        },
+        "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+        "d2e3bfdc923986d04e7a6368b5fdd78b1ddf84f1",
+    ));
+}
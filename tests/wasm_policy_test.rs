@@ -0,0 +1,331 @@
+use capn::config::Config;
+use capn::git::LiveGit;
+use capn::gpg::test::MockGpg;
+use capn::policies::external::ExternalPolicyInput;
+use capn::policies::policy_result::PolicyResult;
+
+use git2::{Repository, Signature};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::*;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const SIGNED_COMMIT: &str = "26b9047d071ad631d4a7c25df8bfd5361a679938";
+
+/// A copy of the test repo, and a place to put the modules used by
+/// the tests.
+struct TempTestRepo {
+    path: PathBuf,
+    modules: PathBuf,
+}
+
+impl TempTestRepo {
+    fn new() -> TempTestRepo {
+        let project_root = env!("CARGO_MANIFEST_DIR");
+        let root = std::env::temp_dir().join(format!("capn_wasm_test_{}", Uuid::new_v4()));
+        let path = root.join("test-repo.git");
+        let modules = root.join("modules");
+        fs::create_dir_all(&modules).unwrap();
+        let status = Command::new("git")
+            .arg("clone")
+            .arg("--mirror")
+            .arg("--quiet")
+            .arg(format!("{}/tests/test-repo.git", project_root))
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success(), "Failed to copy test repo");
+        Repository::open(&path)
+            .unwrap()
+            .config()
+            .unwrap()
+            .set_str("capn.wasmModuleDir", modules.to_str().unwrap())
+            .unwrap();
+        TempTestRepo { path, modules }
+    }
+
+    fn server_module(&self, name: &str, wat: &str) -> String {
+        fs::write(
+            self.modules.join(format!("{}.wasm", name)),
+            wat::parse_str(wat).unwrap(),
+        )
+        .unwrap();
+        server_module_config(name, &format!("{}.wasm", name))
+    }
+
+    fn commit_module_to_head(&self, path: &str, wat: &str) {
+        let repo = Repository::open(&self.path).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let blob = repo.blob(&wat::parse_str(wat).unwrap()).unwrap();
+        let mut tree_builder = repo.treebuilder(Some(&head.tree().unwrap())).unwrap();
+        tree_builder.insert(path, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree_builder.write().unwrap()).unwrap();
+        let signature = Signature::now("Test User", "blackhole@jemstep.com").unwrap();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Add wasm policy",
+            &tree,
+            &[&head],
+        )
+        .unwrap();
+    }
+
+    fn pre_receive(&self, config: &str) -> Result<PolicyResult, Box<dyn Error>> {
+        capn::pre_receive::<LiveGit, MockGpg>(
+            &LiveGit::default(&self.path).unwrap(),
+            MockGpg,
+            &Config::from_toml_string(config).unwrap(),
+            "eb5e0185546b0bb1a13feec6b9ee8b39985fea42",
+            "3eb315d10e2ad89555d7bfc78a1db1ce07bce434",
+            "refs/heads/valid-merge",
+        )
+    }
+}
+
+impl Drop for TempTestRepo {
+    fn drop(&mut self) {
+        fs::remove_dir_all(Path::new(&self.path).parent().unwrap()).unwrap();
+    }
+}
+
+fn server_module_config(name: &str, module: &str) -> String {
+    format!(
+        "[[wasm_policy]]\nname = \"{}\"\nmodule = \"{}\"\nlocation = \"server\"\nfuel = 1000000\n",
+        name, module
+    )
+}
+
+fn violations(result: PolicyResult) -> Vec<String> {
+    match result {
+        PolicyResult::ExternalPolicyViolations(_, violations) => violations,
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+const REPORTS_VIOLATION: &str = r#"
+(module
+  (import "capn" "report_violation" (func $report_violation (param i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "No tabs allowed")
+  (func (export "check")
+    (call $report_violation (i32.const 0) (i32.const 15))))
+"#;
+
+// Reports whatever the host call in $request returns, so that the
+// tests can see what the module was given.
+fn echo_module(imports: &str, request: &str, data: &str) -> String {
+    format!(
+        r#"
+(module
+  {}
+  (import "capn" "read_response" (func $read_response (param i32)))
+  (import "capn" "report_violation" (func $report_violation (param i32 i32)))
+  (memory (export "memory") 1)
+  {}
+  (func (export "check") (local $len i32)
+    (local.set $len {})
+    (if (i32.lt_s (local.get $len) (i32.const 0))
+      (then (local.set $len (i32.const 0)))
+      (else (call $read_response (i32.const 1024))))
+    (call $report_violation (i32.const 1024) (local.get $len))))
+"#,
+        imports, data, request
+    )
+}
+
+#[test]
+fn passing_wasm_policy_accepts_update() {
+    let repo = TempTestRepo::new();
+    let config = repo.server_module(
+        "pass",
+        r#"(module (memory (export "memory") 1) (func (export "check")))"#,
+    );
+
+    let result = repo.pre_receive(&config).unwrap();
+
+    assert!(result.is_ok(), "Error: {:?}", result);
+}
+
+#[test]
+fn violations_from_wasm_policy_reject_update() {
+    let repo = TempTestRepo::new();
+    let config = repo.server_module("no-tabs", REPORTS_VIOLATION);
+
+    let result = repo.pre_receive(&config).unwrap();
+
+    assert_eq!(violations(result), vec!["No tabs allowed"]);
+}
+
+#[test]
+fn wasm_policy_can_be_loaded_from_the_repo() {
+    let repo = TempTestRepo::new();
+    repo.commit_module_to_head("no-tabs.wasm", REPORTS_VIOLATION);
+
+    let result = repo
+        .pre_receive("[[wasm_policy]]\nname = \"no-tabs\"\nmodule = \"no-tabs.wasm\"\n")
+        .unwrap();
+
+    assert_eq!(violations(result), vec!["No tabs allowed"]);
+}
+
+#[test]
+fn wasm_policy_receives_ref_update_and_new_commits() {
+    let repo = TempTestRepo::new();
+    let config = repo.server_module(
+        "input",
+        &echo_module(
+            r#"(import "capn" "input" (func $input (result i32)))"#,
+            "(call $input)",
+            "",
+        ),
+    );
+
+    let result = repo.pre_receive(&config).unwrap();
+
+    let input: ExternalPolicyInput = serde_json::from_str(&violations(result)[0]).unwrap();
+    assert_eq!(input.ref_update.ref_name, "refs/heads/valid-merge");
+    assert_eq!(input.commits.len(), 2);
+}
+
+#[test]
+fn wasm_policy_can_read_files_at_a_commit() {
+    let repo = TempTestRepo::new();
+    let config = repo.server_module(
+        "file",
+        &echo_module(
+            r#"(import "capn" "file_contents" (func $file_contents (param i32 i32 i32 i32) (result i32)))"#,
+            "(call $file_contents (i32.const 0) (i32.const 40) (i32.const 64) (i32.const 17))",
+            &format!(
+                r#"(data (i32.const 0) "{}") (data (i32.const 64) "TEAM_FINGERPRINTS")"#,
+                SIGNED_COMMIT
+            ),
+        ),
+    );
+
+    let result = repo.pre_receive(&config).unwrap();
+
+    assert!(violations(result)[0].contains("Test User,blackhole@jemstep.com"));
+}
+
+#[test]
+fn wasm_policy_can_read_diff_of_a_commit() {
+    let repo = TempTestRepo::new();
+    let config = repo.server_module(
+        "diff",
+        &echo_module(
+            r#"(import "capn" "commit_diff" (func $commit_diff (param i32 i32) (result i32)))"#,
+            "(call $commit_diff (i32.const 0) (i32.const 40))",
+            &format!(r#"(data (i32.const 0) "{}")"#, SIGNED_COMMIT),
+        ),
+    );
+
+    let result = repo.pre_receive(&config).unwrap();
+
+    assert!(violations(result)[0].contains("diff --git a/2.txt b/2.txt"));
+}
+
+#[test]
+fn wasm_policy_that_runs_out_of_fuel_is_an_error() {
+    let repo = TempTestRepo::new();
+    let config = repo.server_module(
+        "forever",
+        r#"(module (memory (export "memory") 1) (func (export "check") (loop (br 0))))"#,
+    );
+
+    let error = repo.pre_receive(&config).unwrap_err();
+
+    assert!(
+        error
+            .to_string()
+            .contains("Wasm policy forever failed to run"),
+        "Error: {}",
+        error
+    );
+}
+
+#[test]
+fn wasm_policy_with_plenty_of_fuel_is_stopped_at_its_timeout() {
+    let repo = TempTestRepo::new();
+    let config = repo
+        .server_module(
+            "forever",
+            r#"(module (memory (export "memory") 1) (func (export "check") (loop (br 0))))"#,
+        )
+        .replace("fuel = 1000000", "fuel = 1000000000000\ntimeout_ms = 200");
+
+    let start = Instant::now();
+    let error = repo.pre_receive(&config).unwrap_err();
+
+    assert!(
+        error.to_string().contains("did not finish within 200 ms"),
+        "Error: {}",
+        error
+    );
+    assert!(
+        start.elapsed() < Duration::from_secs(2),
+        "Took {} ms",
+        start.elapsed().as_millis()
+    );
+}
+
+#[test]
+fn wasm_policy_cannot_import_system_interfaces() {
+    let repo = TempTestRepo::new();
+    let config = repo.server_module(
+        "wasi",
+        r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "check")))
+"#,
+    );
+
+    let error = repo.pre_receive(&config).unwrap_err();
+
+    assert!(error.to_string().contains("fd_write"), "Error: {}", error);
+}
+
+#[test]
+fn server_wasm_module_outside_the_module_dir_is_an_error() {
+    let repo = TempTestRepo::new();
+    repo.server_module("no-tabs", REPORTS_VIOLATION);
+    let outside = repo.path.parent().unwrap().join("outside.wasm");
+    fs::write(&outside, wat::parse_str(REPORTS_VIOLATION).unwrap()).unwrap();
+
+    for module in &["../outside.wasm", outside.to_str().unwrap()] {
+        let error = repo
+            .pre_receive(&server_module_config("no-tabs", module))
+            .unwrap_err();
+
+        assert!(
+            error.to_string().contains("must be a path inside"),
+            "Error: {}",
+            error
+        );
+    }
+}
+
+#[test]
+fn server_wasm_module_without_a_module_dir_is_an_error() {
+    let repo = TempTestRepo::new();
+    let config = repo.server_module("no-tabs", REPORTS_VIOLATION);
+    Repository::open(&repo.path)
+        .unwrap()
+        .config()
+        .unwrap()
+        .remove("capn.wasmModuleDir")
+        .unwrap();
+
+    let error = repo.pre_receive(&config).unwrap_err();
+
+    assert!(
+        error.to_string().contains("capn.wasmModuleDir is not set"),
+        "Error: {}",
+        error
+    );
+}